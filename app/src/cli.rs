@@ -0,0 +1,368 @@
+//! Management commands for operators: schema migrations, bootstrapping users,
+//! organisations and API keys, signing key rotation and config checks.
+
+use std::io::{BufRead, IsTerminal};
+
+use admin_core::api_key_service::{ApiKeyService, NewApiKey};
+use admin_core::config::AdminConfig;
+use admin_core::migrations::{self, Migrate, State};
+use admin_core::models::Role;
+use admin_core::organisation_service::OrganisationService;
+use admin_core::{DbPool, RefreshTokenService, UserService};
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(name = "apisentinel-app", about = "APISentinel server and management commands")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the modules enabled at build time (the default)
+    Serve,
+    /// Inspect, apply or revert database migrations
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+    /// Create users and reset passwords
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Create organisations
+    #[command(subcommand)]
+    Org(OrgCommand),
+    /// Issue API keys
+    #[command(subcommand)]
+    Apikey(ApiKeyCommand),
+    /// Manage auth signing keys in `AUTH_KEYRING_DIR`
+    #[command(subcommand)]
+    Keys(KeysCommand),
+    /// Check configuration before deploying
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand)]
+pub enum MigrateCommand {
+    /// Apply pending migrations
+    Up {
+        /// Stop after this version
+        #[arg(long)]
+        to: Option<i64>,
+    },
+    /// Revert migrations; only the last one unless `--to` is given
+    Down {
+        /// Revert everything newer than this version
+        #[arg(long)]
+        to: Option<i64>,
+    },
+    /// List migrations and whether they have been applied
+    Status,
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Create a user; the password is prompted for, or read from stdin
+    Create {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        name: String,
+        #[arg(long, default_value = "USER", value_parser = parse_role)]
+        role: Role,
+        /// Organisation id or slug
+        #[arg(long)]
+        org: Option<String>,
+    },
+    /// Set a new password and revoke the user's refresh tokens
+    ResetPassword {
+        #[arg(long)]
+        email: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum OrgCommand {
+    /// Create an organisation
+    Create {
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        slug: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ApiKeyCommand {
+    /// Issue a key; the key is printed once and can't be recovered
+    Issue {
+        /// Organisation id or slug
+        #[arg(long)]
+        org: String,
+        #[arg(long)]
+        name: String,
+        #[arg(long, default_value = "USER", value_parser = parse_role)]
+        role: Role,
+        /// Repeat for several scopes
+        #[arg(long = "scope")]
+        scopes: Vec<String>,
+        #[arg(long)]
+        expires_in_days: Option<i64>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum KeysCommand {
+    /// Add a new signing key (generated unless `--pem` is given)
+    Rotate {
+        /// PEM private key to sign with from now on
+        #[arg(long)]
+        pem: Option<std::path::PathBuf>,
+    },
+    /// List signing keys
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration from the environment and `GATEWAY_CONFIG`
+    Check,
+}
+
+fn parse_role(s: &str) -> Result<Role, String> {
+    Role::parse(s)
+        .ok_or_else(|| format!("unknown role '{s}' (expected SUPER_ADMIN, ADMIN, SUPERVISOR or USER)"))
+}
+
+/// Run a management command (anything but `serve`)
+pub async fn run(command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::Migrate(command) => migrate(command).await,
+        Command::User(command) => user(command).await,
+        Command::Org(command) => org(command).await,
+        Command::Apikey(command) => apikey(command).await,
+        Command::Keys(command) => keys(command),
+        Command::Config(ConfigCommand::Check) => config_check().await,
+    }
+}
+
+async fn connect() -> anyhow::Result<DbPool> {
+    let config = AdminConfig::default();
+    admin_core::db::create_pool(&config.database_url)
+        .await
+        .with_context(|| format!("connect to {}", config.database_url))
+}
+
+async fn migrate(command: MigrateCommand) -> anyhow::Result<()> {
+    let pool = connect().await?;
+    let command = match command {
+        MigrateCommand::Up { to } => Migrate::Up(to),
+        MigrateCommand::Down { to } => Migrate::Down(to),
+        MigrateCommand::Status => Migrate::Status,
+    };
+    migrations::command(&pool, command).await
+}
+
+async fn user(command: UserCommand) -> anyhow::Result<()> {
+    let pool = connect().await?;
+    let users = UserService::new(pool.clone());
+
+    match command {
+        UserCommand::Create {
+            email,
+            name,
+            role,
+            org,
+        } => {
+            let organisation_id = match org {
+                Some(org) => Some(find_org(&pool, &org).await?),
+                None => None,
+            };
+            if users.find_by_email(&email).await?.is_some() {
+                anyhow::bail!("a user with email {email} already exists");
+            }
+            let password = read_password()?;
+            let hash = auth_core::token::hash_password(&password)?;
+            let user = users
+                .create(&email, &name, &hash, organisation_id, role)
+                .await
+                .context("create user")?;
+            println!("created user {} ({}, {})", user.id, user.email, user.role);
+        }
+        UserCommand::ResetPassword { email } => {
+            let user = users
+                .find_by_email(&email)
+                .await?
+                .with_context(|| format!("no user with email {email}"))?;
+            let password = read_password()?;
+            let hash = auth_core::token::hash_password(&password)?;
+            users.update_password(user.id, &hash).await?;
+            let revoked = RefreshTokenService::new(pool).revoke_user(user.id).await?;
+            println!("password reset for {email}, {revoked} refresh token(s) revoked");
+        }
+    }
+    Ok(())
+}
+
+async fn org(command: OrgCommand) -> anyhow::Result<()> {
+    let pool = connect().await?;
+    match command {
+        OrgCommand::Create { name, slug } => {
+            let org = OrganisationService::new(pool)
+                .create(&name, &slug)
+                .await
+                .context("create organisation")?;
+            println!("created organisation {} ({})", org.id, org.slug);
+        }
+    }
+    Ok(())
+}
+
+async fn apikey(command: ApiKeyCommand) -> anyhow::Result<()> {
+    let pool = connect().await?;
+    match command {
+        ApiKeyCommand::Issue {
+            org,
+            name,
+            role,
+            scopes,
+            expires_in_days,
+        } => {
+            let organisation_id = find_org(&pool, &org).await?;
+            let (api_key, key) = ApiKeyService::new(pool)
+                .create(NewApiKey {
+                    organisation_id,
+                    name: &name,
+                    role,
+                    scopes: &scopes,
+                    expires_at: expires_in_days.map(|d| chrono::Utc::now() + chrono::Duration::days(d)),
+                    created_by: None,
+                })
+                .await
+                .context("issue api key")?;
+            eprintln!("issued api key {} ({}); store it now, it won't be shown again", api_key.id, api_key.prefix);
+            println!("{key}");
+        }
+    }
+    Ok(())
+}
+
+fn keys(command: KeysCommand) -> anyhow::Result<()> {
+    let config = auth_core::AuthConfig::default();
+    if config.keyring_dir.is_none() {
+        anyhow::bail!("AUTH_KEYRING_DIR is not set; keys only live in the auth process");
+    }
+    let keyring = auth_core::Keyring::load(&config)?;
+
+    if let KeysCommand::Rotate { pem } = &command {
+        let pem = pem
+            .as_ref()
+            .map(|path| std::fs::read(path).with_context(|| format!("read {}", path.display())))
+            .transpose()?;
+        keyring.rotate(pem.as_deref())?;
+        println!("running auth instances pick the new key up when restarted; use POST /auth/keys/rotate to rotate live");
+    }
+    for key in keyring.list() {
+        println!(
+            "{}  {:<7}  {:<7}  {}{}",
+            key.kid,
+            key.algorithm.as_deref().unwrap_or("-"),
+            key.status,
+            key.created_at.to_rfc3339(),
+            if key.signing { "  (signing)" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+async fn config_check() -> anyhow::Result<()> {
+    let mut errors: Vec<String> = Vec::new();
+    let mut warnings: Vec<String> = Vec::new();
+
+    match gateway_core::GatewayConfig::load() {
+        Ok(gateway) => {
+            println!("gateway: {} route(s), listening on {}", gateway.routes.len(), gateway.listen_addr);
+            if gateway.identity_secret == common::identity::DEFAULT_IDENTITY_SECRET {
+                warnings.push("GATEWAY_IDENTITY_SECRET is the development default".to_string());
+            }
+        }
+        Err(err) => errors.push(format!("gateway: {err:#}")),
+    }
+
+    let auth = auth_core::AuthConfig::default();
+    if auth.refresh_token_pepper == auth_core::config::DEFAULT_REFRESH_TOKEN_PEPPER {
+        warnings.push("AUTH_REFRESH_TOKEN_PEPPER is the development default".to_string());
+    }
+    if auth.keyring_dir.is_none() && auth.signing_key_path.is_none() {
+        warnings.push("neither AUTH_KEYRING_DIR nor AUTH_SIGNING_KEY_PATH is set; tokens won't survive a restart".to_string());
+    }
+    if let Some(path) = &auth.signing_key_path
+        && let Err(err) = std::fs::metadata(path)
+    {
+        errors.push(format!("auth: signing key {path}: {err}"));
+    }
+    if auth.default_admin_email.is_some() {
+        warnings.push("AUTH_DEFAULT_ADMIN_* is set; create a real admin with `user create --role SUPER_ADMIN` instead".to_string());
+    }
+
+    let admin = AdminConfig::default();
+    if let Err(err) = admin.validate() {
+        errors.push(format!("admin: {err:#}"));
+    }
+    if admin.service_secret == common::service_auth::DEFAULT_SERVICE_SECRET {
+        warnings.push("ADMIN_SERVICE_SECRET is the development default".to_string());
+    }
+    match connect().await {
+        Ok(pool) => match migrations::status(&pool).await {
+            Ok(statuses) => {
+                let pending = statuses.iter().filter(|s| s.state == State::Pending).count();
+                let modified = statuses.iter().filter(|s| s.state == State::Modified).count();
+                println!("database: reachable, {pending} pending migration(s)");
+                if modified > 0 {
+                    errors.push(format!("database: {modified} applied migration(s) changed since they were applied"));
+                }
+            }
+            Err(err) => errors.push(format!("database: {err:#}")),
+        },
+        Err(err) => errors.push(format!("database: {err:#}")),
+    }
+
+    for warning in &warnings {
+        println!("warning: {warning}");
+    }
+    for error in &errors {
+        println!("error: {error}");
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("{} problem(s) found", errors.len());
+    }
+    println!("config ok");
+    Ok(())
+}
+
+async fn find_org(pool: &DbPool, id_or_slug: &str) -> anyhow::Result<uuid::Uuid> {
+    OrganisationService::new(pool.clone())
+        .find(id_or_slug)
+        .await?
+        .map(|org| org.id)
+        .with_context(|| format!("no organisation '{id_or_slug}'"))
+}
+
+/// Prompt for a password without echoing it, or read one line from stdin
+/// when it is not a terminal. Never taken as an argument, where it would end
+/// up in shell history and `ps`.
+fn read_password() -> anyhow::Result<String> {
+    let password = if std::io::stdin().is_terminal() {
+        rpassword::prompt_password("password: ")?
+    } else {
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    if password.is_empty() {
+        anyhow::bail!("password must not be empty");
+    }
+    Ok(password)
+}
@@ -1,83 +1,127 @@
+mod cli;
 mod modules;
 
+use clap::Parser;
+
 #[tokio::main]
 async fn main() {
+    let cli = cli::Cli::parse();
+    match cli.command {
+        None | Some(cli::Command::Serve) => {
+            if let Err(err) = serve().await {
+                eprintln!("error: {err:#}");
+                std::process::exit(1);
+            }
+        }
+        Some(command) => {
+            if let Err(err) = cli::run(command).await {
+                eprintln!("error: {err:#}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(not(any(feature = "gateway", feature = "auth", feature = "admin")))]
+async fn serve() -> anyhow::Result<()> {
+    anyhow::bail!("No modules enabled. Use --features to enable: gateway, auth, admin")
+}
+
+/// Run every enabled module side by side. Modules the gateway embeds are
+/// served through it; the others get their own listener. All of them share
+/// one database pool and one shutdown: when any module stops, the rest are
+/// shut down too.
+#[cfg(any(feature = "gateway", feature = "auth", feature = "admin"))]
+async fn serve() -> anyhow::Result<()> {
+    use common::shutdown::Shutdown;
+    use tokio::task::JoinSet;
+
     println!("apisentinel app");
     println!("  mode: modular monolith");
 
+    let shutdown = Shutdown::new();
+    shutdown.trigger_on_signals();
+    let mut running: JoinSet<(&'static str, anyhow::Result<()>)> = JoinSet::new();
+
     // Initialize shared database pool once for all modules
     #[cfg(any(feature = "admin", feature = "auth"))]
     let pool = {
+        use anyhow::Context;
+
         let admin_config = admin_core::config::AdminConfig::default();
         println!("  database: connecting to {}", admin_config.database_url);
 
-        let pool = match admin_core::db::create_pool(&admin_config.database_url).await {
-            Ok(pool) => pool,
-            Err(err) => {
-                eprintln!("failed to connect to database: {err}");
-                return;
-            }
-        };
-
-        if let Err(err) = admin_core::db::migrate(&pool).await {
-            eprintln!("failed to run migrations: {err}");
-            return;
+        let pool = admin_core::db::create_pool(&admin_config.database_url)
+            .await
+            .context("connect to database")?;
+        if admin_config.migrate_on_start {
+            admin_core::db::migrate(&pool).await.context("run migrations")?;
         }
-        println!("  database: connected and migrated");
+        println!("  database: connected");
         pool
     };
 
-    // Set JWT secret for gateway to validate tokens
     #[cfg(feature = "gateway")]
-    {
-        let jwt_secret = std::env::var("JWT_SECRET").unwrap_or_else(|_| "secret".to_string());
-        gateway_core::set_jwt_secret(jwt_secret);
+    let gateway_config = Some(gateway_core::GatewayConfig::load()?);
+    #[cfg(not(feature = "gateway"))]
+    let gateway_config: Option<gateway_core::GatewayConfig> = None;
+
+    // A module the gateway serves in-process needs no listener of its own
+    #[cfg(any(feature = "admin", feature = "auth"))]
+    let standalone = |route: &str| {
+        gateway_config
+            .as_ref()
+            .is_none_or(|config| config.is_proxy(route))
+    };
+
+    #[cfg(feature = "admin")]
+    if standalone("/admin") {
+        let (pool, shutdown) = (pool.clone(), shutdown.clone());
+        running.spawn(async move { ("admin", modules::admin::start(pool, shutdown).await) });
     }
 
-    let handles: Vec<tokio::task::JoinHandle<()>> = Vec::new();
+    #[cfg(feature = "auth")]
+    if standalone("/auth") {
+        let (pool, shutdown) = (pool.clone(), shutdown.clone());
+        running.spawn(async move { ("auth", modules::auth::start(pool, shutdown).await) });
+    }
 
-    // Gateway is the main entry point - it handles routing and can embed other modules
     #[cfg(feature = "gateway")]
-    {
+    if let Some(config) = gateway_config {
         #[cfg(any(feature = "admin", feature = "auth"))]
         let gateway_pool = Some(pool.clone());
         #[cfg(not(any(feature = "admin", feature = "auth")))]
         let gateway_pool: Option<admin_core::DbPool> = None;
 
-        let handle = tokio::spawn(async move {
-            modules::gateway::start(gateway_pool).await;
+        let shutdown = shutdown.clone();
+        running.spawn(async move {
+            ("gateway", modules::gateway::start(config, gateway_pool, shutdown).await)
         });
-
-        // Gateway is the only service in monolith mode, wait for it
-        let _ = handle.await;
-        return;
     }
 
-    // Auth runs as a separate service only when gateway is not enabled
-    // (otherwise auth is embedded in gateway)
-    #[cfg(all(feature = "auth", not(feature = "gateway")))]
-    {
-        let auth_pool = pool.clone();
-        let handle = tokio::spawn(async move {
-            modules::auth::start(auth_pool).await;
-        });
-        let _ = handle.await;
-        return;
+    // Supervise: the first module to stop takes the others down with it
+    let mut failed = false;
+    while let Some(joined) = running.join_next().await {
+        let (name, result) = joined.unwrap_or_else(|err| ("module", Err(err.into())));
+        match result {
+            Err(err) => {
+                eprintln!("{name} module failed: {err:#}");
+                failed = true;
+            }
+            Ok(()) if !shutdown.is_triggered() => {
+                eprintln!("{name} module stopped unexpectedly");
+                failed = true;
+            }
+            Ok(()) => println!("{name} module stopped"),
+        }
+        shutdown.trigger();
     }
 
-    // Admin runs standalone only when gateway is not enabled
-    // (otherwise admin is embedded in gateway or proxied by gateway)
-    #[cfg(all(feature = "admin", not(feature = "gateway")))]
-    {
-        let admin_pool = pool.clone();
-        let handle = tokio::spawn(async move {
-            modules::admin::start(admin_pool).await;
-        });
-        let _ = handle.await;
-        return;
-    }
+    #[cfg(any(feature = "admin", feature = "auth"))]
+    admin_core::db::close(&pool).await;
 
-    if handles.is_empty() {
-        eprintln!("No modules enabled. Use --features to enable: gateway, auth, admin");
+    if failed {
+        anyhow::bail!("shut down after a module failure");
     }
+    Ok(())
 }
@@ -1,12 +1,15 @@
 use admin_core::{config::AdminConfig, DbPool};
+use anyhow::Context;
+use common::shutdown::Shutdown;
 
 /// Start admin module in standalone mode
 /// Uses the shared database pool from main
-pub async fn start(pool: DbPool) {
+pub async fn start(pool: DbPool, shutdown: Shutdown) -> anyhow::Result<()> {
     let config = AdminConfig::default();
+    config.validate()?;
     println!("admin module starting on {}", config.bind_addr);
 
-    if let Err(err) = admin_core::server::run(&config.bind_addr, pool).await {
-        eprintln!("admin module error: {err}");
-    }
+    admin_core::server::run(&config, pool, shutdown)
+        .await
+        .context("admin server")
 }
@@ -1,11 +1,13 @@
 use std::sync::Arc;
 
 use admin_core::{DbPool, InMemoryRefreshTokenService, InMemoryUserService};
-use auth_core::AuthConfig;
+use anyhow::Context;
+use auth_core::{AuthConfig, Keyring};
+use common::shutdown::Shutdown;
 
 /// Start auth module in embedded (monolith) mode
 /// Uses in-memory implementations - no HTTP calls to admin service
-pub async fn start(pool: DbPool) {
+pub async fn start(pool: DbPool, shutdown: Shutdown) -> anyhow::Result<()> {
     let config = AuthConfig::default();
 
     println!("auth module starting on {} (embedded mode)", config.listen_addr);
@@ -19,17 +21,23 @@ pub async fn start(pool: DbPool) {
 
     // Create in-memory service implementations (direct database access)
     let user_service = Arc::new(InMemoryUserService::new(pool.clone()));
-    let token_service = Arc::new(InMemoryRefreshTokenService::new(pool));
+    let token_service = Arc::new(InMemoryRefreshTokenService::new(pool.clone()));
+
+    let keyring = Arc::new(Keyring::load(&config)?);
+    if let Some(key) = keyring.signing_key() {
+        println!("  signing key: {:?} (kid {})", key.algorithm(), key.kid());
+    }
 
     let config = Arc::new(config);
-    if let Err(err) = auth_core::server::run(
+    auth_core::server::run(
         &config.listen_addr,
         user_service,
         token_service,
         config.clone(),
+        keyring,
+        vec![admin_core::db::readiness(pool)],
+        shutdown,
     )
     .await
-    {
-        eprintln!("auth module error: {err}");
-    }
+    .context("auth server")
 }
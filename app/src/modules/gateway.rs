@@ -1,9 +1,17 @@
 use std::collections::HashMap;
+#[cfg(any(feature = "admin", feature = "auth"))]
 use std::sync::Arc;
 
+use common::shutdown::Shutdown;
+use gateway_core::GatewayConfig;
+
 /// Start gateway with embedded modules based on feature flags and config
-pub async fn start(pool: Option<admin_core::DbPool>) {
-    let config = gateway_core::GatewayConfig::default();
+pub async fn start(
+    config: GatewayConfig,
+    pool: Option<admin_core::DbPool>,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
+    #[cfg_attr(not(any(feature = "admin", feature = "auth")), allow(unused_mut))]
     let mut routers: HashMap<String, axum::Router> = HashMap::new();
 
     // If admin feature is enabled and route is configured as embedded, add admin router
@@ -11,38 +19,52 @@ pub async fn start(pool: Option<admin_core::DbPool>) {
     {
         if let Some(ref pool) = pool {
             if !config.is_proxy("/admin") {
-                let router = admin_core::server::build_router(pool.clone());
+                // Admin verifies what this gateway signs, so the two can't disagree
+                let admin_config = admin_core::config::AdminConfig {
+                    identity_secret: config.identity_secret.clone(),
+                    ..admin_core::config::AdminConfig::default()
+                };
+                admin_config.validate()?;
+                let router = admin_core::server::build_router(pool.clone(), &admin_config);
                 routers.insert("/admin".to_string(), router);
             }
+
+            // Validate API keys directly against the database
+            gateway_core::set_api_key_service(Arc::new(admin_core::InMemoryApiKeyService::new(
+                pool.clone(),
+            )));
         }
     }
 
     // If auth feature is enabled and route is configured as embedded, add auth router
     #[cfg(feature = "auth")]
+    if let Some(ref pool) = pool
+        && !config.is_proxy("/auth")
     {
-        if let Some(ref pool) = pool {
-            if !config.is_proxy("/auth") {
-                let auth_config = auth_core::AuthConfig::default();
-
-                // Create in-memory service implementations (direct database access)
-                let user_service = Arc::new(admin_core::InMemoryUserService::new(pool.clone()));
-                let token_service = Arc::new(admin_core::InMemoryRefreshTokenService::new(pool.clone()));
-
-                let router = auth_core::server::build_inner_router(
-                    user_service,
-                    token_service,
-                    Arc::new(auth_config),
-                );
-                routers.insert("/auth".to_string(), router);
-            }
-        }
-    }
+        let auth_config = auth_core::AuthConfig::default();
+        let keyring = Arc::new(auth_core::Keyring::load(&auth_config)?);
+
+        // Verify tokens with the embedded auth's keys, no JWKS fetch needed
+        let jwks_keyring = keyring.clone();
+        gateway_core::set_jwks_provider(Arc::new(move || jwks_keyring.jwks()));
 
-    // Suppress unused variable warning when neither admin nor auth feature is enabled
-    #[cfg(not(any(feature = "admin", feature = "auth")))]
-    let _ = pool;
+        // Create in-memory service implementations (direct database access)
+        let user_service = Arc::new(admin_core::InMemoryUserService::new(pool.clone()));
+        let token_service = Arc::new(admin_core::InMemoryRefreshTokenService::new(pool.clone()));
 
-    if let Err(err) = gateway_core::run_with_config_and_routers(config, routers).await {
-        eprintln!("gateway module error: {err}");
+        let router = auth_core::server::build_inner_router(
+            user_service,
+            token_service,
+            Arc::new(auth_config),
+            keyring,
+        );
+        routers.insert("/auth".to_string(), router);
     }
+
+    // Embedded modules and API key checks need the database
+    if let Some(pool) = pool {
+        gateway_core::set_readiness_checks(vec![admin_core::db::readiness(pool)]);
+    }
+
+    gateway_core::run_with_config_and_routers(config, routers, shutdown).await
 }
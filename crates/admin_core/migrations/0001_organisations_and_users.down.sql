@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS users;
+DROP TABLE IF EXISTS organisations;
+DROP TYPE IF EXISTS user_role;
@@ -0,0 +1,165 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::db::DbPool;
+use crate::models::{ApiKey, Role};
+
+const API_KEY_COLUMNS: &str = "id, organisation_id, name, prefix, role, scopes, expires_at, last_used_at, revoked_at, created_by, created_at, updated_at";
+
+/// Fields for a new API key
+pub struct NewApiKey<'a> {
+    pub organisation_id: Uuid,
+    pub name: &'a str,
+    pub role: Role,
+    pub scopes: &'a [String],
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_by: Option<Uuid>,
+}
+
+/// API key service for issuing, listing, rotating and revoking keys
+#[derive(Clone)]
+pub struct ApiKeyService {
+    pool: DbPool,
+}
+
+impl ApiKeyService {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Issue a key; returns the stored metadata and the plaintext key
+    pub async fn create(&self, new: NewApiKey<'_>) -> anyhow::Result<(ApiKey, String)> {
+        let (key, prefix) = generate_key();
+        let api_key = sqlx::query_as::<_, ApiKey>(&format!(
+            r#"
+            INSERT INTO api_keys (id, organisation_id, name, prefix, key_hash, role, scopes, expires_at, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, (SELECT id FROM users WHERE id = $9))
+            RETURNING {API_KEY_COLUMNS}
+            "#
+        ))
+        .bind(Uuid::new_v4())
+        .bind(new.organisation_id)
+        .bind(new.name)
+        .bind(&prefix)
+        .bind(common::api_key::hash(&key))
+        .bind(new.role)
+        .bind(new.scopes)
+        .bind(new.expires_at)
+        .bind(new.created_by)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok((api_key, key))
+    }
+
+    /// List keys, newest first, optionally restricted to one organisation
+    pub async fn list(
+        &self,
+        organisation_id: Option<Uuid>,
+        limit: i64,
+        offset: i64,
+    ) -> anyhow::Result<(i64, Vec<ApiKey>)> {
+        let total: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM api_keys WHERE $1::uuid IS NULL OR organisation_id = $1",
+        )
+        .bind(organisation_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let keys = sqlx::query_as::<_, ApiKey>(&format!(
+            "SELECT {API_KEY_COLUMNS} FROM api_keys WHERE $1::uuid IS NULL OR organisation_id = $1 ORDER BY created_at DESC LIMIT $2 OFFSET $3"
+        ))
+        .bind(organisation_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok((total, keys))
+    }
+
+    pub async fn find_by_id(&self, id: Uuid) -> anyhow::Result<Option<ApiKey>> {
+        let key = sqlx::query_as::<_, ApiKey>(&format!(
+            "SELECT {API_KEY_COLUMNS} FROM api_keys WHERE id = $1"
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(key)
+    }
+
+    /// Replace the secret of an active key; the old key stops working immediately
+    pub async fn rotate(&self, id: Uuid) -> anyhow::Result<Option<(ApiKey, String)>> {
+        let (key, prefix) = generate_key();
+        let api_key = sqlx::query_as::<_, ApiKey>(&format!(
+            r#"
+            UPDATE api_keys
+            SET prefix = $1, key_hash = $2, last_used_at = NULL, updated_at = NOW()
+            WHERE id = $3 AND revoked_at IS NULL
+            RETURNING {API_KEY_COLUMNS}
+            "#
+        ))
+        .bind(&prefix)
+        .bind(common::api_key::hash(&key))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(api_key.map(|api_key| (api_key, key)))
+    }
+
+    /// Revoke a key; returns `None` if it does not exist
+    pub async fn revoke(&self, id: Uuid) -> anyhow::Result<Option<ApiKey>> {
+        let api_key = sqlx::query_as::<_, ApiKey>(&format!(
+            r#"
+            UPDATE api_keys
+            SET revoked_at = COALESCE(revoked_at, NOW()), updated_at = NOW()
+            WHERE id = $1
+            RETURNING {API_KEY_COLUMNS}
+            "#
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(api_key)
+    }
+
+    /// Find an active key by hash and record its use.
+    /// `last_used_at` is only written once a minute to keep hot keys cheap.
+    pub async fn verify(&self, key_hash: &str) -> anyhow::Result<Option<ApiKey>> {
+        let api_key = sqlx::query_as::<_, ApiKey>(&format!(
+            r#"
+            SELECT {API_KEY_COLUMNS} FROM api_keys
+            WHERE key_hash = $1
+              AND revoked_at IS NULL
+              AND (expires_at IS NULL OR expires_at > NOW())
+            "#
+        ))
+        .bind(key_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if let Some(api_key) = &api_key {
+            sqlx::query(
+                r#"
+                UPDATE api_keys SET last_used_at = NOW()
+                WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')
+                "#,
+            )
+            .bind(api_key.id)
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(api_key)
+    }
+}
+
+/// New random key and its public prefix
+fn generate_key() -> (String, String) {
+    let mut prefix = [0u8; 4];
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut prefix);
+    OsRng.fill_bytes(&mut secret);
+
+    let prefix = format!("{}{}", common::api_key::KEY_PREFIX, common::hex::encode(&prefix));
+    let key = format!("{prefix}_{}", common::hex::encode(&secret));
+    (key, prefix)
+}
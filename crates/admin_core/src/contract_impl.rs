@@ -8,11 +8,13 @@ use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
 use contracts::{
-    ContractError, ContractResult, RefreshTokenInfo, RefreshTokenServiceContract, Role,
-    UserServiceContract, UserWithPassword,
+    ApiKeyInfo, ApiKeyServiceContract, ContractError, ContractResult, RefreshTokenInfo,
+    RefreshTokenServiceContract, Role, UserServiceContract, UserWithPassword,
 };
 
+use crate::api_key_service::ApiKeyService;
 use crate::db::DbPool;
+use crate::user_service::RefreshTokenService;
 
 // ============================================================================
 // In-Memory User Service Implementation
@@ -52,27 +54,13 @@ impl From<DbUserWithPassword> for UserWithPassword {
             email: u.email,
             name: u.name,
             password_hash: u.password_hash,
-            role: match u.role {
-                crate::models::Role::SuperAdmin => Role::SuperAdmin,
-                crate::models::Role::Admin => Role::Admin,
-                crate::models::Role::Supervisor => Role::Supervisor,
-                crate::models::Role::User => Role::User,
-            },
+            role: u.role.into(),
             created_at: u.created_at,
             updated_at: u.updated_at,
         }
     }
 }
 
-fn contract_role_to_db(role: Role) -> crate::models::Role {
-    match role {
-        Role::SuperAdmin => crate::models::Role::SuperAdmin,
-        Role::Admin => crate::models::Role::Admin,
-        Role::Supervisor => crate::models::Role::Supervisor,
-        Role::User => crate::models::Role::User,
-    }
-}
-
 #[async_trait]
 impl UserServiceContract for InMemoryUserService {
     async fn count(&self) -> ContractResult<i64> {
@@ -114,7 +102,7 @@ impl UserServiceContract for InMemoryUserService {
         role: Role,
     ) -> ContractResult<UserWithPassword> {
         let id = Uuid::new_v4();
-        let db_role = contract_role_to_db(role);
+        let db_role = crate::models::Role::from(role);
         let user = sqlx::query_as::<_, DbUserWithPassword>(
             r#"
             INSERT INTO users (id, organisation_id, email, name, password_hash, role)
@@ -174,7 +162,10 @@ struct DbRefreshTokenInfo {
     id: Uuid,
     user_id: Uuid,
     organisation_id: Option<Uuid>,
+    family_id: Uuid,
     expires_at: DateTime<Utc>,
+    rotated_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
 }
 
 impl From<DbRefreshTokenInfo> for RefreshTokenInfo {
@@ -183,7 +174,10 @@ impl From<DbRefreshTokenInfo> for RefreshTokenInfo {
             id: t.id,
             user_id: t.user_id,
             organisation_id: t.organisation_id,
+            family_id: t.family_id,
             expires_at: t.expires_at,
+            rotated_at: t.rotated_at,
+            revoked_at: t.revoked_at,
         }
     }
 }
@@ -200,8 +194,8 @@ impl RefreshTokenServiceContract for InMemoryRefreshTokenService {
         let id = Uuid::new_v4();
         sqlx::query(
             r#"
-            INSERT INTO refresh_tokens (id, user_id, organisation_id, token_hash, expires_at)
-            VALUES ($1, $2, $3, $4, $5)
+            INSERT INTO refresh_tokens (id, user_id, organisation_id, family_id, token_hash, expires_at)
+            VALUES ($1, $2, $3, $1, $4, $5)
             "#,
         )
         .bind(id)
@@ -218,7 +212,7 @@ impl RefreshTokenServiceContract for InMemoryRefreshTokenService {
     async fn find_by_hash(&self, token_hash: &str) -> ContractResult<Option<RefreshTokenInfo>> {
         let result = sqlx::query_as::<_, DbRefreshTokenInfo>(
             r#"
-            SELECT id, user_id, organisation_id, expires_at
+            SELECT id, user_id, organisation_id, family_id, expires_at, rotated_at, revoked_at
             FROM refresh_tokens
             WHERE token_hash = $1
             "#,
@@ -231,26 +225,35 @@ impl RefreshTokenServiceContract for InMemoryRefreshTokenService {
         Ok(result.map(Into::into))
     }
 
-    async fn update(
+    async fn rotate(
         &self,
         token_id: Uuid,
         new_token_hash: &str,
         new_expires_at: DateTime<Utc>,
+    ) -> ContractResult<Option<Uuid>> {
+        RefreshTokenService::new(self.pool.clone())
+            .rotate(token_id, new_token_hash, new_expires_at)
+            .await
+            .map_err(|e| ContractError::Internal(e.to_string()))
+    }
+
+    async fn rehash(
+        &self,
+        token_id: Uuid,
+        old_token_hash: &str,
+        new_token_hash: &str,
     ) -> ContractResult<()> {
-        sqlx::query(
-            r#"
-            UPDATE refresh_tokens
-            SET token_hash = $1, expires_at = $2, updated_at = NOW()
-            WHERE id = $3
-            "#,
-        )
-        .bind(new_token_hash)
-        .bind(new_expires_at)
-        .bind(token_id)
-        .execute(&self.pool)
-        .await
-        .map_err(|e| ContractError::Internal(e.to_string()))?;
-        Ok(())
+        RefreshTokenService::new(self.pool.clone())
+            .rehash(token_id, old_token_hash, new_token_hash)
+            .await
+            .map_err(|e| ContractError::Internal(e.to_string()))
+    }
+
+    async fn revoke_family(&self, family_id: Uuid) -> ContractResult<u64> {
+        RefreshTokenService::new(self.pool.clone())
+            .revoke_family(family_id)
+            .await
+            .map_err(|e| ContractError::Internal(e.to_string()))
     }
 
     async fn delete_by_hash(&self, token_hash: &str) -> ContractResult<()> {
@@ -271,3 +274,42 @@ impl RefreshTokenServiceContract for InMemoryRefreshTokenService {
         Ok(())
     }
 }
+
+// ============================================================================
+// In-Memory API Key Service Implementation
+// ============================================================================
+
+/// Direct database implementation of ApiKeyServiceContract
+/// Used in monolith mode - no network overhead
+#[derive(Clone)]
+pub struct InMemoryApiKeyService {
+    service: ApiKeyService,
+}
+
+impl InMemoryApiKeyService {
+    pub fn new(pool: DbPool) -> Self {
+        Self {
+            service: ApiKeyService::new(pool),
+        }
+    }
+}
+
+#[async_trait]
+impl ApiKeyServiceContract for InMemoryApiKeyService {
+    async fn verify(&self, key_hash: &str) -> ContractResult<Option<ApiKeyInfo>> {
+        let key = self
+            .service
+            .verify(key_hash)
+            .await
+            .map_err(|e| ContractError::Internal(e.to_string()))?;
+
+        Ok(key.map(|k| ApiKeyInfo {
+            id: k.id,
+            organisation_id: k.organisation_id,
+            name: k.name,
+            role: k.role.into(),
+            scopes: k.scopes,
+            expires_at: k.expires_at,
+        }))
+    }
+}
@@ -8,10 +8,14 @@ use argon2::{
     Argon2,
 };
 
+use crate::api_key_service::{ApiKeyService, NewApiKey};
 use crate::db::DbPool;
 use crate::models::{
-    CreateOrganisation, CreateUser, Organisation, Role, UpdateOrganisation, UpdateUser, User,
+    ApiKey, CreateApiKey, CreateOrganisation, CreateUser, IssuedApiKey, Organisation, Role,
+    UpdateOrganisation, UpdateUser, User,
 };
+use crate::policy::{self, Action, Caller, Resource, Scope, Target};
+use crate::user_service::RefreshTokenService;
 
 #[derive(Debug, Default, serde::Deserialize)]
 pub struct ListQuery {
@@ -26,46 +30,19 @@ pub struct AppState {
     pub pool: DbPool,
 }
 
-/// Extract organisation_id from request headers (set by gateway)
-fn get_org_id_from_headers(headers: &HeaderMap) -> Option<Uuid> {
-    headers
-        .get("x-organisation-id")
-        .and_then(|v| v.to_str().ok())
-        .and_then(|s| Uuid::parse_str(s).ok())
-}
-
-/// Extract user role from request headers (set by gateway)
-fn get_role_from_headers(headers: &HeaderMap) -> Option<String> {
-    headers
-        .get("x-user-role")
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string())
-}
-
-/// Check if user is a super admin (can access all organisations)
-fn is_super_admin(headers: &HeaderMap) -> bool {
-    get_role_from_headers(headers)
-        .map(|r| r == "SUPER_ADMIN")
-        .unwrap_or(false)
-}
-
 // ============ Organisation Handlers ============
 
 pub async fn list_organisations(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    caller: Caller,
     Query(query): Query<ListQuery>,
 ) -> Result<impl IntoResponse, StatusCode> {
     let start = query._start.unwrap_or(0).max(0);
     let end = query._end.unwrap_or(start + 25).max(start + 1);
-    let limit = (end - start) as i64;
+    let limit = end - start;
 
-    // Super admins can see all organisations, others only see their own
-    let org_id = if is_super_admin(&headers) {
-        None
-    } else {
-        get_org_id_from_headers(&headers)
-    };
+    // `None` only for callers allowed to see every organisation
+    let org_id = caller.list_scope(Resource::Organisation)?;
 
     let (total, orgs) = if let Some(org_id) = org_id {
         let total: i64 = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM organisations WHERE id = $1")
@@ -133,17 +110,10 @@ pub async fn list_organisations(
 
 pub async fn get_organisation(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    caller: Caller,
     Path(id): Path<Uuid>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    // Check access: super admins can access any org, others only their own
-    if !is_super_admin(&headers) {
-        if let Some(org_id) = get_org_id_from_headers(&headers) {
-            if org_id != id {
-                return Err(StatusCode::FORBIDDEN);
-            }
-        }
-    }
+    caller.authorize(Action::Read, Resource::Organisation, Target::organisation(Some(id)))?;
 
     let org = sqlx::query_as::<_, Organisation>(
         "SELECT id, name, slug, created_at, updated_at FROM organisations WHERE id = $1",
@@ -164,13 +134,10 @@ pub async fn get_organisation(
 
 pub async fn create_organisation(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    caller: Caller,
     Json(payload): Json<CreateOrganisation>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    // Only super admins can create organisations
-    if !is_super_admin(&headers) {
-        return Err(StatusCode::FORBIDDEN);
-    }
+    caller.authorize(Action::Create, Resource::Organisation, Target::default())?;
 
     let id = Uuid::new_v4();
 
@@ -192,20 +159,11 @@ pub async fn create_organisation(
 
 pub async fn update_organisation(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    caller: Caller,
     Path(id): Path<Uuid>,
     Json(payload): Json<UpdateOrganisation>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    // Check access: super admins can update any org, admins only their own
-    if !is_super_admin(&headers) {
-        if let Some(org_id) = get_org_id_from_headers(&headers) {
-            if org_id != id {
-                return Err(StatusCode::FORBIDDEN);
-            }
-        } else {
-            return Err(StatusCode::FORBIDDEN);
-        }
-    }
+    caller.authorize(Action::Update, Resource::Organisation, Target::organisation(Some(id)))?;
 
     let org = sqlx::query_as::<_, Organisation>(
         "UPDATE organisations SET name = COALESCE($1, name), slug = COALESCE($2, slug), updated_at = NOW() WHERE id = $3 RETURNING id, name, slug, created_at, updated_at",
@@ -228,13 +186,10 @@ pub async fn update_organisation(
 
 pub async fn delete_organisation(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    caller: Caller,
     Path(id): Path<Uuid>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    // Only super admins can delete organisations
-    if !is_super_admin(&headers) {
-        return Err(StatusCode::FORBIDDEN);
-    }
+    caller.authorize(Action::Delete, Resource::Organisation, Target::organisation(Some(id)))?;
 
     let org = sqlx::query_as::<_, Organisation>(
         "DELETE FROM organisations WHERE id = $1 RETURNING id, name, slug, created_at, updated_at",
@@ -257,19 +212,15 @@ pub async fn delete_organisation(
 
 pub async fn list_users(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    caller: Caller,
     Query(query): Query<ListQuery>,
 ) -> Result<impl IntoResponse, StatusCode> {
     let start = query._start.unwrap_or(0).max(0);
     let end = query._end.unwrap_or(start + 25).max(start + 1);
-    let limit = (end - start) as i64;
+    let limit = end - start;
 
-    // Super admins can see all users, others only see users in their organisation
-    let org_id = if is_super_admin(&headers) {
-        None
-    } else {
-        get_org_id_from_headers(&headers)
-    };
+    // `None` only for callers allowed to see every user
+    let org_id = caller.list_scope(Resource::User)?;
 
     let (total, users) = if let Some(org_id) = org_id {
         let total: i64 = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE organisation_id = $1")
@@ -337,7 +288,7 @@ pub async fn list_users(
 
 pub async fn get_user(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    caller: Caller,
     Path(id): Path<Uuid>,
 ) -> Result<impl IntoResponse, StatusCode> {
     let user = sqlx::query_as::<_, User>(
@@ -353,16 +304,7 @@ pub async fn get_user(
 
     match user {
         Some(user) => {
-            // Check access: super admins can see all, others only their org
-            if !is_super_admin(&headers) {
-                if let Some(caller_org) = get_org_id_from_headers(&headers) {
-                    if user.organisation_id != Some(caller_org) {
-                        return Err(StatusCode::FORBIDDEN);
-                    }
-                } else {
-                    return Err(StatusCode::FORBIDDEN);
-                }
-            }
+            caller.authorize(Action::Read, Resource::User, user_target(&user))?;
             Ok(Json(user))
         }
         None => Err(StatusCode::NOT_FOUND),
@@ -371,25 +313,19 @@ pub async fn get_user(
 
 pub async fn create_user(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    caller: Caller,
     Json(payload): Json<CreateUser>,
 ) -> Result<impl IntoResponse, StatusCode> {
     let id = Uuid::new_v4();
     let role = payload.role.unwrap_or(Role::User);
 
-    // Determine organisation_id: super admins can specify any, others use their own
-    let organisation_id = if is_super_admin(&headers) {
-        payload.organisation_id
-    } else {
-        // Non-super admins can only create users in their own organisation
-        let caller_org = get_org_id_from_headers(&headers).ok_or(StatusCode::FORBIDDEN)?;
-        if let Some(req_org) = payload.organisation_id {
-            if req_org != caller_org {
-                return Err(StatusCode::FORBIDDEN);
-            }
-        }
-        Some(caller_org)
+    // Callers limited to their organisation create users there by default
+    let organisation_id = match policy::scope(caller.role, Action::Create, Resource::User) {
+        Scope::Any => payload.organisation_id,
+        _ => payload.organisation_id.or(caller.organisation_id),
     };
+    caller.authorize(Action::Create, Resource::User, Target::organisation(organisation_id))?;
+    caller.can_grant(role)?;
 
     // Hash password if provided
     let password_hash = if let Some(ref password) = payload.password {
@@ -429,7 +365,7 @@ pub async fn create_user(
 
 pub async fn update_user(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    caller: Caller,
     Path(id): Path<Uuid>,
     Json(payload): Json<UpdateUser>,
 ) -> Result<impl IntoResponse, StatusCode> {
@@ -450,21 +386,20 @@ pub async fn update_user(
         None => return Err(StatusCode::NOT_FOUND),
     };
 
-    // Check access: super admins can update all, others only their org
-    if !is_super_admin(&headers) {
-        if let Some(caller_org) = get_org_id_from_headers(&headers) {
-            if existing.organisation_id != Some(caller_org) {
-                return Err(StatusCode::FORBIDDEN);
-            }
-            // Non-super admins cannot move users to another org
-            if let Some(new_org) = payload.organisation_id {
-                if new_org != caller_org {
-                    return Err(StatusCode::FORBIDDEN);
-                }
-            }
-        } else {
-            return Err(StatusCode::FORBIDDEN);
-        }
+    caller.authorize(Action::Update, Resource::User, user_target(&existing))?;
+    // Peers and users more privileged than the caller are out of reach, and
+    // nobody can hand out a role at or above their own
+    caller.can_manage(Some(existing.id), existing.role)?;
+    if let Some(role) = payload.role
+        && role != existing.role
+    {
+        caller.can_grant(role)?;
+    }
+    // Moving a user needs the right to create users in the new organisation
+    if let Some(new_org) = payload.organisation_id
+        && existing.organisation_id != Some(new_org)
+    {
+        caller.authorize(Action::Create, Resource::User, Target::organisation(Some(new_org)))?;
     }
 
     // Hash password if provided
@@ -508,15 +443,27 @@ pub async fn update_user(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    match user {
-        Some(user) => Ok(Json(user)),
-        None => Err(StatusCode::NOT_FOUND),
+    let Some(user) = user else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    // A new password ends every session signed in with the old one
+    if password_hash.is_some() {
+        RefreshTokenService::new(state.pool.clone())
+            .revoke_user(user.id)
+            .await
+            .map_err(|err| {
+                eprintln!("update_user revoke refresh tokens error: {err}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
     }
+
+    Ok(Json(user))
 }
 
 pub async fn delete_user(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    caller: Caller,
     Path(id): Path<Uuid>,
 ) -> Result<impl IntoResponse, StatusCode> {
     // First check if user exists and belongs to caller's org
@@ -536,16 +483,8 @@ pub async fn delete_user(
         None => return Err(StatusCode::NOT_FOUND),
     };
 
-    // Check access: super admins can delete all, others only their org
-    if !is_super_admin(&headers) {
-        if let Some(caller_org) = get_org_id_from_headers(&headers) {
-            if existing.organisation_id != Some(caller_org) {
-                return Err(StatusCode::FORBIDDEN);
-            }
-        } else {
-            return Err(StatusCode::FORBIDDEN);
-        }
-    }
+    caller.authorize(Action::Delete, Resource::User, user_target(&existing))?;
+    caller.can_manage(Some(existing.id), existing.role)?;
 
     let user = sqlx::query_as::<_, User>(
         "DELETE FROM users WHERE id = $1 RETURNING id, organisation_id, email, name, role, created_at, updated_at",
@@ -563,3 +502,165 @@ pub async fn delete_user(
         None => Err(StatusCode::NOT_FOUND),
     }
 }
+
+fn user_target(user: &User) -> Target {
+    Target {
+        organisation_id: user.organisation_id,
+        owner_id: Some(user.id),
+    }
+}
+
+// ============ API Key Handlers ============
+
+/// Load a key and check the caller may perform `action` on it. Keys with a
+/// role the caller couldn't grant are out of reach, like users are: rotating
+/// one would hand the caller its new secret.
+async fn find_managed_api_key(
+    service: &ApiKeyService,
+    caller: &Caller,
+    action: Action,
+    id: Uuid,
+) -> Result<ApiKey, StatusCode> {
+    let api_key = service
+        .find_by_id(id)
+        .await
+        .map_err(|err| {
+            eprintln!("find api key error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    caller.authorize(action, Resource::ApiKey, Target::organisation(Some(api_key.organisation_id)))?;
+    caller.can_grant(api_key.role)?;
+    Ok(api_key)
+}
+
+pub async fn list_api_keys(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(query): Query<ListQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let start = query._start.unwrap_or(0).max(0);
+    let end = query._end.unwrap_or(start + 25).max(start + 1);
+    let limit = end - start;
+
+    // `None` only for callers allowed to see every key
+    let org_id = caller.list_scope(Resource::ApiKey)?;
+
+    let (total, keys) = ApiKeyService::new(state.pool.clone())
+        .list(org_id, limit, start)
+        .await
+        .map_err(|err| {
+            eprintln!("list_api_keys error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut response_headers = HeaderMap::new();
+    let content_range = format!("api-keys {}-{}/{}", start, end.saturating_sub(1), total);
+    response_headers.insert(
+        "Content-Range",
+        HeaderValue::from_str(&content_range).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    );
+    response_headers.insert(
+        "X-Total-Count",
+        HeaderValue::from_str(&total.to_string()).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    );
+    response_headers.insert(
+        "Access-Control-Expose-Headers",
+        HeaderValue::from_static("Content-Range, X-Total-Count"),
+    );
+
+    Ok((response_headers, Json(keys)))
+}
+
+pub async fn get_api_key(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let service = ApiKeyService::new(state.pool.clone());
+    let api_key = find_managed_api_key(&service, &caller, Action::Read, id).await?;
+    Ok(Json(api_key))
+}
+
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<CreateApiKey>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let organisation_id = payload
+        .organisation_id
+        .or(caller.organisation_id)
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    caller.authorize(Action::Create, Resource::ApiKey, Target::organisation(Some(organisation_id)))?;
+
+    // A key can never carry more privilege than the caller issuing it
+    let role = payload.role.unwrap_or(Role::User);
+    caller.can_grant(role)?;
+
+    if payload.name.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if let Some(expires_at) = payload.expires_at
+        && expires_at <= chrono::Utc::now()
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let (api_key, key) = ApiKeyService::new(state.pool.clone())
+        .create(NewApiKey {
+            organisation_id,
+            name: payload.name.trim(),
+            role,
+            scopes: &payload.scopes,
+            expires_at: payload.expires_at,
+            created_by: caller.user_id,
+        })
+        .await
+        .map_err(|err| {
+            eprintln!("create_api_key error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok((StatusCode::CREATED, Json(IssuedApiKey { api_key, key })))
+}
+
+pub async fn rotate_api_key(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let service = ApiKeyService::new(state.pool.clone());
+    let existing = find_managed_api_key(&service, &caller, Action::Update, id).await?;
+    if existing.revoked_at.is_some() {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let rotated = service.rotate(id).await.map_err(|err| {
+        eprintln!("rotate_api_key error: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    match rotated {
+        Some((api_key, key)) => Ok(Json(IssuedApiKey { api_key, key })),
+        None => Err(StatusCode::CONFLICT),
+    }
+}
+
+pub async fn revoke_api_key(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let service = ApiKeyService::new(state.pool.clone());
+    find_managed_api_key(&service, &caller, Action::Delete, id).await?;
+
+    let revoked = service.revoke(id).await.map_err(|err| {
+        eprintln!("revoke_api_key error: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    match revoked {
+        Some(api_key) => Ok(Json(api_key)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
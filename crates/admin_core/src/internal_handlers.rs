@@ -1,5 +1,17 @@
 //! Internal API handlers for service-to-service communication
-//! These endpoints are NOT meant for external access and should be protected in production.
+//!
+//! These endpoints are for other services only; the gateway never forwards
+//! `/internal/*`. Every request must carry a valid service signature, checked
+//! by [`require_service_signature`](crate::identity::require_service_signature)
+//! before any handler runs:
+//!
+//! - `x-service-name`: the calling service
+//! - `x-service-timestamp`: Unix time of signing, within 5 minutes of ours
+//! - `x-service-signature`: `v1=` and the hex HMAC-SHA-256, keyed with
+//!   `ADMIN_SERVICE_SECRET`, over the service name, timestamp, method, path
+//!   and query (including `/internal`) and the SHA-256 of the body
+//!
+//! Anything missing, stale or not matching is answered with `401`.
 
 use axum::{
     Json,
@@ -11,6 +23,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::api_key_service::ApiKeyService;
 use crate::handlers::AppState;
 use crate::models::Role;
 use crate::user_service::{RefreshTokenService, UserService};
@@ -152,7 +165,10 @@ pub struct RefreshTokenInfoResponse {
     pub id: Uuid,
     pub user_id: Uuid,
     pub organisation_id: Option<Uuid>,
+    pub family_id: Uuid,
     pub expires_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
 }
 
 pub async fn get_refresh_token_by_hash(
@@ -168,7 +184,10 @@ pub async fn get_refresh_token_by_hash(
                 id: info.id,
                 user_id: info.user_id,
                 organisation_id: info.organisation_id,
+                family_id: info.family_id,
                 expires_at: info.expires_at,
+                rotated_at: info.rotated_at,
+                revoked_at: info.revoked_at,
             }),
         )
             .into_response(),
@@ -181,21 +200,52 @@ pub async fn get_refresh_token_by_hash(
     }
 }
 
-/// PUT /internal/refresh-tokens/{id} - Update (rotate) refresh token
+/// POST /internal/refresh-tokens/{id}/rotate - Replace a token with its successor
 #[derive(Deserialize)]
-pub struct UpdateRefreshTokenRequest {
+pub struct RotateRefreshTokenRequest {
     pub token_hash: String,
     pub expires_at: DateTime<Utc>,
 }
 
-pub async fn update_refresh_token(
+#[derive(Serialize)]
+pub struct RotateRefreshTokenResponse {
+    pub id: Uuid,
+}
+
+pub async fn rotate_refresh_token(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
-    Json(payload): Json<UpdateRefreshTokenRequest>,
+    Json(payload): Json<RotateRefreshTokenRequest>,
 ) -> impl IntoResponse {
     let token_service = RefreshTokenService::new(state.pool.clone());
-    
-    match token_service.update(id, &payload.token_hash, payload.expires_at).await {
+
+    match token_service.rotate(id, &payload.token_hash, payload.expires_at).await {
+        Ok(Some(id)) => (StatusCode::OK, Json(RotateRefreshTokenResponse { id })).into_response(),
+        // Already rotated or revoked
+        Ok(None) => StatusCode::CONFLICT.into_response(),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(InternalError { error: err.to_string() }),
+        )
+            .into_response(),
+    }
+}
+
+/// PUT /internal/refresh-tokens/{id}/hash - Replace a token's stored hash
+#[derive(Deserialize)]
+pub struct RehashRefreshTokenRequest {
+    pub old_token_hash: String,
+    pub token_hash: String,
+}
+
+pub async fn rehash_refresh_token(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<RehashRefreshTokenRequest>,
+) -> impl IntoResponse {
+    let token_service = RefreshTokenService::new(state.pool.clone());
+
+    match token_service.rehash(id, &payload.old_token_hash, &payload.token_hash).await {
         Ok(()) => StatusCode::NO_CONTENT.into_response(),
         Err(err) => (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -205,6 +255,28 @@ pub async fn update_refresh_token(
     }
 }
 
+/// POST /internal/refresh-tokens/families/{family_id}/revoke - Revoke a token family
+#[derive(Serialize)]
+pub struct RevokeFamilyResponse {
+    pub revoked: u64,
+}
+
+pub async fn revoke_refresh_token_family(
+    State(state): State<AppState>,
+    Path(family_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let token_service = RefreshTokenService::new(state.pool.clone());
+
+    match token_service.revoke_family(family_id).await {
+        Ok(revoked) => (StatusCode::OK, Json(RevokeFamilyResponse { revoked })).into_response(),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(InternalError { error: err.to_string() }),
+        )
+            .into_response(),
+    }
+}
+
 /// DELETE /internal/refresh-tokens/{id} - Delete refresh token by ID
 pub async fn delete_refresh_token(
     State(state): State<AppState>,
@@ -231,6 +303,54 @@ pub async fn delete_refresh_token_by_hash(
     }
 }
 
+// ============================================================================
+// API Key Internal API
+// ============================================================================
+
+/// POST /internal/api-keys/verify - Resolve an active API key by hash
+#[derive(Deserialize)]
+pub struct VerifyApiKeyRequest {
+    pub key_hash: String,
+}
+
+pub async fn verify_api_key(
+    State(state): State<AppState>,
+    Json(payload): Json<VerifyApiKeyRequest>,
+) -> impl IntoResponse {
+    let api_key_service = ApiKeyService::new(state.pool.clone());
+
+    match api_key_service.verify(&payload.key_hash).await {
+        Ok(Some(key)) => (
+            StatusCode::OK,
+            Json(ApiKeyInfo {
+                id: key.id,
+                organisation_id: key.organisation_id,
+                name: key.name,
+                role: key.role,
+                scopes: key.scopes,
+                expires_at: key.expires_at,
+            }),
+        )
+            .into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(InternalError { error: err.to_string() }),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyInfo {
+    pub id: Uuid,
+    pub organisation_id: Uuid,
+    pub name: String,
+    pub role: Role,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
 #[derive(Serialize)]
 struct InternalError {
     error: String,
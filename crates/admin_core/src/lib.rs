@@ -1,9 +1,14 @@
+pub mod api_key_service;
 pub mod config;
 pub mod contract_impl;
 pub mod db;
 pub mod handlers;
+pub mod identity;
 pub mod internal_handlers;
+pub mod migrations;
 pub mod models;
+pub mod organisation_service;
+pub mod policy;
 pub mod server;
 pub mod service;
 pub mod user_service;
@@ -12,7 +17,7 @@ pub mod user_service;
 pub use db::DbPool;
 
 // Re-export contract implementations for monolith mode
-pub use contract_impl::{InMemoryRefreshTokenService, InMemoryUserService};
+pub use contract_impl::{InMemoryApiKeyService, InMemoryRefreshTokenService, InMemoryUserService};
 
 // Re-export the old services for backward compatibility
 pub use user_service::{RefreshTokenService, UserService, UserWithPassword, UserInfo};
@@ -21,3 +26,10 @@ pub async fn run() -> anyhow::Result<()> {
     let config = config::AdminConfig::default();
     service::run(&config).await
 }
+
+/// Run a `migrate` subcommand against `DATABASE_URL`
+pub async fn run_migrate_command(args: &[String]) -> anyhow::Result<()> {
+    let config = config::AdminConfig::default();
+    let pool = db::create_pool(&config.database_url).await?;
+    migrations::command(&pool, migrations::Migrate::parse(args)?).await
+}
@@ -0,0 +1,323 @@
+//! Versioned schema migrations.
+//!
+//! The admin service owns the database schema. Migrations live in
+//! `crates/admin_core/migrations` as `NNNN_name.up.sql` / `NNNN_name.down.sql`
+//! pairs, are compiled into the binary and are recorded in `schema_migrations`
+//! with a SHA-256 of their up script. A migration that was edited after being
+//! applied is refused rather than silently skipped.
+//!
+//! Runs are serialized with a Postgres advisory lock, so several instances
+//! starting at once apply each migration exactly once.
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{Connection, PgConnection};
+
+use crate::db::DbPool;
+
+/// Advisory lock key held while migrating
+const LOCK_KEY: i64 = 0x6170_6973_656e_7469;
+
+pub struct Migration {
+    pub version: i64,
+    /// File stem, e.g. `0001_organisations_and_users`
+    pub name: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        common::hex::encode(&Sha256::digest(self.up.as_bytes()))
+    }
+}
+
+macro_rules! migration {
+    ($version:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../migrations/", $name, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $name, ".down.sql")),
+        }
+    };
+}
+
+/// All migrations, in version order
+pub static MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_organisations_and_users"),
+    migration!(2, "0002_refresh_tokens"),
+    migration!(3, "0003_refresh_token_families"),
+    migration!(4, "0004_api_keys"),
+];
+
+/// Version the schema is at once every migration is applied
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Pending,
+    Applied,
+    /// Applied, but the script has changed since
+    Modified,
+    /// Applied by a newer build; not known to this one
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub state: State,
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow)]
+struct AppliedRow {
+    version: i64,
+    name: String,
+    checksum: String,
+    applied_at: DateTime<Utc>,
+}
+
+/// Every known or applied migration and whether it has been run
+pub async fn status(pool: &DbPool) -> anyhow::Result<Vec<MigrationStatus>> {
+    let mut conn = pool.acquire().await.context("acquire connection")?;
+    ensure_table(&mut conn).await?;
+    let applied = applied(&mut conn).await?;
+
+    let mut statuses: Vec<MigrationStatus> = MIGRATIONS
+        .iter()
+        .map(|m| {
+            let row = applied.iter().find(|row| row.version == m.version);
+            MigrationStatus {
+                version: m.version,
+                name: m.name.to_string(),
+                state: match row {
+                    None => State::Pending,
+                    Some(row) if row.checksum == m.checksum() => State::Applied,
+                    Some(_) => State::Modified,
+                },
+                applied_at: row.map(|row| row.applied_at),
+            }
+        })
+        .collect();
+    statuses.extend(
+        applied
+            .iter()
+            .filter(|row| !MIGRATIONS.iter().any(|m| m.version == row.version))
+            .map(|row| MigrationStatus {
+                version: row.version,
+                name: row.name.clone(),
+                state: State::Unknown,
+                applied_at: Some(row.applied_at),
+            }),
+    );
+    statuses.sort_by_key(|s| s.version);
+    Ok(statuses)
+}
+
+/// Apply pending migrations up to `target` (all when `None`); returns the
+/// versions applied
+pub async fn up(pool: &DbPool, target: Option<i64>) -> anyhow::Result<Vec<i64>> {
+    let target = target.unwrap_or_else(latest_version);
+    with_lock(pool, async |conn| {
+        let applied = applied(conn).await?;
+        for row in &applied {
+            match MIGRATIONS.iter().find(|m| m.version == row.version) {
+                Some(m) if m.checksum() != row.checksum => anyhow::bail!(
+                    "migration {} was changed after it was applied",
+                    m.name
+                ),
+                Some(_) => {}
+                None => eprintln!(
+                    "[admin] database has migration {} which this build does not know",
+                    row.name
+                ),
+            }
+        }
+
+        let mut done = Vec::new();
+        for m in MIGRATIONS.iter().filter(|m| m.version <= target) {
+            if applied.iter().any(|row| row.version == m.version) {
+                continue;
+            }
+            let started = std::time::Instant::now();
+            let mut tx = conn.begin().await?;
+            sqlx::raw_sql(m.up)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("apply migration {}", m.name))?;
+            sqlx::query(
+                "INSERT INTO schema_migrations (version, name, checksum, execution_ms) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(m.version)
+            .bind(m.name)
+            .bind(m.checksum())
+            .bind(started.elapsed().as_millis() as i64)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            println!("[admin] applied migration {}", m.name);
+            done.push(m.version);
+        }
+        Ok(done)
+    })
+    .await
+}
+
+/// Revert applied migrations newer than `target` (only the last one when
+/// `None`), newest first; returns the versions reverted
+pub async fn down(pool: &DbPool, target: Option<i64>) -> anyhow::Result<Vec<i64>> {
+    with_lock(pool, async |conn| {
+        let applied = applied(conn).await?;
+        let target = match target {
+            Some(target) => target,
+            None => match applied.as_slice() {
+                [.., previous, _] => previous.version,
+                _ => 0,
+            },
+        };
+        let mut done = Vec::new();
+        for row in applied.iter().rev().filter(|row| row.version > target) {
+            let Some(m) = MIGRATIONS.iter().find(|m| m.version == row.version) else {
+                anyhow::bail!(
+                    "cannot revert migration {}: not known to this build",
+                    row.name
+                );
+            };
+            let mut tx = conn.begin().await?;
+            sqlx::raw_sql(m.down)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("revert migration {}", m.name))?;
+            sqlx::query("DELETE FROM schema_migrations WHERE version = $1")
+                .bind(m.version)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            println!("[admin] reverted migration {}", m.name);
+            done.push(m.version);
+        }
+        Ok(done)
+    })
+    .await
+}
+
+/// Run `f` on one connection while holding the migration lock
+async fn with_lock<T>(
+    pool: &DbPool,
+    f: impl AsyncFnOnce(&mut PgConnection) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut conn = pool.acquire().await.context("acquire connection")?;
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(LOCK_KEY)
+        .execute(&mut *conn)
+        .await
+        .context("take migration lock")?;
+
+    let result = match ensure_table(&mut conn).await {
+        Ok(()) => f(&mut conn).await,
+        Err(err) => Err(err),
+    };
+
+    let unlocked = sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(LOCK_KEY)
+        .execute(&mut *conn)
+        .await;
+    if unlocked.is_err() {
+        // The lock is tied to the session; don't hand it back to the pool
+        conn.detach();
+    }
+    result
+}
+
+async fn ensure_table(conn: &mut PgConnection) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version BIGINT PRIMARY KEY,
+            name TEXT NOT NULL,
+            checksum TEXT NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            execution_ms BIGINT NOT NULL
+        );
+        "#,
+    )
+    .execute(conn)
+    .await
+    .context("create schema_migrations table")?;
+    Ok(())
+}
+
+async fn applied(conn: &mut PgConnection) -> anyhow::Result<Vec<AppliedRow>> {
+    sqlx::query_as::<_, AppliedRow>(
+        "SELECT version, name, checksum, applied_at FROM schema_migrations ORDER BY version",
+    )
+    .fetch_all(conn)
+    .await
+    .context("read schema_migrations")
+}
+
+/// Print `status` as a table
+pub async fn print_status(pool: &DbPool) -> anyhow::Result<()> {
+    for s in status(pool).await? {
+        let state = match s.state {
+            State::Pending => "pending",
+            State::Applied => "applied",
+            State::Modified => "MODIFIED",
+            State::Unknown => "UNKNOWN",
+        };
+        let applied_at = s.applied_at.map(|t| t.to_rfc3339()).unwrap_or_default();
+        println!("{:<8}  {:<36}  {applied_at}", state, s.name);
+    }
+    Ok(())
+}
+
+/// A `migrate` subcommand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Migrate {
+    Status,
+    /// Apply pending migrations, up to a version if given
+    Up(Option<i64>),
+    /// Revert down to a version, or only the last applied migration
+    Down(Option<i64>),
+}
+
+impl Migrate {
+    /// `status | up [VERSION] | down [VERSION]`, as passed on a command line
+    pub fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let version = args
+            .get(1)
+            .map(|v| v.parse().with_context(|| format!("invalid version '{v}'")))
+            .transpose()?;
+
+        match args.first().map(String::as_str) {
+            None | Some("status") => Ok(Self::Status),
+            Some("up") => Ok(Self::Up(version)),
+            Some("down") => Ok(Self::Down(version)),
+            Some(other) => anyhow::bail!("unknown migrate command '{other}' (expected status, up or down)"),
+        }
+    }
+}
+
+/// Run a `migrate` subcommand, reporting what it did on stdout
+pub async fn command(pool: &DbPool, command: Migrate) -> anyhow::Result<()> {
+    match command {
+        Migrate::Status => print_status(pool).await?,
+        Migrate::Up(version) => {
+            if up(pool, version).await?.is_empty() {
+                println!("schema is up to date");
+            }
+        }
+        Migrate::Down(version) => {
+            if down(pool, version).await?.is_empty() {
+                println!("nothing to revert");
+            }
+        }
+    }
+    Ok(())
+}
@@ -3,7 +3,8 @@ use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
-/// User roles
+/// User roles as stored in the `user_role` column. Names and ranks come
+/// from `contracts::Role`, so both sides order roles the same way.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, sqlx::Type)]
 #[sqlx(type_name = "user_role", rename_all = "SCREAMING_SNAKE_CASE")]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
@@ -17,11 +18,37 @@ pub enum Role {
 
 impl Role {
     pub fn as_str(&self) -> &'static str {
-        match self {
-            Role::SuperAdmin => "SUPER_ADMIN",
-            Role::Admin => "ADMIN",
-            Role::Supervisor => "SUPERVISOR",
-            Role::User => "USER",
+        contracts::Role::from(*self).as_str()
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        contracts::Role::parse(s).map(Self::from)
+    }
+
+    /// Privilege level; higher can do more
+    pub fn rank(&self) -> u8 {
+        contracts::Role::from(*self).rank()
+    }
+}
+
+impl From<Role> for contracts::Role {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::SuperAdmin => contracts::Role::SuperAdmin,
+            Role::Admin => contracts::Role::Admin,
+            Role::Supervisor => contracts::Role::Supervisor,
+            Role::User => contracts::Role::User,
+        }
+    }
+}
+
+impl From<contracts::Role> for Role {
+    fn from(role: contracts::Role) -> Self {
+        match role {
+            contracts::Role::SuperAdmin => Role::SuperAdmin,
+            contracts::Role::Admin => Role::Admin,
+            contracts::Role::Supervisor => Role::Supervisor,
+            contracts::Role::User => Role::User,
         }
     }
 }
@@ -87,3 +114,43 @@ pub struct UpdateUser {
     #[serde(default)]
     pub role: Option<Role>,
 }
+
+/// API key metadata; the key itself is only returned once, on creation or rotation
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub organisation_id: Uuid,
+    pub name: String,
+    /// Public part of the key (`ask_<prefix>`), for telling keys apart
+    pub prefix: String,
+    pub role: Role,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateApiKey {
+    pub name: String,
+    /// Defaults to the caller's organisation
+    #[serde(default)]
+    pub organisation_id: Option<Uuid>,
+    #[serde(default)]
+    pub role: Option<Role>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Response for a newly issued key; `key` is not retrievable afterwards
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
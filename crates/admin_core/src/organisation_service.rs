@@ -0,0 +1,39 @@
+use uuid::Uuid;
+
+use crate::db::DbPool;
+use crate::models::Organisation;
+
+/// Organisation service
+#[derive(Clone)]
+pub struct OrganisationService {
+    pool: DbPool,
+}
+
+impl OrganisationService {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create(&self, name: &str, slug: &str) -> anyhow::Result<Organisation> {
+        let org = sqlx::query_as::<_, Organisation>(
+            "INSERT INTO organisations (id, name, slug) VALUES ($1, $2, $3) RETURNING id, name, slug, created_at, updated_at",
+        )
+        .bind(Uuid::new_v4())
+        .bind(name)
+        .bind(slug)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(org)
+    }
+
+    /// Find an organisation by id or, failing that, by slug
+    pub async fn find(&self, id_or_slug: &str) -> anyhow::Result<Option<Organisation>> {
+        let org = sqlx::query_as::<_, Organisation>(
+            "SELECT id, name, slug, created_at, updated_at FROM organisations WHERE id::text = $1 OR slug = $1 ORDER BY (id::text = $1) DESC LIMIT 1",
+        )
+        .bind(id_or_slug)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(org)
+    }
+}
@@ -0,0 +1,329 @@
+//! Authorization policy for the public admin API.
+//!
+//! Every decision goes through one table mapping (role, action, resource) to
+//! the scope the role may act in. Handlers take a [`Caller`] extractor, built
+//! from the gateway-signed identity headers, and ask it whether an action on a
+//! target is allowed.
+
+use axum::extract::FromRequestParts;
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use uuid::Uuid;
+
+use crate::models::Role;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    List,
+    Read,
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Organisation,
+    User,
+    ApiKey,
+}
+
+/// Where a role may perform an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Anywhere
+    Any,
+    /// Within the caller's own organisation
+    Organisation,
+    /// Only on the caller's own record
+    Own,
+    Denied,
+}
+
+/// The policy table
+pub fn scope(role: Role, action: Action, resource: Resource) -> Scope {
+    use Action::*;
+    use Resource::*;
+
+    match (role, resource, action) {
+        (Role::SuperAdmin, _, _) => Scope::Any,
+
+        // Organisations are created and removed by super admins only
+        (Role::Admin, Organisation, List | Read | Update) => Scope::Organisation,
+        (Role::Supervisor | Role::User, Organisation, List | Read) => Scope::Organisation,
+
+        // Supervisors look after the users of their organisation; `can_manage`
+        // keeps them to users ranked below them (and themselves)
+        (Role::Admin, User, _) => Scope::Organisation,
+        (Role::Supervisor, User, List | Read | Update) => Scope::Organisation,
+        (Role::User, User, List | Read) => Scope::Organisation,
+        (Role::User, User, Update) => Scope::Own,
+
+        (Role::Admin, ApiKey, _) => Scope::Organisation,
+
+        _ => Scope::Denied,
+    }
+}
+
+/// What an action applies to
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Target {
+    /// Organisation the target belongs to (or, for organisations, its id)
+    pub organisation_id: Option<Uuid>,
+    /// User the target is (or belongs to)
+    pub owner_id: Option<Uuid>,
+}
+
+impl Target {
+    pub fn organisation(organisation_id: Option<Uuid>) -> Self {
+        Self {
+            organisation_id,
+            owner_id: None,
+        }
+    }
+}
+
+/// The authenticated caller, as asserted by the gateway
+#[derive(Debug, Clone)]
+pub struct Caller {
+    /// Absent for API key callers
+    pub user_id: Option<Uuid>,
+    pub role: Role,
+    pub organisation_id: Option<Uuid>,
+}
+
+impl Caller {
+    /// Allow `action` on `target`, or answer `403`
+    pub fn authorize(
+        &self,
+        action: Action,
+        resource: Resource,
+        target: Target,
+    ) -> Result<(), StatusCode> {
+        let allowed = match scope(self.role, action, resource) {
+            Scope::Any => true,
+            Scope::Organisation => {
+                self.organisation_id.is_some() && self.organisation_id == target.organisation_id
+            }
+            Scope::Own => self.user_id.is_some() && self.user_id == target.owner_id,
+            Scope::Denied => false,
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+
+    /// Organisation a listing is restricted to; `None` means everything
+    pub fn list_scope(&self, resource: Resource) -> Result<Option<Uuid>, StatusCode> {
+        match scope(self.role, Action::List, resource) {
+            Scope::Any => Ok(None),
+            Scope::Organisation => self.organisation_id.map(Some).ok_or(StatusCode::FORBIDDEN),
+            Scope::Own | Scope::Denied => Err(StatusCode::FORBIDDEN),
+        }
+    }
+
+    /// Roles can only be handed out below the caller's own rank. Super
+    /// admins, with nobody above them, are the exception.
+    pub fn can_grant(&self, role: Role) -> Result<(), StatusCode> {
+        if self.role == Role::SuperAdmin || role.rank() < self.role.rank() {
+            Ok(())
+        } else {
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+
+    /// Records of a role the caller couldn't grant are out of reach, apart
+    /// from the caller's own
+    pub fn can_manage(&self, owner_id: Option<Uuid>, role: Role) -> Result<(), StatusCode> {
+        if self.user_id.is_some() && self.user_id == owner_id {
+            return Ok(());
+        }
+        self.can_grant(role)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Caller {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = |name: &str| parts.headers.get(name).and_then(|v| v.to_str().ok());
+
+        let role = header("x-user-role")
+            .and_then(Role::parse)
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        Ok(Self {
+            user_id: header("x-user-id").and_then(|s| Uuid::parse_str(s).ok()),
+            role,
+            organisation_id: header("x-organisation-id").and_then(|s| Uuid::parse_str(s).ok()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROLES: [Role; 4] = [Role::SuperAdmin, Role::Admin, Role::Supervisor, Role::User];
+    const ACTIONS: [Action; 5] = [
+        Action::List,
+        Action::Read,
+        Action::Create,
+        Action::Update,
+        Action::Delete,
+    ];
+    const RESOURCES: [Resource; 3] = [Resource::Organisation, Resource::User, Resource::ApiKey];
+
+    const ME: Uuid = Uuid::from_u128(1);
+    const SOMEONE_ELSE: Uuid = Uuid::from_u128(2);
+    const MY_ORG: Uuid = Uuid::from_u128(10);
+    const OTHER_ORG: Uuid = Uuid::from_u128(20);
+
+    /// The policy written out per role and resource, for List, Read, Create,
+    /// Update and Delete in that order: `a` anywhere, `o` in the caller's
+    /// organisation, `s` on the caller's own record only, `-` never
+    const MATRIX: [(Role, Resource, &str); 12] = [
+        (Role::SuperAdmin, Resource::Organisation, "aaaaa"),
+        (Role::SuperAdmin, Resource::User, "aaaaa"),
+        (Role::SuperAdmin, Resource::ApiKey, "aaaaa"),
+        (Role::Admin, Resource::Organisation, "oo-o-"),
+        (Role::Admin, Resource::User, "ooooo"),
+        (Role::Admin, Resource::ApiKey, "ooooo"),
+        (Role::Supervisor, Resource::Organisation, "oo---"),
+        (Role::Supervisor, Resource::User, "oo-o-"),
+        (Role::Supervisor, Resource::ApiKey, "-----"),
+        (Role::User, Resource::Organisation, "oo---"),
+        (Role::User, Resource::User, "oo-s-"),
+        (Role::User, Resource::ApiKey, "-----"),
+    ];
+
+    fn caller(role: Role) -> Caller {
+        Caller {
+            user_id: Some(ME),
+            role,
+            organisation_id: Some(MY_ORG),
+        }
+    }
+
+    fn target(organisation_id: Uuid, owner_id: Uuid) -> Target {
+        Target {
+            organisation_id: Some(organisation_id),
+            owner_id: Some(owner_id),
+        }
+    }
+
+    #[test]
+    fn matrix_covers_every_role_and_resource() {
+        for role in ROLES {
+            for resource in RESOURCES {
+                let rows = MATRIX
+                    .iter()
+                    .filter(|(r, res, _)| *r == role && *res == resource)
+                    .count();
+                assert_eq!(rows, 1, "{role:?} {resource:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn authorize_follows_the_matrix() {
+        for (role, resource, allowed) in MATRIX {
+            for (action, expected) in ACTIONS.into_iter().zip(allowed.chars()) {
+                // (own record, someone else in my organisation, another organisation)
+                let want = match expected {
+                    'a' => (true, true, true),
+                    'o' => (true, true, false),
+                    's' => (true, false, false),
+                    _ => (false, false, false),
+                };
+                let caller = caller(role);
+                let got = (
+                    caller.authorize(action, resource, target(MY_ORG, ME)).is_ok(),
+                    caller.authorize(action, resource, target(MY_ORG, SOMEONE_ELSE)).is_ok(),
+                    caller.authorize(action, resource, target(OTHER_ORG, SOMEONE_ELSE)).is_ok(),
+                );
+                assert_eq!(got, want, "{role:?} {action:?} {resource:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn organisation_scope_needs_an_organisation() {
+        for (role, resource, allowed) in MATRIX {
+            let caller = Caller {
+                organisation_id: None,
+                ..caller(role)
+            };
+            for (action, expected) in ACTIONS.into_iter().zip(allowed.chars()) {
+                let got = caller.authorize(action, resource, Target::default()).is_ok();
+                assert_eq!(got, expected == 'a', "{role:?} {action:?} {resource:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn list_scope_restricts_to_own_organisation() {
+        for (role, resource, allowed) in MATRIX {
+            let want = match allowed.chars().next() {
+                Some('a') => Ok(None),
+                Some('o') => Ok(Some(MY_ORG)),
+                _ => Err(StatusCode::FORBIDDEN),
+            };
+            assert_eq!(caller(role).list_scope(resource), want, "{role:?} {resource:?}");
+        }
+
+        let homeless = Caller {
+            organisation_id: None,
+            ..caller(Role::Admin)
+        };
+        assert_eq!(homeless.list_scope(Resource::User), Err(StatusCode::FORBIDDEN));
+    }
+
+    #[test]
+    fn roles_parse_and_rank_like_the_gateway() {
+        for role in ROLES {
+            let shared = contracts::Role::from(role);
+            assert_eq!(Role::parse(shared.as_str()), Some(role));
+            assert_eq!(Role::parse(&shared.as_str().to_lowercase()), Some(role));
+            assert_eq!(role.rank(), shared.rank());
+        }
+        assert_eq!(Role::parse("OWNER"), None);
+    }
+
+    #[test]
+    fn can_grant_only_below_own_rank() {
+        for granter in ROLES {
+            for role in ROLES {
+                let allowed = caller(granter).can_grant(role).is_ok();
+                let want = granter == Role::SuperAdmin || role.rank() < granter.rank();
+                assert_eq!(allowed, want, "{granter:?} granting {role:?}");
+            }
+        }
+        assert!(caller(Role::Admin).can_grant(Role::Admin).is_err());
+        assert!(caller(Role::Admin).can_grant(Role::SuperAdmin).is_err());
+        assert!(caller(Role::Supervisor).can_grant(Role::Supervisor).is_err());
+        assert!(caller(Role::Supervisor).can_grant(Role::User).is_ok());
+        assert!(caller(Role::User).can_grant(Role::User).is_err());
+    }
+
+    #[test]
+    fn can_manage_allows_own_record_only_at_own_rank() {
+        for role in ROLES {
+            let caller = caller(role);
+            assert!(caller.can_manage(Some(ME), role).is_ok(), "{role:?} on itself");
+            assert_eq!(
+                caller.can_manage(Some(SOMEONE_ELSE), role).is_ok(),
+                role == Role::SuperAdmin,
+                "{role:?} on a peer"
+            );
+        }
+
+        let api_key_caller = Caller {
+            user_id: None,
+            ..caller(Role::Admin)
+        };
+        assert!(api_key_caller.can_manage(None, Role::Admin).is_err());
+        assert!(api_key_caller.can_manage(None, Role::User).is_ok());
+    }
+}
@@ -1,35 +1,59 @@
+use std::sync::Arc;
+
 use axum::Router;
 use axum::routing::{delete, get, post, put};
+use common::health;
+use common::identity::IdentitySigner;
+use common::service_auth::ServiceSigner;
+use common::shutdown::Shutdown;
 use tower_http::cors::{Any, CorsLayer};
 
-use crate::db::DbPool;
+use crate::config::AdminConfig;
+use crate::db::{self, DbPool};
 use crate::handlers::{
     AppState,
+    // API key handlers
+    create_api_key, get_api_key, list_api_keys, revoke_api_key, rotate_api_key,
     // Organisation handlers
     create_organisation, delete_organisation, get_organisation, list_organisations, update_organisation,
     // User handlers
     create_user, delete_user, get_user, list_users, update_user,
 };
+use crate::identity::{GatewayIdentity, require_gateway_identity, require_service_signature};
 use crate::internal_handlers::{
     create_refresh_token, create_user_internal, delete_refresh_token,
     delete_refresh_token_by_hash, get_refresh_token_by_hash, get_user_by_email,
-    get_user_by_id_internal, get_user_count, update_refresh_token,
+    get_user_by_id_internal, get_user_count, rehash_refresh_token, revoke_refresh_token_family,
+    rotate_refresh_token, verify_api_key,
 };
 
-pub async fn run(bind_addr: &str, pool: DbPool) -> Result<(), std::io::Error> {
-    let app = build_router(pool);
+/// Serve until `shutdown` stops listeners, letting in-flight requests finish
+/// within the drain deadline
+pub async fn run(
+    config: &AdminConfig,
+    pool: DbPool,
+    shutdown: Shutdown,
+) -> Result<(), std::io::Error> {
+    let health = health::routes("admin", shutdown.clone(), vec![db::readiness(pool.clone())]);
+    let app = build_router(pool, config).merge(health);
 
-    let listener = tokio::net::TcpListener::bind(bind_addr).await?;
-    axum::serve(listener, app).await
+    let listener = tokio::net::TcpListener::bind(&config.bind_addr).await?;
+    let server = axum::serve(listener, app).with_graceful_shutdown(shutdown.wait());
+    shutdown.drain("admin", server).await
 }
 
-pub fn build_router(pool: DbPool) -> Router {
+pub fn build_router(pool: DbPool, config: &AdminConfig) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
     let state = AppState { pool };
+    let identity = Arc::new(GatewayIdentity {
+        signer: IdentitySigner::new(&config.identity_secret),
+        audience: config.identity_audience.clone(),
+    });
+    let service_signer = Arc::new(ServiceSigner::new(&config.service_secret));
 
     // Internal routes for service-to-service communication
     let internal_routes = Router::new()
@@ -42,8 +66,17 @@ pub fn build_router(pool: DbPool) -> Router {
         .route("/refresh-tokens", post(create_refresh_token))
         .route("/refresh-tokens/by-hash/{hash}", get(get_refresh_token_by_hash))
         .route("/refresh-tokens/by-hash/{hash}", delete(delete_refresh_token_by_hash))
-        .route("/refresh-tokens/{id}", put(update_refresh_token))
-        .route("/refresh-tokens/{id}", delete(delete_refresh_token));
+        .route("/refresh-tokens/{id}/rotate", post(rotate_refresh_token))
+        .route("/refresh-tokens/{id}/hash", put(rehash_refresh_token))
+        .route("/refresh-tokens/families/{family_id}/revoke", post(revoke_refresh_token_family))
+        .route("/refresh-tokens/{id}", delete(delete_refresh_token))
+        // API key endpoints
+        .route("/api-keys/verify", post(verify_api_key))
+        // Only other services may call these, never a client
+        .route_layer(axum::middleware::from_fn_with_state(
+            service_signer,
+            require_service_signature,
+        ));
 
     Router::new()
         // Organisation routes
@@ -58,6 +91,15 @@ pub fn build_router(pool: DbPool) -> Router {
             "/users/{id}",
             get(get_user).put(update_user).delete(delete_user),
         )
+        // API key routes
+        .route("/api-keys", get(list_api_keys).post(create_api_key))
+        .route("/api-keys/{id}", get(get_api_key).delete(revoke_api_key))
+        .route("/api-keys/{id}/rotate", post(rotate_api_key))
+        // Public routes act on the caller's identity, which only the gateway may assert
+        .route_layer(axum::middleware::from_fn_with_state(
+            identity,
+            require_gateway_identity,
+        ))
         .nest("/internal", internal_routes)
         .with_state(state)
         .layer(cors)
@@ -1,20 +1,28 @@
 use anyhow::Context;
+use common::shutdown::Shutdown;
 
 use crate::config::AdminConfig;
 use crate::db;
 use crate::server;
 
 pub async fn run(config: &AdminConfig) -> anyhow::Result<()> {
+    config.validate()?;
     println!("admin service running on {}", config.bind_addr);
 
     let pool = db::create_pool(&config.database_url)
         .await
         .context("create pool")?;
-    db::migrate(&pool).await.context("run migrations")?;
+    if config.migrate_on_start {
+        db::migrate(&pool).await.context("run migrations")?;
+    }
 
-    server::run(&config.bind_addr, pool)
+    let shutdown = Shutdown::new();
+    shutdown.trigger_on_signals();
+
+    let result = server::run(config, pool.clone(), shutdown)
         .await
-        .context("start http server")?;
+        .context("start http server");
+    db::close(&pool).await;
 
-    Ok(())
+    result
 }
@@ -37,7 +37,7 @@ impl From<&UserWithPassword> for UserInfo {
             organisation_id: user.organisation_id,
             email: user.email.clone(),
             name: user.name.clone(),
-            role: user.role.clone(),
+            role: user.role,
             created_at: user.created_at,
             updated_at: user.updated_at,
         }
@@ -136,7 +136,10 @@ pub struct RefreshTokenInfo {
     pub id: Uuid,
     pub user_id: Uuid,
     pub organisation_id: Option<Uuid>,
+    pub family_id: Uuid,
     pub expires_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
 }
 
 impl RefreshTokenService {
@@ -144,7 +147,7 @@ impl RefreshTokenService {
         Self { pool }
     }
 
-    /// Store a new refresh token
+    /// Store a new refresh token as the start of a new family
     pub async fn create(
         &self,
         user_id: Uuid,
@@ -155,8 +158,8 @@ impl RefreshTokenService {
         let id = Uuid::new_v4();
         sqlx::query(
             r#"
-            INSERT INTO refresh_tokens (id, user_id, organisation_id, token_hash, expires_at)
-            VALUES ($1, $2, $3, $4, $5)
+            INSERT INTO refresh_tokens (id, user_id, organisation_id, family_id, token_hash, expires_at)
+            VALUES ($1, $2, $3, $1, $4, $5)
             "#,
         )
         .bind(id)
@@ -176,7 +179,7 @@ impl RefreshTokenService {
     ) -> anyhow::Result<Option<RefreshTokenInfo>> {
         let result: Option<RefreshTokenInfo> = sqlx::query_as(
             r#"
-            SELECT id, user_id, organisation_id, expires_at
+            SELECT id, user_id, organisation_id, family_id, expires_at, rotated_at, revoked_at
             FROM refresh_tokens
             WHERE token_hash = $1
             "#,
@@ -187,28 +190,93 @@ impl RefreshTokenService {
         Ok(result)
     }
 
-    /// Update refresh token (for rotation)
-    pub async fn update(
+    /// Rotate a refresh token: mark it superseded and store its successor in
+    /// the same family. Returns `None` if the token was already rotated or revoked.
+    pub async fn rotate(
         &self,
         token_id: Uuid,
         new_token_hash: &str,
         new_expires_at: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        sqlx::query(
+    ) -> anyhow::Result<Option<Uuid>> {
+        let new_id = Uuid::new_v4();
+        let mut tx = self.pool.begin().await?;
+
+        // The conditional update makes concurrent rotations of one token race
+        // for a single winner; the loser sees it as reuse
+        let rotated: Option<(Uuid, Option<Uuid>, Uuid)> = sqlx::query_as(
             r#"
             UPDATE refresh_tokens
-            SET token_hash = $1, expires_at = $2, updated_at = NOW()
-            WHERE id = $3
+            SET rotated_at = NOW(), replaced_by = $1
+            WHERE id = $2 AND rotated_at IS NULL AND revoked_at IS NULL
+            RETURNING user_id, organisation_id, family_id
+            "#,
+        )
+        .bind(new_id)
+        .bind(token_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some((user_id, organisation_id, family_id)) = rotated else {
+            return Ok(None);
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO refresh_tokens (id, user_id, organisation_id, family_id, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
             "#,
         )
+        .bind(new_id)
+        .bind(user_id)
+        .bind(organisation_id)
+        .bind(family_id)
         .bind(new_token_hash)
         .bind(new_expires_at)
-        .bind(token_id)
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await?;
+
+        tx.commit().await?;
+        Ok(Some(new_id))
+    }
+
+    /// Replace a token's stored hash if it still has `old_token_hash`
+    pub async fn rehash(
+        &self,
+        token_id: Uuid,
+        old_token_hash: &str,
+        new_token_hash: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE refresh_tokens SET token_hash = $1 WHERE id = $2 AND token_hash = $3")
+            .bind(new_token_hash)
+            .bind(token_id)
+            .bind(old_token_hash)
+            .execute(&self.pool)
+            .await?;
         Ok(())
     }
 
+    /// Revoke every token in a family; returns how many were still active
+    pub async fn revoke_family(&self, family_id: Uuid) -> anyhow::Result<u64> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = NOW() WHERE family_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(family_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Revoke every active token of a user; returns how many were revoked
+    pub async fn revoke_user(&self, user_id: Uuid) -> anyhow::Result<u64> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
     /// Delete refresh token by hash
     pub async fn delete_by_hash(&self, token_hash: &str) -> anyhow::Result<()> {
         sqlx::query("DELETE FROM refresh_tokens WHERE token_hash = $1")
@@ -227,3 +295,88 @@ impl RefreshTokenService {
         Ok(())
     }
 }
+
+/// These run against the database in `DATABASE_URL`:
+/// `cargo test -p admin_core -- --ignored`
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn services() -> (RefreshTokenService, Uuid) {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
+        let pool = crate::db::create_pool(&url).await.unwrap();
+        crate::db::migrate(&pool).await.unwrap();
+        let email = format!("{}@example.com", Uuid::new_v4());
+        let user = UserService::new(pool.clone())
+            .create(&email, "Refresh Test", "", None, Role::User)
+            .await
+            .unwrap();
+        (RefreshTokenService::new(pool), user.id)
+    }
+
+    fn expires() -> DateTime<Utc> {
+        Utc::now() + chrono::Duration::days(7)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn rotate_keeps_the_family_and_only_wins_once() {
+        let (tokens, user_id) = services().await;
+        let hash = Uuid::new_v4().to_string();
+        let first = tokens.create(user_id, None, &hash, expires()).await.unwrap();
+
+        let second = tokens
+            .rotate(first, &Uuid::new_v4().to_string(), expires())
+            .await
+            .unwrap()
+            .expect("first rotation wins");
+        // Replaying, or losing a race to, the first rotation gets nothing
+        let replay = tokens.rotate(first, &Uuid::new_v4().to_string(), expires()).await;
+        assert!(replay.unwrap().is_none());
+
+        let rotated = tokens.find_by_hash(&hash).await.unwrap().unwrap();
+        assert!(rotated.rotated_at.is_some());
+        let revoked = tokens.revoke_family(rotated.family_id).await.unwrap();
+        // The rotated token and its successor
+        assert_eq!(revoked, 2);
+        assert_eq!(tokens.revoke_family(rotated.family_id).await.unwrap(), 0);
+
+        // A revoked token can't be rotated either
+        let rotate_revoked = tokens.rotate(second, &Uuid::new_v4().to_string(), expires()).await;
+        assert!(rotate_revoked.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn concurrent_rotations_have_one_winner() {
+        let (tokens, user_id) = services().await;
+        let token = tokens
+            .create(user_id, None, &Uuid::new_v4().to_string(), expires())
+            .await
+            .unwrap();
+
+        let (hash_a, hash_b) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+        let (a, b) = tokio::join!(
+            tokens.rotate(token, &hash_a, expires()),
+            tokens.rotate(token, &hash_b, expires()),
+        );
+        let winners = [a.unwrap(), b.unwrap()].iter().filter(|r| r.is_some()).count();
+        assert_eq!(winners, 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn revoke_family_leaves_other_families_alone() {
+        let (tokens, user_id) = services().await;
+        let mine = tokens
+            .create(user_id, None, &Uuid::new_v4().to_string(), expires())
+            .await
+            .unwrap();
+        let other_hash = Uuid::new_v4().to_string();
+        tokens.create(user_id, None, &other_hash, expires()).await.unwrap();
+
+        assert_eq!(tokens.revoke_family(mine).await.unwrap(), 1);
+        let other = tokens.find_by_hash(&other_hash).await.unwrap().unwrap();
+        assert!(other.revoked_at.is_none());
+    }
+}
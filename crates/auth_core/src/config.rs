@@ -1,16 +1,30 @@
+/// Fallback pepper for development setups
+pub const DEFAULT_REFRESH_TOKEN_PEPPER: &str = "change-me-in-production-refresh-token-pepper";
+
 #[derive(Debug, Clone)]
 pub struct AuthConfig {
     pub listen_addr: String,
-    pub jwt_secret: String,
+    /// Algorithm of generated keys: `EdDSA` (default) or `RS256` (keys must
+    /// then be supplied as PEM)
+    pub signing_algorithm: String,
+    /// PEM private key used to sign access tokens (seeds an empty keyring)
+    pub signing_key_path: Option<String>,
+    /// Directory where the signing keyring is persisted
+    pub keyring_dir: Option<String>,
     pub issuer: String,
     /// Token validity duration in seconds (default: 300 = 5 minutes)
     pub token_ttl_seconds: u64,
+    /// Server secret keyed into refresh token hashes; changing it invalidates
+    /// every stored refresh token
+    pub refresh_token_pepper: String,
     /// Default admin email (only works when no users exist)
     pub default_admin_email: Option<String>,
     /// Default admin password (only works when no users exist)
     pub default_admin_password: Option<String>,
     /// Admin service base URL for internal API calls
     pub admin_service_url: String,
+    /// Shared secret for signing requests to the admin internal API
+    pub admin_service_secret: String,
 }
 
 impl Default for AuthConfig {
@@ -18,18 +32,37 @@ impl Default for AuthConfig {
         Self {
             listen_addr: std::env::var("AUTH_LISTEN_ADDR")
                 .unwrap_or_else(|_| "0.0.0.0:4002".to_string()),
-            jwt_secret: std::env::var("AUTH_JWT_SECRET")
-                .unwrap_or_else(|_| "change-me-in-production-super-secret-key".to_string()),
+            signing_algorithm: std::env::var("AUTH_SIGNING_ALGORITHM")
+                .unwrap_or_else(|_| "EdDSA".to_string()),
+            signing_key_path: std::env::var("AUTH_SIGNING_KEY_PATH").ok(),
+            keyring_dir: std::env::var("AUTH_KEYRING_DIR").ok(),
             issuer: std::env::var("AUTH_ISSUER")
                 .unwrap_or_else(|_| "apisentinel".to_string()),
             token_ttl_seconds: std::env::var("AUTH_TOKEN_TTL_SECONDS")
                 .ok()
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(300), // 5 minutes
+            refresh_token_pepper: std::env::var("AUTH_REFRESH_TOKEN_PEPPER")
+                .unwrap_or_else(|_| DEFAULT_REFRESH_TOKEN_PEPPER.to_string()),
             default_admin_email: std::env::var("AUTH_DEFAULT_ADMIN_EMAIL").ok(),
             default_admin_password: std::env::var("AUTH_DEFAULT_ADMIN_PASSWORD").ok(),
             admin_service_url: std::env::var("ADMIN_SERVICE_URL")
                 .unwrap_or_else(|_| "http://localhost:4001".to_string()),
+            admin_service_secret: std::env::var("ADMIN_SERVICE_SECRET")
+                .unwrap_or_else(|_| common::service_auth::DEFAULT_SERVICE_SECRET.to_string()),
         }
     }
 }
+
+impl AuthConfig {
+    /// Refuse to call the admin internal API with the public development
+    /// secret outside development mode. Only standalone auth signs requests.
+    pub fn validate_service_secret(&self) -> anyhow::Result<()> {
+        common::secret::check(
+            "ADMIN_SERVICE_SECRET",
+            &self.admin_service_secret,
+            common::service_auth::DEFAULT_SERVICE_SECRET,
+        )
+        .map_err(anyhow::Error::msg)
+    }
+}
@@ -2,22 +2,27 @@ use std::sync::Arc;
 
 use axum::{
     Json,
-    extract::State,
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
 };
 use chrono::{Duration, Utc};
 use uuid::Uuid;
 
-use contracts::{RefreshTokenServiceContract, Role, UserServiceContract, UserWithPassword};
+use contracts::{
+    ContractResult, RefreshTokenInfo, RefreshTokenServiceContract, Role, UserServiceContract,
+    UserWithPassword,
+};
 
 use crate::config::AuthConfig;
+use crate::keys::Keyring;
 use crate::models::{
-    AuthResponse, AuthUserInfo, ErrorResponse, LoginRequest, RefreshRequest, ValidateResponse,
+    AuthResponse, AuthUserInfo, ErrorResponse, LoginRequest, RefreshRequest, RotateKeyRequest,
+    ValidateResponse,
 };
 use crate::token::{
     generate_access_token, generate_refresh_token, hash_password, hash_refresh_token,
-    validate_access_token, verify_password,
+    legacy_refresh_token_hash, validate_access_token, verify_password,
 };
 
 /// Shared application state using trait objects for flexibility
@@ -26,6 +31,7 @@ pub struct AppState {
     pub user_service: Arc<dyn UserServiceContract>,
     pub token_service: Arc<dyn RefreshTokenServiceContract>,
     pub config: Arc<AuthConfig>,
+    pub keyring: Arc<Keyring>,
 }
 
 /// POST /auth/login - Authenticate user with email and password
@@ -97,7 +103,7 @@ pub async fn login(
     };
 
     // Generate tokens
-    let access_token = match generate_access_token(&user, &state.config) {
+    let access_token = match generate_access_token(&user, &state.config, &state.keyring) {
         Ok(token) => token,
         Err(err) => {
             return (
@@ -112,7 +118,7 @@ pub async fn login(
     };
 
     let refresh_token = generate_refresh_token();
-    let refresh_token_hash = hash_refresh_token(&refresh_token);
+    let refresh_token_hash = hash_refresh_token(&refresh_token, &state.config.refresh_token_pepper);
 
     // Store refresh token via contract (only for real users, not default admin)
     if user.id != Uuid::nil() {
@@ -141,12 +147,8 @@ pub async fn refresh(
     State(state): State<AppState>,
     Json(payload): Json<RefreshRequest>,
 ) -> impl IntoResponse {
-    let token_hash = hash_refresh_token(&payload.refresh_token);
-
     // Find the refresh token via contract
-    let result = state
-        .token_service
-        .find_by_hash(&token_hash)
+    let result = find_refresh_token(&state, &payload.refresh_token)
         .await
         .unwrap_or(None);
 
@@ -161,6 +163,22 @@ pub async fn refresh(
             .into_response();
     };
 
+    // A revoked family stays dead; replaying it again is not news
+    if token_info.revoked_at.is_some() {
+        return error_response(
+            StatusCode::UNAUTHORIZED,
+            "invalid_token",
+            "Invalid or expired refresh token",
+        )
+        .into_response();
+    }
+
+    // A superseded token means it was copied: whoever holds the newer one
+    // may be the attacker, so the whole family goes
+    if token_info.rotated_at.is_some() {
+        return token_reused(&state, &token_info).await.into_response();
+    }
+
     // Check if token is expired
     if token_info.expires_at < Utc::now() {
         // Delete expired token
@@ -195,7 +213,7 @@ pub async fn refresh(
     };
 
     // Generate new access token
-    let access_token = match generate_access_token(&user, &state.config) {
+    let access_token = match generate_access_token(&user, &state.config, &state.keyring) {
         Ok(token) => token,
         Err(err) => {
             return (
@@ -211,14 +229,27 @@ pub async fn refresh(
 
     // Generate new refresh token (rotate)
     let new_refresh_token = generate_refresh_token();
-    let new_refresh_token_hash = hash_refresh_token(&new_refresh_token);
+    let new_refresh_token_hash = hash_refresh_token(&new_refresh_token, &state.config.refresh_token_pepper);
     let new_expires_at = Utc::now() + Duration::days(7);
 
-    // Update refresh token via contract
-    let _ = state
+    // Rotate within the family; losing a race to a concurrent refresh with
+    // the same token counts as reuse too
+    match state
         .token_service
-        .update(token_info.id, &new_refresh_token_hash, new_expires_at)
-        .await;
+        .rotate(token_info.id, &new_refresh_token_hash, new_expires_at)
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return token_reused(&state, &token_info).await.into_response(),
+        Err(err) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "token_error",
+                format!("Failed to rotate refresh token: {}", err),
+            )
+            .into_response();
+        }
+    }
 
     (
         StatusCode::OK,
@@ -233,6 +264,56 @@ pub async fn refresh(
         .into_response()
 }
 
+/// Look up a presented refresh token. Rows still stored under the legacy
+/// hash are upgraded to the current format when they are found; a failed
+/// upgrade is retried on the next use rather than refusing the token.
+async fn find_refresh_token(
+    state: &AppState,
+    refresh_token: &str,
+) -> ContractResult<Option<RefreshTokenInfo>> {
+    let token_hash = hash_refresh_token(refresh_token, &state.config.refresh_token_pepper);
+    if let Some(token_info) = state.token_service.find_by_hash(&token_hash).await? {
+        return Ok(Some(token_info));
+    }
+
+    let legacy_hash = legacy_refresh_token_hash(refresh_token);
+    let token_info = state.token_service.find_by_hash(&legacy_hash).await?;
+    if let Some(token_info) = &token_info
+        && let Err(err) = state
+            .token_service
+            .rehash(token_info.id, &legacy_hash, &token_hash)
+            .await
+    {
+        eprintln!("failed to upgrade refresh token {} hash: {}", token_info.id, err);
+    }
+    Ok(token_info)
+}
+
+/// Revoke the family of a replayed refresh token and report it
+async fn token_reused(state: &AppState, token_info: &RefreshTokenInfo) -> ErrorReply {
+    let revoked = match state.token_service.revoke_family(token_info.family_id).await {
+        Ok(revoked) => revoked.to_string(),
+        Err(err) => {
+            eprintln!("failed to revoke refresh token family {}: {}", token_info.family_id, err);
+            "failed".to_string()
+        }
+    };
+    observability::security_event(
+        "refresh_token_reuse",
+        &[
+            ("user_id", token_info.user_id.to_string()),
+            ("family_id", token_info.family_id.to_string()),
+            ("token_id", token_info.id.to_string()),
+            ("revoked", revoked),
+        ],
+    );
+    error_response(
+        StatusCode::UNAUTHORIZED,
+        "token_reused",
+        "Refresh token was already used; sign in again",
+    )
+}
+
 /// GET /auth/validate - Validate an access token (for internal service use)
 pub async fn validate(
     State(state): State<AppState>,
@@ -243,8 +324,8 @@ pub async fn validate(
         .and_then(|v| v.to_str().ok())
         .unwrap_or("");
 
-    let token = if auth_header.starts_with("Bearer ") {
-        &auth_header[7..]
+    let token = if let Some(token) = auth_header.strip_prefix("Bearer ") {
+        token
     } else {
         return (
             StatusCode::OK,
@@ -257,7 +338,7 @@ pub async fn validate(
         );
     };
 
-    match validate_access_token(token, &state.config) {
+    match validate_access_token(token, &state.config, &state.keyring) {
         Ok(claims) => (
             StatusCode::OK,
             Json(ValidateResponse {
@@ -284,8 +365,10 @@ pub async fn logout(
     State(state): State<AppState>,
     Json(payload): Json<RefreshRequest>,
 ) -> impl IntoResponse {
-    let token_hash = hash_refresh_token(&payload.refresh_token);
-    let _ = state.token_service.delete_by_hash(&token_hash).await;
+    // Logging out ends the whole session, including any copies of the token
+    if let Ok(Some(token_info)) = find_refresh_token(&state, &payload.refresh_token).await {
+        let _ = state.token_service.revoke_family(token_info.family_id).await;
+    }
     StatusCode::NO_CONTENT
 }
 
@@ -325,7 +408,7 @@ pub async fn register(
     match result {
         Ok(user) => {
             // Generate tokens
-            let access_token = match generate_access_token(&user, &state.config) {
+            let access_token = match generate_access_token(&user, &state.config, &state.keyring) {
                 Ok(token) => token,
                 Err(err) => {
                     return (
@@ -340,7 +423,7 @@ pub async fn register(
             };
 
             let refresh_token = generate_refresh_token();
-            let refresh_token_hash = hash_refresh_token(&refresh_token);
+            let refresh_token_hash = hash_refresh_token(&refresh_token, &state.config.refresh_token_pepper);
             let expires_at = Utc::now() + Duration::days(7);
 
             let _ = state
@@ -377,3 +460,451 @@ pub async fn register(
         }
     }
 }
+
+/// GET /auth/.well-known/jwks.json - Public keys for verifying access tokens
+pub async fn jwks(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(state.keyring.jwks()),
+    )
+}
+
+// ============================================================================
+// Signing Key Management
+// ============================================================================
+
+type ErrorReply = (StatusCode, Json<ErrorResponse>);
+
+fn error_response(status: StatusCode, error: &str, message: impl Into<String>) -> ErrorReply {
+    (
+        status,
+        Json(ErrorResponse {
+            error: error.to_string(),
+            message: message.into(),
+        }),
+    )
+}
+
+/// Key management is limited to super admins holding a valid access token
+fn require_super_admin(state: &AppState, headers: &HeaderMap) -> Result<(), ErrorReply> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or_else(|| {
+            error_response(StatusCode::UNAUTHORIZED, "unauthorized", "Missing bearer token")
+        })?;
+
+    let claims = validate_access_token(token, &state.config, &state.keyring).map_err(|_| {
+        error_response(StatusCode::UNAUTHORIZED, "unauthorized", "Invalid access token")
+    })?;
+
+    if Role::parse(&claims.role).unwrap_or_default() != Role::SuperAdmin {
+        return Err(error_response(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "Super admin role required",
+        ));
+    }
+    Ok(())
+}
+
+/// GET /auth/keys - List active and retired signing keys
+pub async fn list_keys(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(res) = require_super_admin(&state, &headers) {
+        return res.into_response();
+    }
+    Json(state.keyring.list()).into_response()
+}
+
+/// POST /auth/keys/rotate - Start signing with a new key
+pub async fn rotate_keys(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    payload: Option<Json<RotateKeyRequest>>,
+) -> Response {
+    if let Err(res) = require_super_admin(&state, &headers) {
+        return res.into_response();
+    }
+
+    let pem = payload.and_then(|Json(p)| p.private_key_pem);
+    match state.keyring.rotate(pem.as_deref().map(str::as_bytes)) {
+        Ok(key) => (StatusCode::CREATED, Json(key)).into_response(),
+        Err(err) => {
+            error_response(StatusCode::BAD_REQUEST, "rotation_failed", format!("{err:#}"))
+                .into_response()
+        }
+    }
+}
+
+/// POST /auth/keys/{kid}/retire - Stop accepting tokens signed with a key
+pub async fn retire_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(kid): Path<String>,
+) -> Response {
+    if let Err(res) = require_super_admin(&state, &headers) {
+        return res.into_response();
+    }
+
+    match state.keyring.retire(&kid) {
+        Ok(Some(key)) => Json(key).into_response(),
+        Ok(None) => {
+            error_response(StatusCode::NOT_FOUND, "not_found", "Unknown key").into_response()
+        }
+        Err(err) => {
+            error_response(StatusCode::CONFLICT, "retire_failed", format!("{err:#}"))
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use async_trait::async_trait;
+    use chrono::DateTime;
+
+    use super::*;
+
+    struct StoredToken {
+        info: RefreshTokenInfo,
+        hash: String,
+    }
+
+    /// Refresh token store with the same rotation rules as the database one
+    #[derive(Default)]
+    struct Tokens {
+        rows: Mutex<Vec<StoredToken>>,
+        /// Make the next rotation lose to a concurrent one
+        lose_race: AtomicBool,
+        /// Fail hash upgrades
+        fail_rehash: AtomicBool,
+    }
+
+    impl Tokens {
+        fn insert(&self, user_id: Uuid, family_id: Option<Uuid>, hash: &str) -> Uuid {
+            let id = Uuid::new_v4();
+            self.rows.lock().unwrap().push(StoredToken {
+                info: RefreshTokenInfo {
+                    id,
+                    user_id,
+                    organisation_id: None,
+                    family_id: family_id.unwrap_or(id),
+                    expires_at: Utc::now() + Duration::days(7),
+                    rotated_at: None,
+                    revoked_at: None,
+                },
+                hash: hash.to_string(),
+            });
+            id
+        }
+
+        fn family_revoked(&self, family_id: Uuid) -> bool {
+            self.rows
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|row| row.info.family_id == family_id)
+                .all(|row| row.info.revoked_at.is_some())
+        }
+    }
+
+    #[async_trait]
+    impl RefreshTokenServiceContract for Tokens {
+        async fn create(
+            &self,
+            user_id: Uuid,
+            _organisation_id: Option<Uuid>,
+            token_hash: &str,
+            _expires_at: DateTime<Utc>,
+        ) -> ContractResult<Uuid> {
+            Ok(self.insert(user_id, None, token_hash))
+        }
+
+        async fn find_by_hash(&self, token_hash: &str) -> ContractResult<Option<RefreshTokenInfo>> {
+            let rows = self.rows.lock().unwrap();
+            Ok(rows.iter().find(|row| row.hash == token_hash).map(|row| row.info.clone()))
+        }
+
+        async fn rotate(
+            &self,
+            token_id: Uuid,
+            new_token_hash: &str,
+            _new_expires_at: DateTime<Utc>,
+        ) -> ContractResult<Option<Uuid>> {
+            let (user_id, family_id) = {
+                let mut rows = self.rows.lock().unwrap();
+                let row = rows.iter_mut().find(|row| row.info.id == token_id).unwrap();
+                if self.lose_race.swap(false, Ordering::SeqCst) {
+                    // Another refresh with the same token got there first
+                    row.info.rotated_at = Some(Utc::now());
+                }
+                if row.info.rotated_at.is_some() || row.info.revoked_at.is_some() {
+                    return Ok(None);
+                }
+                row.info.rotated_at = Some(Utc::now());
+                (row.info.user_id, row.info.family_id)
+            };
+            Ok(Some(self.insert(user_id, Some(family_id), new_token_hash)))
+        }
+
+        async fn rehash(
+            &self,
+            token_id: Uuid,
+            old_token_hash: &str,
+            new_token_hash: &str,
+        ) -> ContractResult<()> {
+            if self.fail_rehash.load(Ordering::SeqCst) {
+                return Err(contracts::ContractError::Internal("rehash failed".to_string()));
+            }
+            let mut rows = self.rows.lock().unwrap();
+            if let Some(row) = rows
+                .iter_mut()
+                .find(|row| row.info.id == token_id && row.hash == old_token_hash)
+            {
+                row.hash = new_token_hash.to_string();
+            }
+            Ok(())
+        }
+
+        async fn revoke_family(&self, family_id: Uuid) -> ContractResult<u64> {
+            let mut revoked = 0;
+            for row in self.rows.lock().unwrap().iter_mut() {
+                if row.info.family_id == family_id && row.info.revoked_at.is_none() {
+                    row.info.revoked_at = Some(Utc::now());
+                    revoked += 1;
+                }
+            }
+            Ok(revoked)
+        }
+
+        async fn delete_by_hash(&self, token_hash: &str) -> ContractResult<()> {
+            self.rows.lock().unwrap().retain(|row| row.hash != token_hash);
+            Ok(())
+        }
+
+        async fn delete(&self, token_id: Uuid) -> ContractResult<()> {
+            self.rows.lock().unwrap().retain(|row| row.info.id != token_id);
+            Ok(())
+        }
+    }
+
+    /// A single user every token belongs to
+    struct Users(UserWithPassword);
+
+    #[async_trait]
+    impl UserServiceContract for Users {
+        async fn count(&self) -> ContractResult<i64> {
+            Ok(1)
+        }
+
+        async fn find_by_email(&self, email: &str) -> ContractResult<Option<UserWithPassword>> {
+            Ok((self.0.email == email).then(|| self.0.clone()))
+        }
+
+        async fn find_by_id(&self, id: Uuid) -> ContractResult<Option<UserWithPassword>> {
+            Ok((self.0.id == id).then(|| self.0.clone()))
+        }
+
+        async fn create(
+            &self,
+            _email: &str,
+            _name: &str,
+            _password_hash: &str,
+            _organisation_id: Option<Uuid>,
+            _role: Role,
+        ) -> ContractResult<UserWithPassword> {
+            Err(contracts::ContractError::AlreadyExists)
+        }
+
+        async fn update_password(&self, _user_id: Uuid, _password_hash: &str) -> ContractResult<()> {
+            Ok(())
+        }
+    }
+
+    struct Harness {
+        state: AppState,
+        tokens: Arc<Tokens>,
+        user_id: Uuid,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let config = AuthConfig {
+                keyring_dir: None,
+                signing_key_path: None,
+                refresh_token_pepper: "test-pepper".to_string(),
+                default_admin_email: None,
+                default_admin_password: None,
+                ..AuthConfig::default()
+            };
+            let keyring = Arc::new(Keyring::load(&config).unwrap());
+            let user = UserWithPassword {
+                id: Uuid::new_v4(),
+                organisation_id: None,
+                email: "user@example.com".to_string(),
+                name: "User".to_string(),
+                password_hash: None,
+                role: Role::User,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            };
+            let user_id = user.id;
+            let tokens = Arc::new(Tokens::default());
+            Self {
+                state: AppState {
+                    user_service: Arc::new(Users(user)),
+                    token_service: tokens.clone(),
+                    config: Arc::new(config),
+                    keyring,
+                },
+                tokens,
+                user_id,
+            }
+        }
+
+        /// Store a fresh token as the start of a family; returns the token
+        /// and its family
+        fn login(&self) -> (String, Uuid) {
+            let token = generate_refresh_token();
+            let hash = hash_refresh_token(&token, &self.state.config.refresh_token_pepper);
+            let family_id = self.tokens.insert(self.user_id, None, &hash);
+            (token, family_id)
+        }
+
+        /// POST /auth/refresh; the status and the new token or error code
+        async fn refresh(&self, token: &str) -> (StatusCode, String) {
+            let response = refresh(
+                State(self.state.clone()),
+                Json(RefreshRequest {
+                    refresh_token: token.to_string(),
+                }),
+            )
+            .await
+            .into_response();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            let field = if status == StatusCode::OK {
+                "refresh_token"
+            } else {
+                "error"
+            };
+            (status, body[field].as_str().unwrap().to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn refresh_rotates_within_the_family() {
+        let harness = Harness::new();
+        let (token, family_id) = harness.login();
+
+        let (status, next) = harness.refresh(&token).await;
+        assert_eq!(status, StatusCode::OK);
+        let next_hash = hash_refresh_token(&next, &harness.state.config.refresh_token_pepper);
+        let next_info = harness.tokens.find_by_hash(&next_hash).await.unwrap().unwrap();
+        assert_eq!(next_info.family_id, family_id);
+        assert_eq!(harness.refresh(&next).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn replaying_a_rotated_token_revokes_the_family() {
+        let harness = Harness::new();
+        let (token, family_id) = harness.login();
+        let (_, next) = harness.refresh(&token).await;
+
+        assert_eq!(
+            harness.refresh(&token).await,
+            (StatusCode::UNAUTHORIZED, "token_reused".to_string())
+        );
+        assert!(harness.tokens.family_revoked(family_id));
+        // The successor the replay may have come from is dead too
+        assert_eq!(
+            harness.refresh(&next).await,
+            (StatusCode::UNAUTHORIZED, "invalid_token".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn losing_the_rotate_race_counts_as_reuse() {
+        let harness = Harness::new();
+        let (token, family_id) = harness.login();
+        harness.tokens.lose_race.store(true, Ordering::SeqCst);
+
+        assert_eq!(
+            harness.refresh(&token).await,
+            (StatusCode::UNAUTHORIZED, "token_reused".to_string())
+        );
+        assert!(harness.tokens.family_revoked(family_id));
+    }
+
+    #[tokio::test]
+    async fn revoked_family_stays_dead() {
+        let harness = Harness::new();
+        let (token, family_id) = harness.login();
+        let (_, next) = harness.refresh(&token).await;
+        harness.refresh(&token).await;
+        assert!(harness.tokens.family_revoked(family_id));
+
+        // Neither token works again, and replays are no longer reported as reuse
+        for token in [&token, &next] {
+            assert_eq!(
+                harness.refresh(token).await,
+                (StatusCode::UNAUTHORIZED, "invalid_token".to_string())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn logout_revokes_the_family() {
+        let harness = Harness::new();
+        let (token, family_id) = harness.login();
+        let (_, next) = harness.refresh(&token).await;
+
+        let status = logout(
+            State(harness.state.clone()),
+            Json(RefreshRequest {
+                refresh_token: next.clone(),
+            }),
+        )
+        .await
+        .into_response()
+        .status();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(harness.tokens.family_revoked(family_id));
+        assert_eq!(
+            harness.refresh(&next).await,
+            (StatusCode::UNAUTHORIZED, "invalid_token".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn legacy_tokens_are_upgraded() {
+        let harness = Harness::new();
+        let token = generate_refresh_token();
+        let legacy_hash = legacy_refresh_token_hash(&token);
+        harness.tokens.insert(harness.user_id, None, &legacy_hash);
+
+        let found = find_refresh_token(&harness.state, &token).await.unwrap();
+        assert!(found.is_some());
+        let hash = hash_refresh_token(&token, &harness.state.config.refresh_token_pepper);
+        assert!(harness.tokens.find_by_hash(&hash).await.unwrap().is_some());
+        assert!(harness.tokens.find_by_hash(&legacy_hash).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_upgrade_keeps_the_legacy_token_valid() {
+        let harness = Harness::new();
+        let token = generate_refresh_token();
+        let legacy_hash = legacy_refresh_token_hash(&token);
+        harness.tokens.insert(harness.user_id, None, &legacy_hash);
+        harness.tokens.fail_rehash.store(true, Ordering::SeqCst);
+
+        assert_eq!(harness.refresh(&token).await.0, StatusCode::OK);
+    }
+}
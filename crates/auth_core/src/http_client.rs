@@ -8,11 +8,29 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use common::service_auth::{self, ServiceSigner};
 use contracts::{
     ContractError, ContractResult, RefreshTokenInfo, RefreshTokenServiceContract, Role,
     UserServiceContract, UserWithPassword,
 };
 
+/// Name this service signs internal API requests with
+const SERVICE_NAME: &str = "auth";
+
+/// Sends requests signed for the admin service's internal API
+trait SendSigned {
+    async fn send_signed(self, signer: &ServiceSigner) -> reqwest::Result<reqwest::Response>;
+}
+
+impl SendSigned for reqwest::RequestBuilder {
+    async fn send_signed(self, signer: &ServiceSigner) -> reqwest::Result<reqwest::Response> {
+        let (client, req) = self.build_split();
+        let mut req = req?;
+        service_auth::sign_request(signer, SERVICE_NAME, &mut req);
+        client.execute(req).await
+    }
+}
+
 // ============================================================================
 // HTTP User Service Implementation
 // ============================================================================
@@ -23,13 +41,15 @@ use contracts::{
 pub struct HttpUserService {
     base_url: String,
     client: reqwest::Client,
+    signer: ServiceSigner,
 }
 
 impl HttpUserService {
-    pub fn new(admin_base_url: &str) -> Self {
+    pub fn new(admin_base_url: &str, service_secret: &str) -> Self {
         Self {
             base_url: admin_base_url.trim_end_matches('/').to_string(),
             client: reqwest::Client::new(),
+            signer: ServiceSigner::new(service_secret),
         }
     }
 }
@@ -41,7 +61,7 @@ impl UserServiceContract for HttpUserService {
         let resp = self
             .client
             .get(&url)
-            .send()
+            .send_signed(&self.signer)
             .await
             .map_err(|e| ContractError::Connection(e.to_string()))?;
 
@@ -73,7 +93,7 @@ impl UserServiceContract for HttpUserService {
         let resp = self
             .client
             .get(&url)
-            .send()
+            .send_signed(&self.signer)
             .await
             .map_err(|e| ContractError::Connection(e.to_string()))?;
 
@@ -100,7 +120,7 @@ impl UserServiceContract for HttpUserService {
         let resp = self
             .client
             .get(&url)
-            .send()
+            .send_signed(&self.signer)
             .await
             .map_err(|e| ContractError::Connection(e.to_string()))?;
 
@@ -151,7 +171,7 @@ impl UserServiceContract for HttpUserService {
                 organisation_id,
                 role,
             })
-            .send()
+            .send_signed(&self.signer)
             .await
             .map_err(|e| ContractError::Connection(e.to_string()))?;
 
@@ -185,7 +205,7 @@ impl UserServiceContract for HttpUserService {
             .client
             .put(&url)
             .json(&UpdatePasswordRequest { password_hash })
-            .send()
+            .send_signed(&self.signer)
             .await
             .map_err(|e| ContractError::Connection(e.to_string()))?;
 
@@ -210,13 +230,15 @@ impl UserServiceContract for HttpUserService {
 pub struct HttpRefreshTokenService {
     base_url: String,
     client: reqwest::Client,
+    signer: ServiceSigner,
 }
 
 impl HttpRefreshTokenService {
-    pub fn new(admin_base_url: &str) -> Self {
+    pub fn new(admin_base_url: &str, service_secret: &str) -> Self {
         Self {
             base_url: admin_base_url.trim_end_matches('/').to_string(),
             client: reqwest::Client::new(),
+            signer: ServiceSigner::new(service_secret),
         }
     }
 }
@@ -249,7 +271,7 @@ impl RefreshTokenServiceContract for HttpRefreshTokenService {
                 token_hash,
                 expires_at,
             })
-            .send()
+            .send_signed(&self.signer)
             .await
             .map_err(|e| ContractError::Connection(e.to_string()))?;
 
@@ -281,7 +303,7 @@ impl RefreshTokenServiceContract for HttpRefreshTokenService {
         let resp = self
             .client
             .get(&url)
-            .send()
+            .send_signed(&self.signer)
             .await
             .map_err(|e| ContractError::Connection(e.to_string()))?;
 
@@ -303,34 +325,83 @@ impl RefreshTokenServiceContract for HttpRefreshTokenService {
         Ok(Some(info))
     }
 
-    async fn update(
+    async fn rotate(
         &self,
         token_id: Uuid,
         new_token_hash: &str,
         new_expires_at: DateTime<Utc>,
-    ) -> ContractResult<()> {
-        let url = format!("{}/internal/refresh-tokens/{}", self.base_url, token_id);
+    ) -> ContractResult<Option<Uuid>> {
+        let url = format!("{}/internal/refresh-tokens/{}/rotate", self.base_url, token_id);
 
         #[derive(Serialize)]
-        struct UpdateTokenRequest<'a> {
+        struct RotateTokenRequest<'a> {
             token_hash: &'a str,
             expires_at: DateTime<Utc>,
         }
 
         let resp = self
             .client
-            .put(&url)
-            .json(&UpdateTokenRequest {
+            .post(&url)
+            .json(&RotateTokenRequest {
                 token_hash: new_token_hash,
                 expires_at: new_expires_at,
             })
-            .send()
+            .send_signed(&self.signer)
             .await
             .map_err(|e| ContractError::Connection(e.to_string()))?;
 
+        // Already rotated or revoked
+        if resp.status() == reqwest::StatusCode::CONFLICT {
+            return Ok(None);
+        }
+
         if !resp.status().is_success() {
             return Err(ContractError::Internal(format!(
-                "Failed to update refresh token: {}",
+                "Failed to rotate refresh token: {}",
+                resp.status()
+            )));
+        }
+
+        #[derive(Deserialize)]
+        struct RotateTokenResponse {
+            id: Uuid,
+        }
+
+        let data: RotateTokenResponse = resp
+            .json()
+            .await
+            .map_err(|e| ContractError::Internal(e.to_string()))?;
+        Ok(Some(data.id))
+    }
+
+    async fn rehash(
+        &self,
+        token_id: Uuid,
+        old_token_hash: &str,
+        new_token_hash: &str,
+    ) -> ContractResult<()> {
+        let url = format!("{}/internal/refresh-tokens/{}/hash", self.base_url, token_id);
+
+        #[derive(Serialize)]
+        struct RehashTokenRequest<'a> {
+            old_token_hash: &'a str,
+            token_hash: &'a str,
+        }
+
+        let resp = self
+            .client
+            .put(&url)
+            .json(&RehashTokenRequest {
+                old_token_hash,
+                token_hash: new_token_hash,
+            })
+            .send_signed(&self.signer)
+            .await
+            .map_err(|e| ContractError::Connection(e.to_string()))?;
+
+        if !resp.status().is_success() {
+            return Err(ContractError::Internal(format!(
+                "Failed to rehash refresh token: {}",
                 resp.status()
             )));
         }
@@ -338,6 +409,37 @@ impl RefreshTokenServiceContract for HttpRefreshTokenService {
         Ok(())
     }
 
+    async fn revoke_family(&self, family_id: Uuid) -> ContractResult<u64> {
+        let url = format!(
+            "{}/internal/refresh-tokens/families/{}/revoke",
+            self.base_url, family_id
+        );
+        let resp = self
+            .client
+            .post(&url)
+            .send_signed(&self.signer)
+            .await
+            .map_err(|e| ContractError::Connection(e.to_string()))?;
+
+        if !resp.status().is_success() {
+            return Err(ContractError::Internal(format!(
+                "Failed to revoke refresh token family: {}",
+                resp.status()
+            )));
+        }
+
+        #[derive(Deserialize)]
+        struct RevokeFamilyResponse {
+            revoked: u64,
+        }
+
+        let data: RevokeFamilyResponse = resp
+            .json()
+            .await
+            .map_err(|e| ContractError::Internal(e.to_string()))?;
+        Ok(data.revoked)
+    }
+
     async fn delete_by_hash(&self, token_hash: &str) -> ContractResult<()> {
         let url = format!(
             "{}/internal/refresh-tokens/by-hash/{}",
@@ -347,7 +449,7 @@ impl RefreshTokenServiceContract for HttpRefreshTokenService {
         let resp = self
             .client
             .delete(&url)
-            .send()
+            .send_signed(&self.signer)
             .await
             .map_err(|e| ContractError::Connection(e.to_string()))?;
 
@@ -366,7 +468,7 @@ impl RefreshTokenServiceContract for HttpRefreshTokenService {
         let resp = self
             .client
             .delete(&url)
-            .send()
+            .send_signed(&self.signer)
             .await
             .map_err(|e| ContractError::Connection(e.to_string()))?;
 
@@ -0,0 +1,568 @@
+//! Access token signing keys.
+//!
+//! Tokens are signed with RSA (RS256) or Ed25519 (EdDSA) private keys that
+//! never leave the auth service. The public halves are published as a JWKS at
+//! `/auth/.well-known/jwks.json` so the gateway can verify tokens on its own.
+//!
+//! Keys live in a keyring: the newest active key signs, every active key
+//! verifies, and retired keys are dropped from the JWKS. Rotating adds a new
+//! key without invalidating tokens signed by the previous one; superseded keys
+//! retire on their own once every token they signed has expired.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use ring::rand::SystemRandom;
+use ring::rsa::PublicKeyComponents;
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+
+use crate::config::AuthConfig;
+use crate::models::Claims;
+
+/// Private key used to sign access tokens
+pub struct SigningKey {
+    algorithm: Algorithm,
+    kid: String,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Jwk,
+}
+
+impl SigningKey {
+    /// Load a PEM private key: PKCS#8 (Ed25519 or RSA) or PKCS#1 (RSA)
+    pub fn from_pem(pem: &[u8]) -> anyhow::Result<Self> {
+        let parsed = pem::parse(pem).map_err(|e| anyhow::anyhow!("not a PEM file: {e}"))?;
+        let der = parsed.contents();
+
+        match parsed.tag() {
+            "RSA PRIVATE KEY" => {
+                let key_pair =
+                    RsaKeyPair::from_der(der).map_err(|e| anyhow::anyhow!("invalid RSA key: {e}"))?;
+                Ok(Self::rsa(&key_pair, EncodingKey::from_rsa_pem(pem)?))
+            }
+            "PRIVATE KEY" => {
+                if let Ok(key_pair) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+                    return Ok(Self::ed25519(&key_pair, EncodingKey::from_ed_der(der)));
+                }
+                let key_pair = RsaKeyPair::from_pkcs8(der).map_err(|e| {
+                    anyhow::anyhow!("expected an Ed25519 or RSA private key: {e}")
+                })?;
+                Ok(Self::rsa(&key_pair, EncodingKey::from_rsa_pem(pem)?))
+            }
+            tag => anyhow::bail!("expected a private key, found {tag}"),
+        }
+    }
+
+    /// A new Ed25519 private key, PEM encoded (PKCS#8)
+    pub fn generate_ed25519_pem() -> anyhow::Result<String> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| anyhow::anyhow!("failed to generate signing key"))?;
+        Ok(pem::encode(&pem::Pem::new("PRIVATE KEY", pkcs8.as_ref())))
+    }
+
+    fn rsa(key_pair: &RsaKeyPair, encoding: EncodingKey) -> Self {
+        let public = PublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+        let n = BASE64_URL.encode(&public.n);
+        let e = BASE64_URL.encode(&public.e);
+        let kid = thumbprint(&format!(r#"{{"e":"{e}","kty":"RSA","n":"{n}"}}"#));
+        Self {
+            algorithm: Algorithm::RS256,
+            decoding: DecodingKey::from_rsa_raw_components(&public.n, &public.e),
+            encoding,
+            jwk: Jwk {
+                common: common_parameters(KeyAlgorithm::RS256, &kid),
+                algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n,
+                    e,
+                }),
+            },
+            kid,
+        }
+    }
+
+    fn ed25519(key_pair: &Ed25519KeyPair, encoding: EncodingKey) -> Self {
+        let public = key_pair.public_key().as_ref();
+        let x = BASE64_URL.encode(public);
+        let kid = thumbprint(&format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{x}"}}"#));
+        Self {
+            algorithm: Algorithm::EdDSA,
+            decoding: DecodingKey::from_ed_der(public),
+            encoding,
+            jwk: Jwk {
+                common: common_parameters(KeyAlgorithm::EdDSA, &kid),
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x,
+                }),
+            },
+            kid,
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Key id (RFC 7638 thumbprint), sent as `kid` in token headers
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn sign(&self, claims: &Claims) -> anyhow::Result<String> {
+        let mut header = Header::new(self.algorithm);
+        header.kid = Some(self.kid.clone());
+        jsonwebtoken::encode(&header, claims, &self.encoding)
+            .map_err(|e| anyhow::anyhow!("failed to encode token: {}", e))
+    }
+
+    pub fn verify(&self, token: &str, issuer: &str) -> anyhow::Result<Claims> {
+        let mut validation = Validation::new(self.algorithm);
+        validation.set_issuer(&[issuer]);
+        let token_data = jsonwebtoken::decode::<Claims>(token, &self.decoding, &validation)
+            .map_err(|e| anyhow::anyhow!("invalid token: {}", e))?;
+        Ok(token_data.claims)
+    }
+
+    pub fn jwk(&self) -> &Jwk {
+        &self.jwk
+    }
+}
+
+// ============================================================================
+// Keyring
+// ============================================================================
+
+/// Key status, as reported by the key management API
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyInfo {
+    pub kid: String,
+    /// Unknown for keys retired before the last restart
+    pub algorithm: Option<String>,
+    /// `active` or `retired`
+    pub status: &'static str,
+    /// Whether new tokens are signed with this key
+    pub signing: bool,
+    pub created_at: DateTime<Utc>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+struct Entry {
+    kid: String,
+    algorithm: Option<Algorithm>,
+    created_at: DateTime<Utc>,
+    retired_at: Option<DateTime<Utc>>,
+    /// `None` once retired
+    key: Option<Arc<SigningKey>>,
+}
+
+/// Active and retired signing keys
+pub struct Keyring {
+    /// Algorithm of keys generated on rotation
+    algorithm: Algorithm,
+    /// Superseded keys are retired after this long
+    token_ttl: chrono::Duration,
+    /// Where keys are persisted, if anywhere
+    dir: Option<PathBuf>,
+    /// Oldest first; the last active entry signs
+    entries: RwLock<Vec<Entry>>,
+}
+
+impl Keyring {
+    /// Load the keyring from `AUTH_KEYRING_DIR`, seeding it from
+    /// `AUTH_SIGNING_KEY_PATH` (or a generated key) when it is empty. Without
+    /// a directory the keyring only lives as long as the process.
+    pub fn load(config: &AuthConfig) -> anyhow::Result<Self> {
+        let keyring = Self {
+            algorithm: parse_algorithm(&config.signing_algorithm)?,
+            token_ttl: chrono::Duration::seconds(config.token_ttl_seconds as i64),
+            dir: config.keyring_dir.as_ref().map(PathBuf::from),
+            entries: RwLock::new(Vec::new()),
+        };
+
+        if let Some(dir) = &keyring.dir {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| anyhow::anyhow!("failed to create keyring {}: {e}", dir.display()))?;
+            *keyring.write() = load_dir(dir)?;
+        }
+
+        if keyring.signing_key().is_none() {
+            let pem = match &config.signing_key_path {
+                Some(path) => std::fs::read(path)
+                    .map_err(|e| anyhow::anyhow!("failed to read signing key {path}: {e}"))?,
+                None if keyring.dir.is_none() => {
+                    eprintln!(
+                        "auth: no AUTH_KEYRING_DIR or AUTH_SIGNING_KEY_PATH, using a generated key (tokens won't survive a restart)"
+                    );
+                    keyring.generate_pem()?.into_bytes()
+                }
+                None => keyring.generate_pem()?.into_bytes(),
+            };
+            keyring.add(&pem)?;
+        }
+        Ok(keyring)
+    }
+
+    /// The key new tokens are signed with
+    pub fn signing_key(&self) -> Option<Arc<SigningKey>> {
+        self.read().iter().rev().find_map(|entry| entry.key.clone())
+    }
+
+    pub fn sign(&self, claims: &Claims) -> anyhow::Result<String> {
+        let key = self
+            .signing_key()
+            .ok_or_else(|| anyhow::anyhow!("no active signing key"))?;
+        key.sign(claims)
+    }
+
+    /// Verify a token signed by any active key
+    pub fn verify(&self, token: &str, issuer: &str) -> anyhow::Result<Claims> {
+        self.retire_superseded();
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| anyhow::anyhow!("invalid token: {}", e))?;
+        let kid = header
+            .kid
+            .ok_or_else(|| anyhow::anyhow!("invalid token: missing kid"))?;
+        let key = self
+            .read()
+            .iter()
+            .find(|entry| entry.kid == kid)
+            .and_then(|entry| entry.key.clone())
+            .ok_or_else(|| anyhow::anyhow!("invalid token: unknown or retired key"))?;
+        key.verify(token, issuer)
+    }
+
+    /// Public keys for `/.well-known/jwks.json`
+    pub fn jwks(&self) -> JwkSet {
+        self.retire_superseded();
+        JwkSet {
+            keys: self
+                .read()
+                .iter()
+                .filter_map(|entry| entry.key.as_ref().map(|key| key.jwk().clone()))
+                .collect(),
+        }
+    }
+
+    pub fn list(&self) -> Vec<KeyInfo> {
+        self.retire_superseded();
+        let entries = self.read();
+        let signing = entries.iter().rev().find(|e| e.key.is_some()).map(|e| e.kid.clone());
+        entries
+            .iter()
+            .map(|entry| KeyInfo {
+                kid: entry.kid.clone(),
+                algorithm: entry.algorithm.map(|a| format!("{a:?}")),
+                status: if entry.key.is_some() { "active" } else { "retired" },
+                signing: signing.as_deref() == Some(entry.kid.as_str()),
+                created_at: entry.created_at,
+                retired_at: entry.retired_at,
+            })
+            .collect()
+    }
+
+    /// Add a new signing key: `pem` if given, otherwise a generated one.
+    /// Older keys keep verifying until their tokens have expired.
+    pub fn rotate(&self, pem: Option<&[u8]>) -> anyhow::Result<KeyInfo> {
+        let kid = match pem {
+            Some(pem) => self.add(pem)?,
+            None => self.add(self.generate_pem()?.as_bytes())?,
+        };
+        self.retire_superseded();
+        println!("auth: signing key rotated, now signing with kid {kid}");
+        self.list()
+            .into_iter()
+            .find(|info| info.kid == kid)
+            .ok_or_else(|| anyhow::anyhow!("rotated key disappeared"))
+    }
+
+    /// Stop accepting tokens signed with `kid`. The signing key can't be
+    /// retired; rotate first.
+    pub fn retire(&self, kid: &str) -> anyhow::Result<Option<KeyInfo>> {
+        let signing = self.signing_key().map(|key| key.kid().to_string());
+        if signing.as_deref() == Some(kid) {
+            anyhow::bail!("can't retire the signing key, rotate first");
+        }
+        {
+            let mut entries = self.write();
+            let Some(entry) = entries.iter_mut().find(|entry| entry.kid == kid) else {
+                return Ok(None);
+            };
+            self.retire_entry(entry)?;
+        }
+        Ok(self.list().into_iter().find(|info| info.kid == kid))
+    }
+
+    /// Retire keys superseded longer ago than the token TTL: every token
+    /// they signed has expired. Runs whenever keys are used or listed, so
+    /// nothing has to trigger it.
+    fn retire_superseded(&self) {
+        let now = Utc::now();
+        // Checked under the read lock first: this runs on every verification
+        if self.superseded(&self.read(), now).is_empty() {
+            return;
+        }
+        let mut entries = self.write();
+        for i in self.superseded(&entries, now) {
+            let entry = &mut entries[i];
+            if let Err(err) = self.retire_entry(entry) {
+                eprintln!("auth: failed to retire key {}: {err:#}", entry.kid);
+            }
+        }
+    }
+
+    /// Indices of active keys whose successor was added longer ago than the
+    /// token TTL
+    fn superseded(&self, entries: &[Entry], now: DateTime<Utc>) -> Vec<usize> {
+        let mut due = Vec::new();
+        let mut superseded_at: Option<DateTime<Utc>> = None;
+        for (i, entry) in entries.iter().enumerate().rev() {
+            if entry.key.is_none() {
+                continue;
+            }
+            if let Some(at) = superseded_at
+                && at + self.token_ttl < now
+            {
+                due.push(i);
+            }
+            superseded_at = Some(entry.created_at);
+        }
+        due
+    }
+
+    fn retire_entry(&self, entry: &mut Entry) -> anyhow::Result<()> {
+        if entry.key.is_none() {
+            return Ok(());
+        }
+        if let Some(dir) = &self.dir {
+            let path = key_path(dir, entry);
+            let mut retired = path.clone().into_os_string();
+            retired.push(".retired");
+            match std::fs::rename(&path, &retired) {
+                Ok(()) => {}
+                // Another instance sharing the directory retired it first
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && Path::new(&retired).exists() => {}
+                Err(e) => anyhow::bail!("failed to retire {}: {e}", path.display()),
+            }
+        }
+        entry.key = None;
+        entry.retired_at = Some(Utc::now());
+        println!("auth: signing key {} retired", entry.kid);
+        Ok(())
+    }
+
+    /// Parse, persist and append a key; returns its kid
+    fn add(&self, pem: &[u8]) -> anyhow::Result<String> {
+        let key = SigningKey::from_pem(pem)?;
+        let mut entries = self.write();
+        if entries.iter().any(|entry| entry.kid == key.kid()) {
+            anyhow::bail!("key {} is already in the keyring", key.kid());
+        }
+
+        // Whole seconds, strictly increasing: file names keep the signing order
+        let mut created_at = Utc
+            .timestamp_opt(Utc::now().timestamp(), 0)
+            .single()
+            .unwrap_or_default();
+        if let Some(last) = entries.last()
+            && created_at <= last.created_at
+        {
+            created_at = last.created_at + chrono::Duration::seconds(1);
+        }
+        let entry = Entry {
+            kid: key.kid().to_string(),
+            algorithm: Some(key.algorithm()),
+            created_at,
+            retired_at: None,
+            key: Some(Arc::new(key)),
+        };
+        if let Some(dir) = &self.dir {
+            write_private(&key_path(dir, &entry), pem)?;
+        }
+        let kid = entry.kid.clone();
+        entries.push(entry);
+        Ok(kid)
+    }
+
+    fn generate_pem(&self) -> anyhow::Result<String> {
+        match self.algorithm {
+            Algorithm::EdDSA => SigningKey::generate_ed25519_pem(),
+            other => anyhow::bail!("{other:?} keys can't be generated, provide a PEM private key"),
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Vec<Entry>> {
+        self.entries.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Vec<Entry>> {
+        self.entries.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// `<dir>/<created unix secs>-<kid>.pem`
+fn key_path(dir: &Path, entry: &Entry) -> PathBuf {
+    dir.join(format!("{}-{}.pem", entry.created_at.timestamp(), entry.kid))
+}
+
+/// Read every `*.pem` (active) and `*.pem.retired` file in the keyring directory
+fn load_dir(dir: &Path) -> anyhow::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let files = std::fs::read_dir(dir)
+        .map_err(|e| anyhow::anyhow!("failed to read keyring {}: {e}", dir.display()))?;
+    for file in files {
+        let path = file?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let (stem, retired) = match name.strip_suffix(".pem.retired") {
+            Some(stem) => (stem, true),
+            None => match name.strip_suffix(".pem") {
+                Some(stem) => (stem, false),
+                None => continue,
+            },
+        };
+        let Some((created_at, kid)) = stem
+            .split_once('-')
+            .and_then(|(ts, kid)| Some((Utc.timestamp_opt(ts.parse().ok()?, 0).single()?, kid)))
+        else {
+            eprintln!("auth: ignoring {} (expected <timestamp>-<kid>.pem)", path.display());
+            continue;
+        };
+
+        if retired {
+            let retired_at = std::fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .ok()
+                .map(DateTime::<Utc>::from);
+            entries.push(Entry {
+                kid: kid.to_string(),
+                algorithm: None,
+                created_at,
+                retired_at,
+                key: None,
+            });
+            continue;
+        }
+
+        let pem = std::fs::read(&path)?;
+        let key = SigningKey::from_pem(&pem)
+            .map_err(|e| anyhow::anyhow!("invalid signing key {}: {e}", path.display()))?;
+        if key.kid() != kid {
+            anyhow::bail!("{}: file name does not match key id {}", path.display(), key.kid());
+        }
+        entries.push(Entry {
+            kid: kid.to_string(),
+            algorithm: Some(key.algorithm()),
+            created_at,
+            retired_at: None,
+            key: Some(Arc::new(key)),
+        });
+    }
+    entries.sort_by_key(|entry| entry.created_at);
+    Ok(entries)
+}
+
+/// Write a private key readable only by the owner
+fn write_private(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("failed to write {}: {e}", path.display()))?;
+    file.write_all(contents)?;
+    Ok(())
+}
+
+fn parse_algorithm(name: &str) -> anyhow::Result<Algorithm> {
+    match name.to_ascii_uppercase().as_str() {
+        "RS256" => Ok(Algorithm::RS256),
+        "EDDSA" | "ED25519" => Ok(Algorithm::EdDSA),
+        _ => anyhow::bail!("unsupported AUTH_SIGNING_ALGORITHM '{name}' (expected RS256 or EdDSA)"),
+    }
+}
+
+fn common_parameters(algorithm: KeyAlgorithm, kid: &str) -> CommonParameters {
+    CommonParameters {
+        public_key_use: Some(PublicKeyUse::Signature),
+        key_algorithm: Some(algorithm),
+        key_id: Some(kid.to_string()),
+        ..Default::default()
+    }
+}
+
+/// RFC 7638 thumbprint of the canonical JWK members
+fn thumbprint(canonical: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, canonical.as_bytes());
+    BASE64_URL.encode(digest.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring(token_ttl: chrono::Duration) -> Keyring {
+        Keyring {
+            algorithm: Algorithm::EdDSA,
+            token_ttl,
+            dir: None,
+            entries: RwLock::new(Vec::new()),
+        }
+    }
+
+    fn claims() -> Claims {
+        let now = Utc::now().timestamp() as u64;
+        Claims {
+            sub: "user".to_string(),
+            iss: "test".to_string(),
+            exp: now + 600,
+            iat: now,
+            email: "user@example.com".to_string(),
+            name: "User".to_string(),
+            role: "USER".to_string(),
+            org_id: None,
+        }
+    }
+
+    #[test]
+    fn superseded_keys_retire_without_listing() {
+        // A negative TTL makes every superseded key overdue
+        let keyring = keyring(chrono::Duration::seconds(-1));
+        keyring.add(keyring.generate_pem().unwrap().as_bytes()).unwrap();
+        let old_token = keyring.sign(&claims()).unwrap();
+        keyring.add(keyring.generate_pem().unwrap().as_bytes()).unwrap();
+
+        assert!(keyring.verify(&old_token, "test").is_err());
+        assert_eq!(keyring.jwks().keys.len(), 1);
+        let new_token = keyring.sign(&claims()).unwrap();
+        assert!(keyring.verify(&new_token, "test").is_ok());
+    }
+
+    #[test]
+    fn superseded_keys_verify_until_the_ttl_has_passed() {
+        let keyring = keyring(chrono::Duration::hours(1));
+        keyring.add(keyring.generate_pem().unwrap().as_bytes()).unwrap();
+        let old_token = keyring.sign(&claims()).unwrap();
+        keyring.add(keyring.generate_pem().unwrap().as_bytes()).unwrap();
+
+        assert!(keyring.verify(&old_token, "test").is_ok());
+        assert_eq!(keyring.jwks().keys.len(), 2);
+    }
+}
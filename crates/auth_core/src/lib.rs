@@ -1,6 +1,7 @@
 pub mod config;
 pub mod handlers;
 pub mod http_client;
+pub mod keys;
 pub mod models;
 pub mod server;
 pub mod service;
@@ -13,6 +14,7 @@ pub use http_client::{HttpRefreshTokenService, HttpUserService};
 pub use contracts::{RefreshTokenServiceContract, UserServiceContract, UserWithPassword};
 
 pub use config::AuthConfig;
+pub use keys::{Keyring, SigningKey};
 
 pub async fn run() -> anyhow::Result<()> {
     let config = config::AuthConfig::default();
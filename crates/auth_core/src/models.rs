@@ -70,6 +70,13 @@ pub struct Claims {
     pub org_id: Option<String>,
 }
 
+/// Signing key rotation request; without a key one is generated
+#[derive(Debug, Default, Deserialize)]
+pub struct RotateKeyRequest {
+    /// PEM private key (PKCS#8, or PKCS#1 for RSA)
+    pub private_key_pem: Option<String>,
+}
+
 /// Token validation response
 #[derive(Debug, Serialize)]
 pub struct ValidateResponse {
@@ -4,26 +4,39 @@ use axum::routing::{get, post};
 use axum::Router;
 use tower_http::cors::{Any, CorsLayer};
 
+use common::health::{self, Dependency};
+use common::shutdown::Shutdown;
 use contracts::{RefreshTokenServiceContract, UserServiceContract};
 
 use crate::config::AuthConfig;
-use crate::handlers::{login, logout, refresh, register, validate, AppState};
+use crate::handlers::{
+    jwks, list_keys, login, logout, refresh, register, retire_key, rotate_keys, validate,
+    AppState,
+};
+use crate::keys::Keyring;
 
 /// Run the auth server with the given service implementations
 /// 
 /// This allows running with different backends:
 /// - In-memory implementations for monolith mode
 /// - HTTP implementations for microservice mode
+///
+/// `readiness` lists what those implementations depend on, for `/readyz`
 pub async fn run(
     bind_addr: &str,
     user_service: Arc<dyn UserServiceContract>,
     token_service: Arc<dyn RefreshTokenServiceContract>,
     config: Arc<AuthConfig>,
+    keyring: Arc<Keyring>,
+    readiness: Vec<Dependency>,
+    shutdown: Shutdown,
 ) -> Result<(), std::io::Error> {
-    let app = build_router(user_service, token_service, config);
+    let health = health::routes("auth", shutdown.clone(), readiness);
+    let app = build_router(user_service, token_service, config, keyring).merge(health);
 
     let listener = tokio::net::TcpListener::bind(bind_addr).await?;
-    axum::serve(listener, app).await
+    let server = axum::serve(listener, app).with_graceful_shutdown(shutdown.wait());
+    shutdown.drain("auth", server).await
 }
 
 /// Build the auth router with the given service implementations
@@ -32,13 +45,14 @@ pub fn build_router(
     user_service: Arc<dyn UserServiceContract>,
     token_service: Arc<dyn RefreshTokenServiceContract>,
     config: Arc<AuthConfig>,
+    keyring: Arc<Keyring>,
 ) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
-    let inner = build_inner_router(user_service, token_service, config);
+    let inner = build_inner_router(user_service, token_service, config, keyring);
 
     Router::new()
         .nest("/auth", inner)
@@ -51,11 +65,13 @@ pub fn build_inner_router(
     user_service: Arc<dyn UserServiceContract>,
     token_service: Arc<dyn RefreshTokenServiceContract>,
     config: Arc<AuthConfig>,
+    keyring: Arc<Keyring>,
 ) -> Router {
     let state = AppState {
         user_service,
         token_service,
         config,
+        keyring,
     };
 
     Router::new()
@@ -64,5 +80,9 @@ pub fn build_inner_router(
         .route("/validate", get(validate))
         .route("/logout", post(logout))
         .route("/register", post(register))
+        .route("/.well-known/jwks.json", get(jwks))
+        .route("/keys", get(list_keys))
+        .route("/keys/rotate", post(rotate_keys))
+        .route("/keys/{kid}/retire", post(retire_key))
         .with_state(state)
 }
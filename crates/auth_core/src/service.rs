@@ -1,11 +1,16 @@
 use std::sync::Arc;
 
+use common::health::{self, Dependency};
+use common::shutdown::Shutdown;
+
 use crate::config::AuthConfig;
 use crate::http_client::{HttpRefreshTokenService, HttpUserService};
+use crate::keys::Keyring;
 
 /// Run the auth service in standalone (microservice) mode
 /// Uses HTTP to communicate with admin service
 pub async fn run(config: &AuthConfig) -> anyhow::Result<()> {
+    config.validate_service_secret()?;
     println!("auth service starting on {}", config.listen_addr);
     println!("  mode: standalone (HTTP client)");
     println!("  issuer: {}", config.issuer);
@@ -17,11 +22,42 @@ pub async fn run(config: &AuthConfig) -> anyhow::Result<()> {
     }
 
     // Create HTTP-based service implementations
-    let user_service = Arc::new(HttpUserService::new(&config.admin_service_url));
-    let token_service = Arc::new(HttpRefreshTokenService::new(&config.admin_service_url));
+    let user_service = Arc::new(HttpUserService::new(
+        &config.admin_service_url,
+        &config.admin_service_secret,
+    ));
+    let token_service = Arc::new(HttpRefreshTokenService::new(
+        &config.admin_service_url,
+        &config.admin_service_secret,
+    ));
+
+    let keyring = Arc::new(Keyring::load(config)?);
+    if let Some(key) = keyring.signing_key() {
+        println!("  signing key: {:?} (kid {})", key.algorithm(), key.kid());
+    }
+
+    // Nothing works without the admin service behind the HTTP clients
+    let readiness = vec![Dependency::required(
+        "admin",
+        health::http_probe(
+            reqwest::Client::new(),
+            format!("{}/healthz", config.admin_service_url.trim_end_matches('/')),
+        ),
+    )];
+
+    let shutdown = Shutdown::new();
+    shutdown.trigger_on_signals();
 
     let config = Arc::new(config.clone());
-    crate::server::run(&config.listen_addr, user_service, token_service, config.clone())
+    crate::server::run(
+        &config.listen_addr,
+        user_service,
+        token_service,
+        config.clone(),
+        keyring,
+        readiness,
+        shutdown,
+    )
         .await
         .map_err(|e| anyhow::anyhow!("server error: {}", e))
 }
@@ -4,11 +4,11 @@ use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use rand::Rng;
 
 use contracts::UserWithPassword;
 use crate::config::AuthConfig;
+use crate::keys::Keyring;
 use crate::models::Claims;
 
 /// Hash a password using Argon2
@@ -31,7 +31,11 @@ pub fn verify_password(password: &str, hash: &str) -> anyhow::Result<bool> {
 }
 
 /// Generate a JWT access token
-pub fn generate_access_token(user: &UserWithPassword, config: &AuthConfig) -> anyhow::Result<String> {
+pub fn generate_access_token(
+    user: &UserWithPassword,
+    config: &AuthConfig,
+    keyring: &Keyring,
+) -> anyhow::Result<String> {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .map_err(|e| anyhow::anyhow!("time error: {}", e))?
@@ -48,25 +52,30 @@ pub fn generate_access_token(user: &UserWithPassword, config: &AuthConfig) -> an
         org_id: user.organisation_id.map(|id| id.to_string()),
     };
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
-    )
-    .map_err(|e| anyhow::anyhow!("failed to encode token: {}", e))?;
-
-    Ok(token)
+    keyring.sign(&claims)
 }
 
 /// Generate a random refresh token
 pub fn generate_refresh_token() -> String {
     let mut rng = rand::thread_rng();
     let bytes: [u8; 32] = rng.r#gen();
-    hex::encode(bytes)
+    common::hex::encode(&bytes)
 }
 
-/// Hash a refresh token for storage
-pub fn hash_refresh_token(token: &str) -> String {
+/// Marks refresh token hashes produced by [`hash_refresh_token`]; stored
+/// hashes without it predate the switch and are upgraded on next use
+pub const REFRESH_TOKEN_HASH_PREFIX: &str = "hmac-sha256:";
+
+/// Hash a refresh token for storage (HMAC-SHA-256 keyed with the server pepper)
+pub fn hash_refresh_token(token: &str, pepper: &str) -> String {
+    let digest = common::mac::hex(pepper.as_bytes(), token.as_bytes());
+    format!("{REFRESH_TOKEN_HASH_PREFIX}{digest}")
+}
+
+/// Hash format used before [`REFRESH_TOKEN_HASH_PREFIX`] was introduced.
+/// Only used to find rows that have not been upgraded yet; the digest is not
+/// stable across Rust releases, so such rows may already be unreachable.
+pub fn legacy_refresh_token_hash(token: &str) -> String {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
 
@@ -76,23 +85,41 @@ pub fn hash_refresh_token(token: &str) -> String {
 }
 
 /// Validate and decode a JWT access token
-pub fn validate_access_token(token: &str, config: &AuthConfig) -> anyhow::Result<Claims> {
-    let mut validation = Validation::default();
-    validation.set_issuer(&[&config.issuer]);
-
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(config.jwt_secret.as_bytes()),
-        &validation,
-    )
-    .map_err(|e| anyhow::anyhow!("invalid token: {}", e))?;
-
-    Ok(token_data.claims)
+pub fn validate_access_token(
+    token: &str,
+    config: &AuthConfig,
+    keyring: &Keyring,
+) -> anyhow::Result<Claims> {
+    keyring.verify(token, &config.issuer)
 }
 
-// Add hex encoding since we need it
-mod hex {
-    pub fn encode(bytes: [u8; 32]) -> String {
-        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refresh_token_hash_is_prefixed_hex() {
+        let hash = hash_refresh_token(&generate_refresh_token(), "pepper");
+        let digest = hash.strip_prefix(REFRESH_TOKEN_HASH_PREFIX).expect("prefixed");
+        assert_eq!(digest.len(), 64);
+        assert!(digest.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+    }
+
+    #[test]
+    fn refresh_token_hash_is_stable_for_a_pepper() {
+        // HMAC-SHA-256("pepper", "token"); stored hashes must never change
+        assert_eq!(
+            hash_refresh_token("token", "pepper"),
+            "hmac-sha256:d06900724f36b88d7f0b2d77004645b24578b2629a5d666200fa5ab3f509b45e"
+        );
+        assert_ne!(hash_refresh_token("token", "other"), hash_refresh_token("token", "pepper"));
+    }
+
+    #[test]
+    fn legacy_hash_is_unprefixed() {
+        let hash = legacy_refresh_token_hash("token");
+        assert_eq!(hash, legacy_refresh_token_hash("token"));
+        assert!(!hash.starts_with(REFRESH_TOKEN_HASH_PREFIX));
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
     }
 }
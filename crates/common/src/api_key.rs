@@ -0,0 +1,21 @@
+//! API key format and hashing shared by the admin service and the gateway.
+//!
+//! Keys look like `ask_<prefix>_<secret>`. Only the SHA-256 hash of the whole
+//! key is stored; the prefix is kept in clear so keys can be told apart in
+//! listings. Keys are long random strings, so a fast hash is sufficient.
+
+use sha2::{Digest, Sha256};
+
+pub const KEY_PREFIX: &str = "ask_";
+
+/// Hash of a presented key, as stored at rest
+pub fn hash(key: &str) -> String {
+    crate::hex::encode(&Sha256::digest(key.as_bytes()))
+}
+
+/// Public part of a key (`ask_<prefix>`), safe to show and log
+pub fn display_prefix(key: &str) -> Option<&str> {
+    let rest = key.strip_prefix(KEY_PREFIX)?;
+    let (prefix, _) = rest.split_once('_')?;
+    Some(&key[..KEY_PREFIX.len() + prefix.len()])
+}
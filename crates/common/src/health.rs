@@ -0,0 +1,288 @@
+//! Liveness and readiness endpoints.
+//!
+//! Every service answers `GET /healthz` with 200 while the process is up, and
+//! `GET /readyz` with a JSON report of its dependencies:
+//!
+//! ```json
+//! {"status":"ok","service":"admin","checks":[{"name":"database","status":"up","required":true,"latency_ms":1}]}
+//! ```
+//!
+//! `/readyz` answers 503 once shutdown has started (`"status":"draining"`) or
+//! when a required dependency is down (`"fail"`). Optional dependencies that
+//! are down only make it `"degraded"`, which is still ready. With `?verbose`
+//! the report includes whatever details a probe returned, such as a
+//! downstream service's own report.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::extract::RawQuery;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::shutdown::Shutdown;
+
+/// A probe taking longer than this counts as down
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a probe found
+pub struct Probed {
+    pub error: Option<String>,
+    /// Extra information shown with `?verbose`
+    pub details: Option<Value>,
+}
+
+impl Probed {
+    pub fn up() -> Self {
+        Self {
+            error: None,
+            details: None,
+        }
+    }
+
+    pub fn down(error: impl Into<String>) -> Self {
+        Self {
+            error: Some(error.into()),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
+
+pub type ProbeFuture = Pin<Box<dyn Future<Output = Probed> + Send>>;
+pub type Probe = Arc<dyn Fn() -> ProbeFuture + Send + Sync>;
+
+/// Something a service needs in order to serve requests
+#[derive(Clone)]
+pub struct Dependency {
+    name: String,
+    required: bool,
+    probe: Probe,
+}
+
+impl Dependency {
+    /// Readiness fails while this is down
+    pub fn required<F, Fut>(name: impl Into<String>, probe: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Probed> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            required: true,
+            probe: Arc::new(move || -> ProbeFuture { Box::pin(probe()) }),
+        }
+    }
+
+    /// Reported, but the service stays ready while this is down
+    pub fn optional<F, Fut>(name: impl Into<String>, probe: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Probed> + Send + 'static,
+    {
+        Self {
+            required: false,
+            ..Self::required(name, probe)
+        }
+    }
+}
+
+/// `GET url`; up on a 2xx answer. A JSON body is kept as details.
+pub fn http_probe(
+    client: reqwest::Client,
+    url: String,
+) -> impl Fn() -> ProbeFuture + Send + Sync + 'static {
+    move || {
+        let request = client.get(&url).timeout(PROBE_TIMEOUT);
+        Box::pin(async move {
+            let response = match request.send().await {
+                Ok(response) => response,
+                Err(err) => return Probed::down(err.to_string()),
+            };
+            let status = response.status();
+            let details = response
+                .bytes()
+                .await
+                .ok()
+                .and_then(|body| serde_json::from_slice::<Value>(&body).ok());
+            let probed = if status.is_success() {
+                Probed::up()
+            } else {
+                Probed::down(format!("HTTP {}", status.as_u16()))
+            };
+            match details {
+                Some(details) => probed.with_details(details),
+                None => probed,
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub required: bool,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+/// Probe every dependency concurrently, keeping their order
+pub async fn check_all(dependencies: &[Dependency]) -> Vec<Check> {
+    let mut probes = tokio::task::JoinSet::new();
+    for (index, dependency) in dependencies.iter().enumerate() {
+        let probe = (dependency.probe)();
+        probes.spawn(async move {
+            let started = Instant::now();
+            let probed = tokio::time::timeout(PROBE_TIMEOUT, probe)
+                .await
+                .unwrap_or_else(|_| Probed::down("timed out"));
+            (index, probed, started.elapsed())
+        });
+    }
+
+    let mut checks: Vec<Option<Check>> = vec![None; dependencies.len()];
+    while let Some(joined) = probes.join_next().await {
+        let Ok((index, probed, elapsed)) = joined else {
+            continue;
+        };
+        let dependency = &dependencies[index];
+        checks[index] = Some(Check {
+            name: dependency.name.clone(),
+            status: if probed.error.is_none() {
+                CheckStatus::Up
+            } else {
+                CheckStatus::Down
+            },
+            required: dependency.required,
+            latency_ms: elapsed.as_millis() as u64,
+            error: probed.error,
+            details: probed.details,
+        });
+    }
+    // A probe that panicked is down
+    checks
+        .into_iter()
+        .zip(dependencies)
+        .map(|(check, dependency)| {
+            check.unwrap_or_else(|| Check {
+                name: dependency.name.clone(),
+                status: CheckStatus::Down,
+                required: dependency.required,
+                latency_ms: 0,
+                error: Some("probe failed".to_string()),
+                details: None,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadyStatus {
+    Ok,
+    /// Ready, but an optional dependency is down
+    Degraded,
+    /// Shutting down
+    Draining,
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub status: ReadyStatus,
+    pub service: String,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn new(service: &str, shutdown: &Shutdown, mut checks: Vec<Check>, verbose: bool) -> Self {
+        let down = |required: bool| {
+            checks
+                .iter()
+                .any(|c| c.required == required && c.status == CheckStatus::Down)
+        };
+        let status = if !shutdown.is_ready() {
+            ReadyStatus::Draining
+        } else if down(true) {
+            ReadyStatus::Fail
+        } else if down(false) {
+            ReadyStatus::Degraded
+        } else {
+            ReadyStatus::Ok
+        };
+        if !verbose {
+            for check in &mut checks {
+                check.details = None;
+            }
+        }
+        Self {
+            status,
+            service: service.to_string(),
+            checks,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(self.status, ReadyStatus::Ok | ReadyStatus::Degraded)
+    }
+}
+
+impl IntoResponse for Report {
+    fn into_response(self) -> Response {
+        let status = if self.is_ready() {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+/// `/healthz` body; the process is alive if it can answer at all
+pub fn liveness(service: &str) -> Json<Value> {
+    Json(serde_json::json!({ "status": "ok", "service": service }))
+}
+
+/// Whether the query string asks for details (`?verbose` or `?verbose=true`)
+pub fn is_verbose(query: Option<&str>) -> bool {
+    query.is_some_and(|query| {
+        query
+            .split('&')
+            .any(|pair| matches!(pair, "verbose" | "verbose=1" | "verbose=true"))
+    })
+}
+
+/// `/healthz` and `/readyz` for a service with a fixed set of dependencies
+pub fn routes(service: &'static str, shutdown: Shutdown, dependencies: Vec<Dependency>) -> Router {
+    let dependencies = Arc::new(dependencies);
+    Router::new()
+        .route("/healthz", get(move || async move { liveness(service) }))
+        .route(
+            "/readyz",
+            get(move |RawQuery(query): RawQuery| async move {
+                let checks = check_all(&dependencies).await;
+                Report::new(service, &shutdown, checks, is_verbose(query.as_deref()))
+            }),
+        )
+}
@@ -1,3 +1,12 @@
+pub mod api_key;
+pub mod health;
+pub mod hex;
+pub mod identity;
+pub mod mac;
+pub mod secret;
+pub mod service_auth;
+pub mod shutdown;
+
 pub fn init_service(name: &str) {
     println!("starting service: {name}");
 }
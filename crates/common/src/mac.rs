@@ -0,0 +1,33 @@
+//! HMAC-SHA-256, the keyed hash behind identity and service signatures and
+//! the stored refresh token hashes.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub type HmacSha256 = Hmac<Sha256>;
+
+/// MAC keyed with `key`, ready for `update`
+pub fn keyed(key: &[u8]) -> HmacSha256 {
+    HmacSha256::new_from_slice(key).expect("hmac accepts keys of any length")
+}
+
+/// Lowercase hex MAC of `data` under `key`
+pub fn hex(key: &[u8], data: &[u8]) -> String {
+    let mut mac = keyed(key);
+    mac.update(data);
+    crate::hex::encode(&mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_rfc_4231() {
+        // Test case 2
+        assert_eq!(
+            hex(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,30 @@
+//! Shared secrets the services are configured with.
+//!
+//! Each secret has a public development default so a checkout runs as is.
+//! Outside development mode (`APISENTINEL_DEV_MODE=true`) a service refuses
+//! to start with a secret that is unset or still the default: anyone can sign
+//! with a value printed in the source.
+
+/// Env var that allows the development defaults
+pub const DEV_MODE_ENV: &str = "APISENTINEL_DEV_MODE";
+
+/// Whether the operator opted into development mode
+pub fn dev_mode() -> bool {
+    std::env::var(DEV_MODE_ENV)
+        .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// Check the secret read from the `var` env var. Fails when it is empty, or
+/// still `default` outside development mode.
+pub fn check(var: &str, value: &str, default: &str) -> Result<(), String> {
+    if value.is_empty() {
+        return Err(format!("{var} must not be empty"));
+    }
+    if value == default && !dev_mode() {
+        return Err(format!(
+            "{var} is not set; the development default is only accepted with {DEV_MODE_ENV}=true"
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,204 @@
+//! Service-to-service request signing.
+//!
+//! Calls to a service's internal API carry an HMAC-SHA-256 signature over the
+//! calling service's name, a timestamp, the method, the path and query, and a
+//! SHA-256 of the body. The secret is shared by the services; a request that
+//! does not verify is rejected before it reaches a handler.
+
+use hmac::Mac;
+use sha2::{Digest, Sha256};
+
+use crate::identity::MAX_SKEW_SECS;
+use crate::mac::{self, HmacSha256};
+
+/// Name of the calling service
+pub const SERVICE_HEADER: &str = "x-service-name";
+/// Unix time (seconds) at which the request was signed
+pub const TIMESTAMP_HEADER: &str = "x-service-timestamp";
+/// `v1=<hex hmac>`
+pub const SIGNATURE_HEADER: &str = "x-service-signature";
+
+/// Headers only services may set; the gateway strips them from client requests
+pub const SERVICE_HEADERS: &[&str] = &[SERVICE_HEADER, TIMESTAMP_HEADER, SIGNATURE_HEADER];
+
+pub const DEFAULT_SERVICE_SECRET: &str = "change-me-in-production-service-secret";
+
+#[derive(Clone)]
+pub struct ServiceSigner {
+    secret: Vec<u8>,
+}
+
+impl ServiceSigner {
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self {
+            secret: secret.as_ref().to_vec(),
+        }
+    }
+
+    /// Headers to attach to a request from `service`
+    pub fn headers(
+        &self,
+        service: &str,
+        method: &str,
+        path_and_query: &str,
+        body: &[u8],
+    ) -> [(&'static str, String); 3] {
+        let timestamp = crate::identity::unix_now();
+        let mac = self.mac(service, timestamp, method, path_and_query, body);
+        [
+            (SERVICE_HEADER, service.to_string()),
+            (TIMESTAMP_HEADER, timestamp.to_string()),
+            (
+                SIGNATURE_HEADER,
+                format!("v1={}", crate::hex::encode(&mac.finalize().into_bytes())),
+            ),
+        ]
+    }
+
+    /// Check a request's signature; returns the calling service's name
+    pub fn verify(
+        &self,
+        header: impl Fn(&str) -> Option<String>,
+        method: &str,
+        path_and_query: &str,
+        body: &[u8],
+        now: u64,
+    ) -> Option<String> {
+        let service = header(SERVICE_HEADER)?;
+        let timestamp = header(TIMESTAMP_HEADER)?.parse::<u64>().ok()?;
+        if timestamp.abs_diff(now) > MAX_SKEW_SECS {
+            return None;
+        }
+        let signature = header(SIGNATURE_HEADER)?
+            .strip_prefix("v1=")
+            .and_then(crate::hex::decode)?;
+        self.mac(&service, timestamp, method, path_and_query, body)
+            .verify_slice(&signature)
+            .ok()?;
+        Some(service)
+    }
+
+    fn mac(
+        &self,
+        service: &str,
+        timestamp: u64,
+        method: &str,
+        path_and_query: &str,
+        body: &[u8],
+    ) -> HmacSha256 {
+        let mut mac = mac::keyed(&self.secret);
+        let body_hash = crate::hex::encode(&Sha256::digest(body));
+        mac.update(
+            format!("v1\n{service}\n{timestamp}\n{method}\n{path_and_query}\n{body_hash}\n")
+                .as_bytes(),
+        );
+        mac
+    }
+}
+
+/// Sign an outgoing `reqwest` request in place
+pub fn sign_request(signer: &ServiceSigner, service: &str, req: &mut reqwest::Request) {
+    let url = req.url();
+    let path_and_query = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let body = req.body().and_then(|b| b.as_bytes()).unwrap_or_default();
+    let headers = signer.headers(service, req.method().as_str(), &path_and_query, body);
+    for (name, value) in headers {
+        if let Ok(value) = reqwest::header::HeaderValue::from_str(&value) {
+            req.headers_mut().insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::identity::unix_now;
+
+    const PATH: &str = "/internal/users/by-email?email=a%40b.c";
+    const BODY: &[u8] = br#"{"key_hash":"abc"}"#;
+
+    fn signed(signer: &ServiceSigner) -> HashMap<String, String> {
+        signer
+            .headers("auth", "POST", PATH, BODY)
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    }
+
+    fn verify(
+        signer: &ServiceSigner,
+        headers: &HashMap<String, String>,
+        path_and_query: &str,
+        body: &[u8],
+        now: u64,
+    ) -> Option<String> {
+        signer.verify(|name| headers.get(name).cloned(), "POST", path_and_query, body, now)
+    }
+
+    #[test]
+    fn round_trip_names_the_caller() {
+        let signer = ServiceSigner::new("secret");
+        let headers = signed(&signer);
+        assert_eq!(verify(&signer, &headers, PATH, BODY, unix_now()).as_deref(), Some("auth"));
+        let other = ServiceSigner::new("other");
+        assert_eq!(verify(&other, &headers, PATH, BODY, unix_now()), None);
+    }
+
+    #[test]
+    fn body_is_covered() {
+        let signer = ServiceSigner::new("secret");
+        let headers = signed(&signer);
+        assert_eq!(verify(&signer, &headers, PATH, br#"{"key_hash":"abd"}"#, unix_now()), None);
+        assert_eq!(verify(&signer, &headers, PATH, b"", unix_now()), None);
+    }
+
+    #[test]
+    fn path_and_query_are_covered() {
+        let signer = ServiceSigner::new("secret");
+        let headers = signed(&signer);
+        assert_eq!(verify(&signer, &headers, "/internal/users/by-email", BODY, unix_now()), None);
+        assert_eq!(
+            verify(&signer, &headers, "/internal/users/by-email?email=x%40b.c", BODY, unix_now()),
+            None
+        );
+        assert_eq!(verify(&signer, &headers, "/internal/api-keys/verify", BODY, unix_now()), None);
+        let header = |name: &str| headers.get(name).cloned();
+        assert_eq!(signer.verify(header, "DELETE", PATH, BODY, unix_now()), None);
+    }
+
+    #[test]
+    fn caller_name_is_covered() {
+        let signer = ServiceSigner::new("secret");
+        let mut headers = signed(&signer);
+        headers.insert(SERVICE_HEADER.to_string(), "gateway".to_string());
+        assert_eq!(verify(&signer, &headers, PATH, BODY, unix_now()), None);
+    }
+
+    #[test]
+    fn timestamp_must_be_within_skew() {
+        let signer = ServiceSigner::new("secret");
+        let headers = signed(&signer);
+        let signed_at: u64 = headers[TIMESTAMP_HEADER].parse().unwrap();
+        for now in [signed_at - MAX_SKEW_SECS, signed_at + MAX_SKEW_SECS] {
+            assert!(verify(&signer, &headers, PATH, BODY, now).is_some());
+        }
+        for now in [signed_at - MAX_SKEW_SECS - 1, signed_at + MAX_SKEW_SECS + 1] {
+            assert_eq!(verify(&signer, &headers, PATH, BODY, now), None);
+        }
+    }
+
+    #[test]
+    fn missing_headers_are_refused() {
+        let signer = ServiceSigner::new("secret");
+        for name in SERVICE_HEADERS {
+            let mut headers = signed(&signer);
+            headers.remove(*name);
+            assert_eq!(verify(&signer, &headers, PATH, BODY, unix_now()), None, "{name}");
+        }
+    }
+}
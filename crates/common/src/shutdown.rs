@@ -0,0 +1,180 @@
+//! Process-wide shutdown coordination.
+//!
+//! Every server in a process is handed the same [`Shutdown`]; triggering it,
+//! from a signal or because a sibling module failed, stops them all in phases:
+//!
+//! 1. **Draining**: readiness reports failure so load balancers stop sending
+//!    traffic, while listeners keep serving for `SHUTDOWN_READINESS_DELAY_SECS`
+//! 2. **Stopping**: listeners close and in-flight requests get up to
+//!    `SHUTDOWN_DRAIN_TIMEOUT_SECS` to finish; connections still open after
+//!    that are dropped
+//!
+//! Whoever owns the database pool closes it once every server has returned.
+//! A second signal exits immediately.
+
+use std::future::{Future, IntoFuture};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+/// Long enough for a load balancer polling every few seconds to see readiness fail
+const DEFAULT_READINESS_DELAY: Duration = Duration::from_secs(5);
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Phase {
+    Running,
+    Draining,
+    Stopping,
+}
+
+#[derive(Clone)]
+pub struct Shutdown {
+    phase: Arc<watch::Sender<Phase>>,
+    /// How long readiness fails before listeners close
+    pub readiness_delay: Duration,
+    /// How long in-flight requests may take once listeners are closed
+    pub drain_timeout: Duration,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    /// Timings from `SHUTDOWN_READINESS_DELAY_SECS` and `SHUTDOWN_DRAIN_TIMEOUT_SECS`
+    pub fn new() -> Self {
+        let secs = |name: &str, default: Duration| {
+            std::env::var(name)
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(default)
+        };
+        Self {
+            phase: Arc::new(watch::Sender::new(Phase::Running)),
+            readiness_delay: secs("SHUTDOWN_READINESS_DELAY_SECS", DEFAULT_READINESS_DELAY),
+            drain_timeout: secs("SHUTDOWN_DRAIN_TIMEOUT_SECS", DEFAULT_DRAIN_TIMEOUT),
+        }
+    }
+
+    /// Start shutting down; later calls are no-ops
+    pub fn trigger(&self) {
+        let started = self.phase.send_if_modified(|phase| {
+            let running = *phase == Phase::Running;
+            if running {
+                *phase = Phase::Draining;
+            }
+            running
+        });
+        if !started {
+            return;
+        }
+
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            if !shutdown.readiness_delay.is_zero() {
+                println!(
+                    "shutdown: reporting not ready, closing listeners in {}s",
+                    shutdown.readiness_delay.as_secs()
+                );
+                tokio::time::sleep(shutdown.readiness_delay).await;
+            }
+            println!(
+                "shutdown: closing listeners, draining in-flight requests (up to {}s)",
+                shutdown.drain_timeout.as_secs()
+            );
+            shutdown.phase.send_replace(Phase::Stopping);
+        });
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.phase.borrow() != Phase::Running
+    }
+
+    /// False from the moment shutdown is triggered
+    pub fn is_ready(&self) -> bool {
+        !self.is_triggered()
+    }
+
+    /// Resolves once listeners should stop accepting connections; pass it to
+    /// `axum::serve(..).with_graceful_shutdown`
+    pub fn wait(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut rx = self.phase.subscribe();
+        async move {
+            let _ = rx.wait_for(|phase| *phase == Phase::Stopping).await;
+        }
+    }
+
+    /// Run a gracefully shutting down server, giving up on connections that
+    /// are still open `drain_timeout` after listeners closed
+    pub async fn drain<E>(
+        &self,
+        name: &str,
+        server: impl IntoFuture<Output = Result<(), E>>,
+    ) -> Result<(), E> {
+        let stopping = self.wait();
+        let drain_timeout = self.drain_timeout;
+        let deadline = async move {
+            stopping.await;
+            tokio::time::sleep(drain_timeout).await;
+        };
+        tokio::select! {
+            result = server.into_future() => result,
+            _ = deadline => {
+                eprintln!(
+                    "{name}: requests still in flight after {}s, dropping them",
+                    drain_timeout.as_secs()
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Trigger on Ctrl-C or SIGTERM; a second signal exits at once
+    pub fn trigger_on_signals(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            signal().await;
+            println!("shutdown signal received");
+            shutdown.trigger();
+
+            signal().await;
+            eprintln!("second shutdown signal received, exiting now");
+            std::process::exit(130);
+        });
+    }
+}
+
+async fn signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            eprintln!("Ctrl-C handler unavailable: {err}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                eprintln!("SIGTERM handler unavailable: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -0,0 +1,17 @@
+//! API key service contract
+
+use async_trait::async_trait;
+
+use crate::types::{ApiKeyInfo, ContractResult};
+
+/// Contract for validating API keys presented to the gateway
+///
+/// Implementations:
+/// - `InMemoryApiKeyService` - Direct database access (for monolith mode)
+/// - `HttpApiKeyService` - HTTP calls to admin service (for microservice mode)
+#[async_trait]
+pub trait ApiKeyServiceContract: Send + Sync {
+    /// Resolve an active (not revoked, not expired) key by its hash and record
+    /// that it was used
+    async fn verify(&self, key_hash: &str) -> ContractResult<Option<ApiKeyInfo>>;
+}
@@ -6,10 +6,12 @@
 //!
 //! All service communication is defined through traits, allowing different backends.
 
+pub mod api_key;
 pub mod types;
 pub mod user;
 pub mod token;
 
 pub use types::*;
+pub use api_key::ApiKeyServiceContract;
 pub use user::UserServiceContract;
 pub use token::RefreshTokenServiceContract;
@@ -13,7 +13,7 @@ use crate::types::{ContractResult, RefreshTokenInfo};
 /// - `HttpRefreshTokenService` - HTTP calls to admin service (for microservice mode)
 #[async_trait]
 pub trait RefreshTokenServiceContract: Send + Sync {
-    /// Store a new refresh token
+    /// Store a new refresh token as the start of a new family
     async fn create(
         &self,
         user_id: Uuid,
@@ -25,14 +25,28 @@ pub trait RefreshTokenServiceContract: Send + Sync {
     /// Find refresh token by hash
     async fn find_by_hash(&self, token_hash: &str) -> ContractResult<Option<RefreshTokenInfo>>;
 
-    /// Update refresh token (for rotation)
-    async fn update(
+    /// Rotate a refresh token: mark it as superseded and store its successor
+    /// in the same family. Returns `None` if the token was already rotated or
+    /// revoked, i.e. it is being replayed.
+    async fn rotate(
         &self,
         token_id: Uuid,
         new_token_hash: &str,
         new_expires_at: DateTime<Utc>,
+    ) -> ContractResult<Option<Uuid>>;
+
+    /// Replace a token's stored hash, e.g. to upgrade it to a new hash format.
+    /// Does nothing unless the current hash is still `old_token_hash`.
+    async fn rehash(
+        &self,
+        token_id: Uuid,
+        old_token_hash: &str,
+        new_token_hash: &str,
     ) -> ContractResult<()>;
 
+    /// Revoke every token in a family; returns how many were still active
+    async fn revoke_family(&self, family_id: Uuid) -> ContractResult<u64>;
+
     /// Delete refresh token by hash
     async fn delete_by_hash(&self, token_hash: &str) -> ContractResult<()>;
 
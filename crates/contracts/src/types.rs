@@ -43,12 +43,24 @@ impl Role {
         }
     }
 
-    pub fn from_str(s: &str) -> Self {
+    /// Strict parse; `None` for anything but a known role name
+    pub fn parse(s: &str) -> Option<Self> {
         match s.to_uppercase().as_str() {
-            "SUPER_ADMIN" => Role::SuperAdmin,
-            "ADMIN" => Role::Admin,
-            "SUPERVISOR" => Role::Supervisor,
-            _ => Role::User,
+            "SUPER_ADMIN" => Some(Role::SuperAdmin),
+            "ADMIN" => Some(Role::Admin),
+            "SUPERVISOR" => Some(Role::Supervisor),
+            "USER" => Some(Role::User),
+            _ => None,
+        }
+    }
+
+    /// Privilege level; higher can do more
+    pub fn rank(&self) -> u8 {
+        match self {
+            Role::SuperAdmin => 3,
+            Role::Admin => 2,
+            Role::Supervisor => 1,
+            Role::User => 0,
         }
     }
 }
@@ -114,5 +126,22 @@ pub struct RefreshTokenInfo {
     pub id: Uuid,
     pub user_id: Uuid,
     pub organisation_id: Option<Uuid>,
+    /// Tokens descended from the same login share a family
+    pub family_id: Uuid,
     pub expires_at: DateTime<Utc>,
+    /// Set once the token has been exchanged for a successor
+    pub rotated_at: Option<DateTime<Utc>>,
+    /// Set when the family was revoked (logout or detected reuse)
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Identity behind a valid API key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyInfo {
+    pub id: Uuid,
+    pub organisation_id: Uuid,
+    pub name: String,
+    pub role: Role,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
 }
@@ -0,0 +1,289 @@
+//! API key validation for the Auth middleware.
+//!
+//! Keys are looked up by hash through an `ApiKeyServiceContract`: in-memory
+//! when the admin module runs in the same process, otherwise over HTTP against
+//! the admin service. Results are cached so hot keys don't hit the database on
+//! every request; a revoked key stays usable for at most one cache TTL.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use common::service_auth::{self, ServiceSigner};
+use contracts::{ApiKeyInfo, ApiKeyServiceContract, ContractError, ContractResult};
+use serde::Serialize;
+
+const DEFAULT_ADMIN_SERVICE_URL: &str = "http://localhost:4001";
+const DEFAULT_CACHE_TTL_SECS: u64 = 30;
+/// Unknown keys are remembered for less time than valid ones
+const NEGATIVE_CACHE_TTL_SECS: u64 = 5;
+const MAX_CACHE_ENTRIES: usize = 10_000;
+/// Unknown keys are capped on their own, so a client spraying random keys
+/// can't push valid ones out of the cache
+const MAX_UNKNOWN_ENTRIES: usize = 1_000;
+
+static SERVICE: OnceLock<Arc<dyn ApiKeyServiceContract>> = OnceLock::new();
+static CACHE: OnceLock<ApiKeyCache> = OnceLock::new();
+
+/// Use `service` to validate API keys. Must be called before the gateway
+/// starts; otherwise keys are checked against `ADMIN_SERVICE_URL`.
+pub fn set_api_key_service(service: Arc<dyn ApiKeyServiceContract>) {
+    let _ = SERVICE.set(service);
+}
+
+fn service() -> &'static Arc<dyn ApiKeyServiceContract> {
+    SERVICE.get_or_init(|| {
+        let url = std::env::var("ADMIN_SERVICE_URL")
+            .unwrap_or_else(|_| DEFAULT_ADMIN_SERVICE_URL.to_string());
+        Arc::new(HttpApiKeyService::new(&url, &service_secret()))
+    })
+}
+
+fn service_secret() -> String {
+    std::env::var("ADMIN_SERVICE_SECRET")
+        .unwrap_or_else(|_| service_auth::DEFAULT_SERVICE_SECRET.to_string())
+}
+
+/// Refuse to check keys against the admin service with the public
+/// development secret; nothing to check when keys are validated in-process
+pub(crate) fn check_service_secret() -> anyhow::Result<()> {
+    if SERVICE.get().is_some() {
+        return Ok(());
+    }
+    common::secret::check(
+        "ADMIN_SERVICE_SECRET",
+        &service_secret(),
+        service_auth::DEFAULT_SERVICE_SECRET,
+    )
+    .map_err(anyhow::Error::msg)
+}
+
+fn cache() -> &'static ApiKeyCache {
+    CACHE.get_or_init(|| {
+        let ttl = std::env::var("GATEWAY_API_KEY_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_CACHE_TTL_SECS);
+        ApiKeyCache::new(Duration::from_secs(ttl))
+    })
+}
+
+/// Resolve a presented key to its identity, `None` if it is not valid
+pub async fn verify(key: &str) -> ContractResult<Option<ApiKeyInfo>> {
+    let key_hash = common::api_key::hash(key);
+    let cache = cache();
+    if let Some(cached) = cache.get(&key_hash) {
+        return Ok(cached);
+    }
+
+    let info = service().verify(&key_hash).await?;
+    cache.insert(key_hash, info.clone());
+    Ok(info)
+}
+
+struct ApiKeyCache {
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    valid: HashMap<String, (Instant, ApiKeyInfo)>,
+    /// Expiry of each key hash the admin service didn't know
+    unknown: HashMap<String, Instant>,
+}
+
+impl ApiKeyCache {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    fn get(&self, key_hash: &str) -> Option<Option<ApiKeyInfo>> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((expires, info)) = entries.valid.get(key_hash)
+            && *expires > now
+        {
+            // A key that expired while cached is no longer valid
+            return match info.expires_at {
+                Some(at) if at <= Utc::now() => Some(None),
+                _ => Some(Some(info.clone())),
+            };
+        }
+        match entries.unknown.get(key_hash) {
+            Some(expires) if *expires > now => Some(None),
+            _ => None,
+        }
+    }
+
+    fn insert(&self, key_hash: String, info: Option<ApiKeyInfo>) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match info {
+            Some(info) => {
+                entries.unknown.remove(&key_hash);
+                let valid = &mut entries.valid;
+                if valid.len() >= MAX_CACHE_ENTRIES {
+                    valid.retain(|_, (expires, _)| *expires > now);
+                }
+                if valid.len() >= MAX_CACHE_ENTRIES {
+                    // Make room by dropping the entry closest to expiring
+                    let oldest = valid
+                        .iter()
+                        .min_by_key(|(_, (expires, _))| *expires)
+                        .map(|(hash, _)| hash.clone());
+                    if let Some(oldest) = oldest {
+                        valid.remove(&oldest);
+                    }
+                }
+                valid.insert(key_hash, (now + self.ttl, info));
+            }
+            None => {
+                entries.valid.remove(&key_hash);
+                let unknown = &mut entries.unknown;
+                if unknown.len() >= MAX_UNKNOWN_ENTRIES {
+                    unknown.retain(|_, expires| *expires > now);
+                    if unknown.len() >= MAX_UNKNOWN_ENTRIES {
+                        unknown.clear();
+                    }
+                }
+                let ttl = self.ttl.min(Duration::from_secs(NEGATIVE_CACHE_TTL_SECS));
+                unknown.insert(key_hash, now + ttl);
+            }
+        }
+    }
+}
+
+// ============================================================================
+// HTTP API Key Service Implementation
+// ============================================================================
+
+/// HTTP implementation of ApiKeyServiceContract
+/// Used in microservice mode - calls admin service via network
+#[derive(Clone)]
+pub struct HttpApiKeyService {
+    base_url: String,
+    client: reqwest::Client,
+    signer: ServiceSigner,
+}
+
+impl HttpApiKeyService {
+    pub fn new(admin_base_url: &str, service_secret: &str) -> Self {
+        Self {
+            base_url: admin_base_url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap_or_default(),
+            signer: ServiceSigner::new(service_secret),
+        }
+    }
+}
+
+#[async_trait]
+impl ApiKeyServiceContract for HttpApiKeyService {
+    async fn verify(&self, key_hash: &str) -> ContractResult<Option<ApiKeyInfo>> {
+        let url = format!("{}/internal/api-keys/verify", self.base_url);
+
+        #[derive(Serialize)]
+        struct VerifyRequest<'a> {
+            key_hash: &'a str,
+        }
+
+        let mut req = self
+            .client
+            .post(&url)
+            .json(&VerifyRequest { key_hash })
+            .build()
+            .map_err(|e| ContractError::Internal(e.to_string()))?;
+        service_auth::sign_request(&self.signer, "gateway", &mut req);
+
+        let resp = self
+            .client
+            .execute(req)
+            .await
+            .map_err(|e| ContractError::Connection(e.to_string()))?;
+
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        if !resp.status().is_success() {
+            return Err(ContractError::Internal(format!(
+                "Failed to verify API key: {}",
+                resp.status()
+            )));
+        }
+
+        let info: ApiKeyInfo = resp
+            .json()
+            .await
+            .map_err(|e| ContractError::Internal(e.to_string()))?;
+        Ok(Some(info))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> ApiKeyInfo {
+        serde_json::from_value(serde_json::json!({
+            "id": "00000000-0000-0000-0000-000000000001",
+            "organisation_id": "00000000-0000-0000-0000-000000000002",
+            "name": "ci",
+            "role": "USER",
+            "scopes": [],
+            "expires_at": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn unknown_keys_dont_flush_valid_ones() {
+        let cache = ApiKeyCache::new(Duration::from_secs(30));
+        cache.insert("valid".to_string(), Some(info()));
+
+        for i in 0..MAX_UNKNOWN_ENTRIES * 3 {
+            cache.insert(format!("random-{i}"), None);
+        }
+
+        assert!(matches!(cache.get("valid"), Some(Some(_))));
+        let entries = cache.entries.lock().unwrap();
+        assert!(entries.unknown.len() <= MAX_UNKNOWN_ENTRIES);
+    }
+
+    #[test]
+    fn full_cache_drops_the_oldest_valid_key() {
+        let cache = ApiKeyCache::new(Duration::from_secs(30));
+        cache.insert("key-0".to_string(), Some(info()));
+        std::thread::sleep(Duration::from_millis(2));
+        for i in 1..MAX_CACHE_ENTRIES {
+            cache.insert(format!("key-{i}"), Some(info()));
+        }
+        cache.insert("newest".to_string(), Some(info()));
+
+        assert!(cache.get("key-0").is_none());
+        assert!(matches!(cache.get("key-1"), Some(Some(_))));
+        assert!(matches!(cache.get("newest"), Some(Some(_))));
+    }
+
+    #[test]
+    fn unknown_keys_are_remembered() {
+        let cache = ApiKeyCache::new(Duration::from_secs(30));
+        cache.insert("unknown".to_string(), None);
+        assert!(matches!(cache.get("unknown"), Some(None)));
+        assert!(cache.get("never-seen").is_none());
+    }
+}
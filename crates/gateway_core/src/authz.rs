@@ -0,0 +1,344 @@
+//! Per-route authorization rules.
+//!
+//! A route can carry access rules that are checked by the Auth middleware once
+//! the caller is known. A rule matches on method and path; the first rule that
+//! matches decides what the caller needs:
+//!
+//! ```toml
+//! [[routes]]
+//! path = "/admin"
+//! access = [
+//!   { path = "/admin/organisations", methods = ["POST"], min_role = "SUPER_ADMIN" },
+//!   { path = "/admin/users/*", methods = ["DELETE"], min_role = "ADMIN" },
+//!   { path = "/admin/reports/**", scopes = ["reports:read"] },
+//! ]
+//! ```
+//!
+//! In paths `*` matches one segment and a trailing `**` any number of them.
+//! `scopes` only restrict API keys; users are limited by their role alone.
+//! A rule listing `GET` also covers `HEAD`, which the same handler answers.
+//! Requests matching no rule are allowed. Paths with `.` or `..` segments
+//! never get this far (see [`has_dot_segments`]).
+
+use contracts::Role;
+
+use crate::types::Response;
+
+/// Access rule for requests under a route
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessRule {
+    /// Full request path pattern, e.g. `/admin/users/*`
+    pub path: String,
+    /// Upper-case methods the rule applies to; empty means all
+    pub methods: Vec<String>,
+    /// Lowest role allowed through
+    pub min_role: Option<Role>,
+    /// Scopes an API key must all carry
+    pub scopes: Vec<String>,
+}
+
+/// Who the Auth middleware resolved the request to
+pub struct Principal<'a> {
+    pub role: Role,
+    /// `Some` for API keys
+    pub scopes: Option<&'a [String]>,
+}
+
+impl AccessRule {
+    pub fn matches(&self, method: &str, path: &str) -> bool {
+        let head = method.eq_ignore_ascii_case("HEAD");
+        (self.methods.is_empty()
+            || self.methods.iter().any(|m| {
+                m.eq_ignore_ascii_case(method) || (head && m.eq_ignore_ascii_case("GET"))
+            }))
+            && path_matches(&self.path, path)
+    }
+
+    /// Why `principal` is refused, if it is
+    fn denial(&self, principal: &Principal) -> Option<String> {
+        if let Some(min_role) = self.min_role
+            && principal.role.rank() < min_role.rank()
+        {
+            return Some(format!("requires role {min_role} or above"));
+        }
+        if let Some(scopes) = principal.scopes {
+            let missing: Vec<&str> = self
+                .scopes
+                .iter()
+                .filter(|s| !scopes.contains(s))
+                .map(String::as_str)
+                .collect();
+            if !missing.is_empty() {
+                return Some(format!("api key lacks scope {}", missing.join(", ")));
+            }
+        }
+        None
+    }
+}
+
+/// First rule matching the request
+pub fn rule_for<'a>(rules: &'a [AccessRule], method: &str, path: &str) -> Option<&'a AccessRule> {
+    rules.iter().find(|rule| rule.matches(method, path))
+}
+
+/// Check `principal` (or an anonymous caller) against the rules
+pub fn check(
+    rules: &[AccessRule],
+    method: &str,
+    path: &str,
+    principal: Option<&Principal>,
+) -> Result<(), Response> {
+    let Some(rule) = rule_for(rules, method, path) else {
+        return Ok(());
+    };
+    match principal {
+        None => Err(problem(401, "Unauthorized", "authentication required", path)),
+        Some(principal) => match rule.denial(principal) {
+            Some(detail) => Err(problem(403, "Forbidden", &detail, path)),
+            None => Ok(()),
+        },
+    }
+}
+
+/// RFC 7807 problem response
+pub fn problem(status: u16, title: &str, detail: &str, instance: &str) -> Response {
+    let body = serde_json::json!({
+        "type": "about:blank",
+        "title": title,
+        "status": status,
+        "detail": detail,
+        "instance": instance,
+    });
+    Response {
+        status,
+        headers: vec![(
+            "content-type".to_string(),
+            "application/problem+json".to_string(),
+        )],
+        body: body.to_string(),
+    }
+}
+
+/// Match `path` against a pattern segment by segment. Empty segments are
+/// ignored and segments are percent-decoded, so `//` or `%2F`-style variants
+/// of a path are held to the same rule.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let decoded: Vec<String> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            urlencoding::decode(s)
+                .map(|d| d.into_owned())
+                .unwrap_or_else(|_| s.to_string())
+        })
+        .collect();
+    let mut segments = decoded.iter();
+
+    let mut pattern_segments = pattern.split('/').filter(|s| !s.is_empty()).peekable();
+    while let Some(expected) = pattern_segments.next() {
+        if expected == "**" && pattern_segments.peek().is_none() {
+            return true;
+        }
+        match segments.next() {
+            Some(segment) if expected == "*" || expected == segment => {}
+            _ => return false,
+        }
+    }
+    segments.next().is_none()
+}
+
+/// Whether any segment of `path` is `.` or `..`, also percent-encoded. Such a
+/// path would be matched against rules as written but resolved upstream, so
+/// the gateway refuses it instead.
+pub fn has_dot_segments(path: &str) -> bool {
+    path.split('/').any(|segment| {
+        let decoded = urlencoding::decode(segment)
+            .map(|d| d.into_owned())
+            .unwrap_or_else(|_| segment.to_string());
+        matches!(decoded.as_str(), "." | "..")
+    })
+}
+
+/// Problems with a route's rules, for config validation
+pub(crate) fn validate(ctx: &str, route: &str, rules: &[AccessRule], errors: &mut Vec<String>) {
+    for (i, rule) in rules.iter().enumerate() {
+        let ctx = format!("{ctx}: access rule {} ('{}')", i + 1, rule.path);
+        if !crate::config::path_matches(route, &rule.path) {
+            errors.push(format!("{ctx}: path must be under the route"));
+        }
+        let segments: Vec<&str> = rule.path.split('/').collect();
+        for (j, segment) in segments.iter().enumerate() {
+            if *segment == "**" && j + 1 != segments.len() {
+                errors.push(format!("{ctx}: '**' is only allowed at the end"));
+            } else if segment.contains('*') && !matches!(*segment, "*" | "**") {
+                errors.push(format!("{ctx}: wildcards must be a whole segment"));
+            }
+        }
+        for method in &rule.methods {
+            if axum::http::Method::from_bytes(method.as_bytes()).is_err() {
+                errors.push(format!("{ctx}: '{method}' is not a valid method"));
+            }
+        }
+        if rule.min_role.is_none() && rule.scopes.is_empty() {
+            errors.push(format!("{ctx}: needs min_role or scopes"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(path: &str, methods: &[&str], min_role: Option<Role>, scopes: &[&str]) -> AccessRule {
+        AccessRule {
+            path: path.to_string(),
+            methods: methods.iter().map(|m| m.to_string()).collect(),
+            min_role,
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn path_matches_whole_segments() {
+        assert!(path_matches("/admin/users", "/admin/users"));
+        assert!(!path_matches("/admin/users", "/admin/users/1"));
+        assert!(!path_matches("/admin/users", "/admin/usersx"));
+        assert!(!path_matches("/admin/users", "/admin"));
+    }
+
+    #[test]
+    fn path_matches_wildcards() {
+        assert!(path_matches("/admin/users/*", "/admin/users/1"));
+        assert!(!path_matches("/admin/users/*", "/admin/users"));
+        assert!(!path_matches("/admin/users/*", "/admin/users/1/keys"));
+        assert!(path_matches("/admin/*/keys", "/admin/users/keys"));
+
+        assert!(path_matches("/admin/reports/**", "/admin/reports"));
+        assert!(path_matches("/admin/reports/**", "/admin/reports/2024/q1"));
+        assert!(!path_matches("/admin/reports/**", "/admin/other"));
+    }
+
+    #[test]
+    fn path_matches_normalised_variants() {
+        assert!(path_matches("/admin/users/*", "//admin///users/1"));
+        assert!(path_matches("/admin/users/*", "/admin/users/1/"));
+        assert!(path_matches("/admin/users/*", "/%61dmin/%75sers/1"));
+        // An encoded slash stays inside its segment
+        assert!(path_matches("/admin/users/*", "/admin/users/a%2Fb"));
+    }
+
+    #[test]
+    fn dot_segments_are_found_also_encoded() {
+        for path in [
+            "/admin/..",
+            "/admin/../internal",
+            "/admin/./users",
+            "/admin/%2e%2e/internal",
+            "/admin/%2E/users",
+            "/admin/.%2E/internal",
+            "/..",
+        ] {
+            assert!(has_dot_segments(path), "{path}");
+        }
+        for path in ["/admin/users", "/admin/.well-known", "/admin/...", "/admin/a..b", "/"] {
+            assert!(!has_dot_segments(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn check_applies_the_first_matching_rule() {
+        let rules = vec![
+            rule("/admin/organisations", &["POST"], Some(Role::SuperAdmin), &[]),
+            rule("/admin/reports/**", &[], None, &["reports:read"]),
+            rule("/admin/**", &[], Some(Role::Supervisor), &[]),
+        ];
+        let admin = Principal {
+            role: Role::Admin,
+            scopes: None,
+        };
+        let status = |method: &str, path: &str, principal: Option<&Principal>| {
+            check(&rules, method, path, principal).map_err(|res| res.status)
+        };
+
+        assert_eq!(status("GET", "/public", None), Ok(()));
+        assert_eq!(status("GET", "/admin/users", None), Err(401));
+        assert_eq!(status("GET", "/admin/organisations", Some(&admin)), Ok(()));
+        assert_eq!(status("POST", "/admin/organisations", Some(&admin)), Err(403));
+
+        let user = Principal {
+            role: Role::User,
+            scopes: None,
+        };
+        assert_eq!(status("GET", "/admin/users", Some(&user)), Err(403));
+        // Scopes only restrict API keys
+        assert_eq!(status("GET", "/admin/reports/q1", Some(&user)), Ok(()));
+
+        let scopes = ["reports:read".to_string()];
+        let key = Principal {
+            role: Role::User,
+            scopes: Some(&scopes),
+        };
+        let keyless = Principal {
+            role: Role::SuperAdmin,
+            scopes: Some(&[]),
+        };
+        assert_eq!(status("GET", "/admin/reports/q1", Some(&key)), Ok(()));
+        assert_eq!(status("GET", "/admin/reports/q1", Some(&keyless)), Err(403));
+    }
+
+    #[test]
+    fn get_rules_also_cover_head() {
+        let rules = vec![rule("/admin/users", &["GET"], Some(Role::Admin), &[])];
+        let user = Principal {
+            role: Role::User,
+            scopes: None,
+        };
+        let status = |method: &str, principal: Option<&Principal>| {
+            check(&rules, method, "/admin/users", principal).map_err(|res| res.status)
+        };
+
+        assert_eq!(status("HEAD", None), Err(401));
+        assert_eq!(status("head", Some(&user)), Err(403));
+        assert_eq!(status("POST", Some(&user)), Ok(()));
+        // HEAD rules don't reach GET
+        let rules = vec![rule("/admin/users", &["HEAD"], Some(Role::Admin), &[])];
+        assert_eq!(check(&rules, "GET", "/admin/users", Some(&user)).map_err(|r| r.status), Ok(()));
+    }
+
+    #[test]
+    fn gateway_admin_needs_role_and_scope_on_keys() {
+        let rules = vec![crate::health::admin_rule()];
+        let status = |principal: &Principal| {
+            check(&rules, "GET", "/_gateway/upstreams", Some(principal)).map_err(|res| res.status)
+        };
+        let scoped = ["gateway:admin".to_string()];
+
+        let super_admin = Principal {
+            role: Role::SuperAdmin,
+            scopes: None,
+        };
+        assert_eq!(status(&super_admin), Ok(()));
+        // An organisation's admin doesn't see the whole gateway
+        let org_admin = Principal {
+            role: Role::Admin,
+            scopes: None,
+        };
+        assert_eq!(status(&org_admin), Err(403));
+        let super_admin_key = Principal {
+            role: Role::SuperAdmin,
+            scopes: Some(&scoped),
+        };
+        assert_eq!(status(&super_admin_key), Ok(()));
+        let unscoped_key = Principal {
+            role: Role::SuperAdmin,
+            scopes: Some(&[]),
+        };
+        assert_eq!(status(&unscoped_key), Err(403));
+        // The scope alone doesn't lift a key above its role
+        let admin_key = Principal {
+            role: Role::Admin,
+            scopes: Some(&scoped),
+        };
+        assert_eq!(status(&admin_key), Err(403));
+    }
+}
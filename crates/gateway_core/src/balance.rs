@@ -0,0 +1,534 @@
+//! Load balancing across a route's upstreams.
+//!
+//! A proxied route can list several weighted upstreams. Each request goes to
+//! one of them, chosen by the route's `balance` strategy among the upstreams
+//! health checks haven't ejected:
+//!
+//! - `round_robin`: in turn, weighted (smooth weighted round robin)
+//! - `least_outstanding`: fewest in-flight requests relative to weight
+//! - `random_two_choices`: the less loaded of two weighted random picks
+//! - `consistent_hash`: by a header or cookie (`hash_on`), so the same key
+//!   keeps going to the same upstream while it is healthy; requests without
+//!   the key fall back to round robin
+//!
+//! Upstreams whose circuit is open are skipped as well.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+
+use crate::upstream::{InFlight, Upstream, UpstreamStatus};
+
+/// Points per unit of weight on the consistent hash ring
+const RING_POINTS_PER_WEIGHT: u32 = 100;
+
+/// Highest upstream weight; keeps the hash ring, rebuilt on every reload, at
+/// 100,000 points per upstream at most
+pub const MAX_WEIGHT: u32 = 1000;
+
+/// Why no upstream could take a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unavailable {
+    /// Health checks ejected every upstream
+    Unhealthy,
+    /// Circuits are open; the earliest one lets requests through after this
+    CircuitOpen(Duration),
+}
+
+/// How a route picks an upstream
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Balance {
+    #[default]
+    RoundRobin,
+    LeastOutstanding,
+    RandomTwoChoices,
+    ConsistentHash(HashOn),
+}
+
+/// Request attribute consistent hashing keys on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashOn {
+    Header(String),
+    Cookie(String),
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RoundRobin => f.write_str("round_robin"),
+            Self::LeastOutstanding => f.write_str("least_outstanding"),
+            Self::RandomTwoChoices => f.write_str("random_two_choices"),
+            Self::ConsistentHash(on) => write!(f, "consistent_hash on {on}"),
+        }
+    }
+}
+
+impl Balance {
+    /// Strategy by name; `hash_on` goes with (and only with) `consistent_hash`
+    pub fn parse(name: &str, hash_on: Option<&str>) -> Result<Self, String> {
+        let balance = match name.to_lowercase().replace('-', "_").as_str() {
+            "round_robin" => Self::RoundRobin,
+            "least_outstanding" | "least_request" => Self::LeastOutstanding,
+            "random_two_choices" | "p2c" => Self::RandomTwoChoices,
+            "consistent_hash" => match hash_on {
+                Some(on) => return Ok(Self::ConsistentHash(on.parse()?)),
+                None => return Err("consistent_hash needs hash_on".to_string()),
+            },
+            other => {
+                return Err(format!(
+                    "unknown balance '{other}' (expected round_robin, least_outstanding, random_two_choices or consistent_hash)"
+                ));
+            }
+        };
+        match hash_on {
+            Some(_) => Err(format!("hash_on only applies to consistent_hash, not {balance}")),
+            None => Ok(balance),
+        }
+    }
+}
+
+impl fmt::Display for HashOn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header(name) => write!(f, "header:{name}"),
+            Self::Cookie(name) => write!(f, "cookie:{name}"),
+        }
+    }
+}
+
+impl FromStr for HashOn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("header", name)) if !name.trim().is_empty() => {
+                Ok(Self::Header(name.trim().to_lowercase()))
+            }
+            Some(("cookie", name)) if !name.trim().is_empty() => {
+                Ok(Self::Cookie(name.trim().to_string()))
+            }
+            _ => Err(format!(
+                "invalid hash_on '{s}' (expected header:<name> or cookie:<name>)"
+            )),
+        }
+    }
+}
+
+struct Target {
+    upstream: Arc<Upstream>,
+    weight: u32,
+}
+
+/// The upstreams of one route and the state their strategy needs
+pub struct Pool {
+    targets: Vec<Target>,
+    balance: Balance,
+    /// Smooth weighted round robin: current weight per target
+    round_robin: Mutex<Vec<i64>>,
+    /// Consistent hash ring of (point, target index), sorted by point
+    ring: Vec<(u64, usize)>,
+}
+
+impl Pool {
+    pub fn new(upstreams: Vec<(Arc<Upstream>, u32)>, balance: Balance) -> Self {
+        let targets: Vec<Target> = upstreams
+            .into_iter()
+            .map(|(upstream, weight)| Target {
+                upstream,
+                weight: weight.clamp(1, MAX_WEIGHT),
+            })
+            .collect();
+
+        let mut ring = Vec::new();
+        if matches!(balance, Balance::ConsistentHash(_)) {
+            for (index, target) in targets.iter().enumerate() {
+                for point in 0..target.weight * RING_POINTS_PER_WEIGHT {
+                    ring.push((hash(format!("{}#{point}", target.upstream.url).as_bytes()), index));
+                }
+            }
+            ring.sort_unstable();
+        }
+
+        Self {
+            round_robin: Mutex::new(vec![0; targets.len()]),
+            targets,
+            balance,
+            ring,
+        }
+    }
+
+    pub fn upstreams(&self) -> impl Iterator<Item = &Arc<Upstream>> {
+        self.targets.iter().map(|t| &t.upstream)
+    }
+
+    pub fn status(&self) -> Vec<UpstreamStatus> {
+        self.targets
+            .iter()
+            .map(|t| UpstreamStatus {
+                weight: t.weight,
+                ..t.upstream.status()
+            })
+            .collect()
+    }
+
+    /// Start a request on an upstream picked for it, preferring ones not in
+    /// `tried` (earlier attempts of the same request)
+    pub fn pick(&self, headers: &HeaderMap, tried: &[Arc<Upstream>]) -> Result<InFlight, Unavailable> {
+        let mut wait: Option<Duration> = None;
+        let mut blocked = Vec::new();
+        let available: Vec<usize> = (0..self.targets.len())
+            .filter(|&i| self.targets[i].upstream.is_available())
+            .filter(|&i| {
+                let circuit_wait = self.targets[i].upstream.circuit().and_then(|c| c.wait());
+                if let Some(left) = circuit_wait {
+                    wait = Some(wait.map_or(left, |w| w.min(left)));
+                    blocked.push(i);
+                }
+                circuit_wait.is_none()
+            })
+            .collect();
+        if available.is_empty() {
+            let Some(wait) = wait else {
+                return Err(Unavailable::Unhealthy);
+            };
+            for i in blocked {
+                if let Some(circuit) = self.targets[i].upstream.circuit() {
+                    circuit.reject();
+                }
+            }
+            return Err(Unavailable::CircuitOpen(wait));
+        }
+        let (untried, retried): (Vec<usize>, Vec<usize>) = available
+            .into_iter()
+            .partition(|&i| !tried.iter().any(|t| Arc::ptr_eq(t, &self.targets[i].upstream)));
+        if untried.is_empty() {
+            self.start(retried, Vec::new(), headers)
+        } else {
+            self.start(untried, retried, headers)
+        }
+    }
+
+    /// Start a request on the target chosen among `candidates`, then `rest`.
+    /// A circuit can run out of half-open trials between the check in
+    /// `pick` and here, when another request takes the last one; the next
+    /// choice is tried instead of failing the request.
+    fn start(
+        &self,
+        mut candidates: Vec<usize>,
+        mut rest: Vec<usize>,
+        headers: &HeaderMap,
+    ) -> Result<InFlight, Unavailable> {
+        loop {
+            let index = self.choose(&candidates, headers);
+            let wait = match self.targets[index].upstream.start_request() {
+                Ok(in_flight) => return Ok(in_flight),
+                Err(wait) => wait,
+            };
+            candidates.retain(|&i| i != index);
+            if candidates.is_empty() {
+                if rest.is_empty() {
+                    return Err(Unavailable::CircuitOpen(wait));
+                }
+                candidates = std::mem::take(&mut rest);
+            }
+        }
+    }
+
+    fn choose(&self, available: &[usize], headers: &HeaderMap) -> usize {
+        if available.len() == 1 {
+            return available[0];
+        }
+        match &self.balance {
+            Balance::RoundRobin => self.round_robin(available),
+            Balance::LeastOutstanding => self.least_outstanding(available),
+            Balance::RandomTwoChoices => self.random_two_choices(available),
+            Balance::ConsistentHash(on) => match hash_key(on, headers) {
+                Some(key) => self.consistent_hash(&key, available),
+                None => self.round_robin(available),
+            },
+        }
+    }
+
+    fn round_robin(&self, available: &[usize]) -> usize {
+        let mut current = self.round_robin.lock().unwrap_or_else(|e| e.into_inner());
+        let total: i64 = available.iter().map(|&i| self.targets[i].weight as i64).sum();
+        let mut best = available[0];
+        for &i in available {
+            current[i] += self.targets[i].weight as i64;
+            if current[i] > current[best] {
+                best = i;
+            }
+        }
+        current[best] -= total;
+        best
+    }
+
+    /// Lowest in-flight count per unit of weight, starting the scan at a
+    /// random target so ties are spread out
+    fn least_outstanding(&self, available: &[usize]) -> usize {
+        let offset = fastrand::usize(..available.len());
+        let mut best = available[offset];
+        for &i in available.iter().cycle().skip(offset).take(available.len()) {
+            if self.less_loaded(i, best) {
+                best = i;
+            }
+        }
+        best
+    }
+
+    fn random_two_choices(&self, available: &[usize]) -> usize {
+        let first = self.weighted_random(available, None);
+        let second = self.weighted_random(available, Some(first));
+        if self.less_loaded(second, first) { second } else { first }
+    }
+
+    fn weighted_random(&self, available: &[usize], except: Option<usize>) -> usize {
+        let candidates: Vec<usize> = available.iter().copied().filter(|&i| Some(i) != except).collect();
+        let total: u64 = candidates.iter().map(|&i| self.targets[i].weight as u64).sum();
+        let mut point = fastrand::u64(..total);
+        for &i in &candidates {
+            let weight = self.targets[i].weight as u64;
+            if point < weight {
+                return i;
+            }
+            point -= weight;
+        }
+        candidates[candidates.len() - 1]
+    }
+
+    /// Whether `a` has fewer in-flight requests per unit of weight than `b`
+    fn less_loaded(&self, a: usize, b: usize) -> bool {
+        let (a, b) = (&self.targets[a], &self.targets[b]);
+        (a.upstream.outstanding() as u64) * (b.weight as u64)
+            < (b.upstream.outstanding() as u64) * (a.weight as u64)
+    }
+
+    /// First available target clockwise from the key's point on the ring
+    fn consistent_hash(&self, key: &str, available: &[usize]) -> usize {
+        let point = hash(key.as_bytes());
+        let start = self.ring.partition_point(|&(p, _)| p < point);
+        self.ring
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(self.ring.len())
+            .map(|&(_, index)| index)
+            .find(|index| available.contains(index))
+            .unwrap_or(available[0])
+    }
+}
+
+fn hash_key(on: &HashOn, headers: &HeaderMap) -> Option<String> {
+    match on {
+        HashOn::Header(name) => headers
+            .get(name.as_str())
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .map(str::to_string),
+        HashOn::Cookie(name) => headers
+            .get_all("cookie")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.to_string()),
+    }
+}
+
+/// FNV-1a followed by the murmur3 finalizer, which spreads short keys over the
+/// whole ring. Stable across processes, so every gateway replica agrees.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use axum::http::HeaderValue;
+
+    use super::*;
+    use crate::circuit::CircuitBreakerConfig;
+    use crate::upstream::HealthCheckConfig;
+
+    /// An upstream ejected by one failed request, whose circuit opens on any
+    /// request that completes (every call counts as slow)
+    fn upstream(url: &str) -> Arc<Upstream> {
+        let checks = HealthCheckConfig {
+            interval: None,
+            max_failures: 1,
+            ejection: Duration::from_secs(600),
+            ..HealthCheckConfig::default()
+        };
+        let circuit = CircuitBreakerConfig {
+            min_requests: 1,
+            slow_call: Some(Duration::ZERO),
+            cool_down: Duration::from_secs(600),
+            ..CircuitBreakerConfig::default()
+        };
+        Arc::new(Upstream::new(url, "/readyz", Some(checks), Some(circuit)))
+    }
+
+    fn pool(weights: &[u32], balance: Balance) -> Pool {
+        let upstreams = weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| (upstream(&format!("http://u{i}")), weight))
+            .collect();
+        Pool::new(upstreams, balance)
+    }
+
+    fn pick(pool: &Pool, headers: &HeaderMap) -> String {
+        pool.pick(headers, &[]).expect("an upstream").upstream().url.clone()
+    }
+
+    fn eject(pool: &Pool, index: usize) {
+        let upstream = &pool.targets[index].upstream;
+        upstream.start_request().unwrap().finish(Err("connection refused"));
+        assert!(!upstream.is_available());
+    }
+
+    fn open_circuit(pool: &Pool, index: usize) {
+        let upstream = &pool.targets[index].upstream;
+        upstream.start_request().unwrap().finish(Ok(200));
+        assert!(upstream.is_available());
+        assert!(upstream.circuit().unwrap().wait().is_some());
+    }
+
+    fn user(id: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-user-id", HeaderValue::from_str(id).unwrap());
+        headers
+    }
+
+    fn consistent_hash() -> Balance {
+        Balance::parse("consistent_hash", Some("header:x-user-id")).unwrap()
+    }
+
+    #[test]
+    fn weights_are_capped() {
+        let pool = pool(&[0, 5, 1_000_000], consistent_hash());
+        let weights: Vec<u32> = pool.status().iter().map(|s| s.weight).collect();
+        assert_eq!(weights, [1, 5, MAX_WEIGHT]);
+        assert_eq!(
+            pool.ring.len(),
+            ((1 + 5 + MAX_WEIGHT) * RING_POINTS_PER_WEIGHT) as usize
+        );
+    }
+
+    #[test]
+    fn round_robin_is_smooth_and_weighted() {
+        let pool = pool(&[5, 1, 1], Balance::RoundRobin);
+        let headers = HeaderMap::new();
+        let order: Vec<String> = (0..7).map(|_| pick(&pool, &headers)).collect();
+        // The heavy upstream is spread out rather than picked five times in a row
+        assert_eq!(
+            order,
+            ["u0", "u0", "u1", "u0", "u2", "u0", "u0"].map(|u| format!("http://{u}"))
+        );
+
+        let pool = self::pool(&[3, 2, 1], Balance::RoundRobin);
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for _ in 0..600 {
+            *counts.entry(pick(&pool, &headers)).or_default() += 1;
+        }
+        assert_eq!(counts["http://u0"], 300);
+        assert_eq!(counts["http://u1"], 200);
+        assert_eq!(counts["http://u2"], 100);
+    }
+
+    #[test]
+    fn ejected_and_open_upstreams_are_skipped() {
+        for balance in [
+            Balance::RoundRobin,
+            Balance::LeastOutstanding,
+            Balance::RandomTwoChoices,
+            consistent_hash(),
+        ] {
+            let pool = pool(&[1, 1, 1], balance.clone());
+            eject(&pool, 0);
+            open_circuit(&pool, 1);
+            for i in 0..50 {
+                assert_eq!(pick(&pool, &user(&i.to_string())), "http://u2", "{balance}");
+            }
+
+            eject(&pool, 2);
+            match pool.pick(&HeaderMap::new(), &[]) {
+                Err(Unavailable::CircuitOpen(wait)) => assert!(wait <= Duration::from_secs(600)),
+                other => panic!("{balance}: expected open circuit, got {:?}", other.map(|_| ())),
+            }
+        }
+
+        let pool = pool(&[1, 1], Balance::RoundRobin);
+        eject(&pool, 0);
+        eject(&pool, 1);
+        assert_eq!(pool.pick(&HeaderMap::new(), &[]).err(), Some(Unavailable::Unhealthy));
+    }
+
+    #[test]
+    fn circuit_opening_after_the_check_falls_through_to_the_next_upstream() {
+        let pool = pool(&[1, 1], Balance::RoundRobin);
+        let headers = HeaderMap::new();
+        // u0 passed `pick`'s check, then its circuit opened before the start
+        open_circuit(&pool, 0);
+        let in_flight = pool.start(vec![0, 1], Vec::new(), &headers).unwrap();
+        assert_eq!(in_flight.upstream().url, "http://u1");
+        drop(in_flight);
+
+        // Tried upstreams are the fallback once the untried ones are out
+        let in_flight = pool.start(vec![0], vec![1], &headers).unwrap();
+        assert_eq!(in_flight.upstream().url, "http://u1");
+        drop(in_flight);
+
+        assert!(matches!(
+            pool.start(vec![0], Vec::new(), &headers),
+            Err(Unavailable::CircuitOpen(_))
+        ));
+    }
+
+    #[test]
+    fn consistent_hash_keeps_keys_on_their_upstream() {
+        let pool = pool(&[1, 2, 1], consistent_hash());
+        let keys: Vec<String> = (0..200).map(|i| format!("user-{i}")).collect();
+        let placed: Vec<String> = keys.iter().map(|k| pick(&pool, &user(k))).collect();
+
+        // Every key sticks, and a rebuilt ring (another replica, a reload)
+        // agrees
+        let rebuilt = self::pool(&[1, 2, 1], consistent_hash());
+        for (key, upstream) in keys.iter().zip(&placed) {
+            assert_eq!(&pick(&pool, &user(key)), upstream);
+            assert_eq!(&pick(&rebuilt, &user(key)), upstream);
+        }
+        for upstream in ["http://u0", "http://u1", "http://u2"] {
+            assert!(placed.iter().any(|p| p == upstream), "{upstream} got no keys");
+        }
+
+        // Only the keys of an ejected upstream move
+        eject(&pool, 1);
+        for (key, upstream) in keys.iter().zip(&placed) {
+            let now = pick(&pool, &user(key));
+            if upstream == "http://u1" {
+                assert_ne!(now, "http://u1");
+            } else {
+                assert_eq!(&now, upstream);
+            }
+        }
+
+        // Requests without the key fall back to round robin
+        let fallback: Vec<String> = (0..3).map(|_| pick(&rebuilt, &HeaderMap::new())).collect();
+        assert_eq!(fallback, ["http://u1", "http://u0", "http://u2"]);
+    }
+}
@@ -0,0 +1,399 @@
+//! Circuit breaking for upstreams.
+//!
+//! Each upstream of a route with a `circuit_breaker` counts its requests over
+//! a `window`. A request fails when it gets no response, a 5xx, or takes
+//! longer than `slow_call` to start answering. Once `min_requests` have been
+//! seen and at least `failure_rate` of them failed, the circuit **opens**:
+//! requests are answered with a 503 and `Retry-After` straight away instead
+//! of waiting on the upstream. After `cool_down` it goes **half-open** and
+//! lets `half_open_requests` trial requests through; if they all pass it
+//! **closes** again, and any failure opens it for another `cool_down`.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Circuit breaker settings for a proxied route
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// Share of failed requests in the window that opens the circuit (0-1)
+    pub failure_rate: f64,
+    /// Requests the window needs before the failure rate counts
+    pub min_requests: u32,
+    /// Length of the window requests are counted over
+    pub window: Duration,
+    /// Requests slower than this to answer count as failed; `None` only
+    /// counts errors
+    pub slow_call: Option<Duration>,
+    /// How long the circuit stays open before trial requests are let through
+    pub cool_down: Duration,
+    /// Trial requests that must pass in a row to close the circuit again
+    pub half_open_requests: u32,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_rate: 0.5,
+            min_requests: 20,
+            window: Duration::from_secs(10),
+            slow_call: None,
+            cool_down: Duration::from_secs(30),
+            half_open_requests: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// Circuit state and counters, as shown by the gateway's upstream status endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    /// Requests and failures in the current window
+    pub requests: u32,
+    pub failures: u32,
+    /// Times the circuit has opened
+    pub opened_total: u64,
+    /// Requests turned away while it was open
+    pub rejected_total: u64,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// A request let through by [`CircuitBreaker::acquire`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permit {
+    /// Half-open trial request
+    pub trial: bool,
+    /// Half-open cycle the request was let through in; outcomes of trials
+    /// from an earlier cycle no longer count
+    cycle: u64,
+}
+
+pub struct CircuitBreaker {
+    url: String,
+    config: CircuitBreakerConfig,
+    state: Mutex<Circuit>,
+}
+
+struct Circuit {
+    state: CircuitState,
+    window_start: Instant,
+    requests: u32,
+    failures: u32,
+    /// When an open circuit goes half-open
+    open_until: Instant,
+    /// Half-open: trial requests in flight and passed so far
+    trials: u32,
+    passed: u32,
+    /// Bumped each time the circuit goes half-open
+    cycle: u64,
+    opened_total: u64,
+    rejected_total: u64,
+    changed_at: DateTime<Utc>,
+}
+
+impl CircuitBreaker {
+    pub fn new(url: impl Into<String>, config: CircuitBreakerConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            url: url.into(),
+            config,
+            state: Mutex::new(Circuit {
+                state: CircuitState::Closed,
+                window_start: now,
+                requests: 0,
+                failures: 0,
+                open_until: now,
+                trials: 0,
+                passed: 0,
+                cycle: 0,
+                opened_total: 0,
+                rejected_total: 0,
+                changed_at: Utc::now(),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
+    fn circuit(&self) -> std::sync::MutexGuard<'_, Circuit> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// How long until the circuit lets a request through; `None` if it would now
+    pub fn wait(&self) -> Option<Duration> {
+        let circuit = self.circuit();
+        match circuit.state {
+            CircuitState::Closed => None,
+            CircuitState::Open => {
+                let left = circuit.open_until.saturating_duration_since(Instant::now());
+                (!left.is_zero()).then_some(left)
+            }
+            // Trials are short; ask the client to come back after a second
+            CircuitState::HalfOpen => (circuit.trials >= self.config.half_open_requests)
+                .then_some(Duration::from_secs(1)),
+        }
+    }
+
+    /// Let a request through. Returns its permit, saying whether it is a
+    /// half-open trial, or how long to wait while the circuit is open.
+    pub fn acquire(&self) -> Result<Permit, Duration> {
+        let mut circuit = self.circuit();
+        if circuit.state == CircuitState::Open {
+            let left = circuit.open_until.saturating_duration_since(Instant::now());
+            if !left.is_zero() {
+                circuit.rejected_total += 1;
+                return Err(left);
+            }
+            println!(
+                "[gateway] circuit for upstream {} half-open: letting {} trial requests through",
+                self.url, self.config.half_open_requests
+            );
+            circuit.state = CircuitState::HalfOpen;
+            circuit.trials = 0;
+            circuit.passed = 0;
+            circuit.cycle += 1;
+            circuit.changed_at = Utc::now();
+        }
+        let cycle = circuit.cycle;
+        match circuit.state {
+            CircuitState::HalfOpen if circuit.trials >= self.config.half_open_requests => {
+                circuit.rejected_total += 1;
+                Err(Duration::from_secs(1))
+            }
+            CircuitState::HalfOpen => {
+                circuit.trials += 1;
+                Ok(Permit { trial: true, cycle })
+            }
+            _ => Ok(Permit {
+                trial: false,
+                cycle,
+            }),
+        }
+    }
+
+    /// Count a request turned away because no upstream would take it
+    pub fn reject(&self) {
+        self.circuit().rejected_total += 1;
+    }
+
+    /// Record how a request let through by `acquire` went
+    pub fn record(&self, permit: Permit, failed: bool, latency: Duration) {
+        let failed = failed || self.config.slow_call.is_some_and(|slow| latency >= slow);
+        let mut circuit = self.circuit();
+        match circuit.state {
+            CircuitState::HalfOpen if permit.trial && permit.cycle == circuit.cycle => {
+                circuit.trials = circuit.trials.saturating_sub(1);
+                if failed {
+                    self.open(&mut circuit, "trial request failed");
+                    return;
+                }
+                circuit.passed += 1;
+                if circuit.passed >= self.config.half_open_requests {
+                    println!(
+                        "[gateway] circuit for upstream {} closed: {} trial requests passed",
+                        self.url, circuit.passed
+                    );
+                    circuit.state = CircuitState::Closed;
+                    circuit.window_start = Instant::now();
+                    circuit.requests = 0;
+                    circuit.failures = 0;
+                    circuit.changed_at = Utc::now();
+                }
+            }
+            CircuitState::Closed => {
+                if circuit.window_start.elapsed() >= self.config.window {
+                    circuit.window_start = Instant::now();
+                    circuit.requests = 0;
+                    circuit.failures = 0;
+                }
+                circuit.requests += 1;
+                if failed {
+                    circuit.failures += 1;
+                }
+                if circuit.requests >= self.config.min_requests
+                    && circuit.failures as f64 >= circuit.requests as f64 * self.config.failure_rate
+                {
+                    let reason = format!(
+                        "{} of {} requests failed within {:?}",
+                        circuit.failures, circuit.requests, self.config.window
+                    );
+                    self.open(&mut circuit, &reason);
+                }
+            }
+            // Requests that started before the circuit opened, and trials
+            // from an earlier half-open cycle
+            _ => {}
+        }
+    }
+
+    /// A request let through by `acquire` ended without an outcome, e.g. the
+    /// client went away; frees its trial slot
+    pub fn release(&self, permit: Permit) {
+        let mut circuit = self.circuit();
+        if permit.trial && permit.cycle == circuit.cycle && circuit.state == CircuitState::HalfOpen
+        {
+            circuit.trials = circuit.trials.saturating_sub(1);
+        }
+    }
+
+    fn open(&self, circuit: &mut Circuit, reason: &str) {
+        eprintln!(
+            "[gateway] circuit for upstream {} opened for {:?}: {reason}",
+            self.url, self.config.cool_down
+        );
+        circuit.state = CircuitState::Open;
+        circuit.open_until = Instant::now() + self.config.cool_down;
+        circuit.opened_total += 1;
+        circuit.trials = 0;
+        circuit.passed = 0;
+        circuit.changed_at = Utc::now();
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        let circuit = self.circuit();
+        // An open circuit whose cool-down is over goes half-open on the next request
+        let state = match circuit.state {
+            CircuitState::Open if circuit.open_until <= Instant::now() => CircuitState::HalfOpen,
+            state => state,
+        };
+        CircuitStatus {
+            state,
+            requests: circuit.requests,
+            failures: circuit.failures,
+            opened_total: circuit.opened_total,
+            rejected_total: circuit.rejected_total,
+            changed_at: circuit.changed_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A circuit that opens on the first failure, goes half-open straight
+    /// away and closes after two passed trials
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(
+            "http://upstream",
+            CircuitBreakerConfig {
+                min_requests: 1,
+                cool_down: Duration::ZERO,
+                half_open_requests: 2,
+                ..CircuitBreakerConfig::default()
+            },
+        )
+    }
+
+    fn state(breaker: &CircuitBreaker) -> CircuitState {
+        breaker.circuit().state
+    }
+
+    fn open(breaker: &CircuitBreaker) {
+        let permit = breaker.acquire().unwrap();
+        assert!(!permit.trial);
+        breaker.record(permit, true, Duration::ZERO);
+        assert_eq!(state(breaker), CircuitState::Open);
+    }
+
+    #[test]
+    fn opens_on_failures() {
+        let breaker = CircuitBreaker::new(
+            "http://upstream",
+            CircuitBreakerConfig {
+                min_requests: 4,
+                ..CircuitBreakerConfig::default()
+            },
+        );
+        for failed in [true, false, true] {
+            breaker.record(breaker.acquire().unwrap(), failed, Duration::ZERO);
+        }
+        assert_eq!(state(&breaker), CircuitState::Closed);
+        breaker.record(breaker.acquire().unwrap(), false, Duration::ZERO);
+        assert_eq!(state(&breaker), CircuitState::Open);
+        assert!(breaker.acquire().is_err());
+        assert_eq!(breaker.status().rejected_total, 1);
+    }
+
+    #[test]
+    fn half_open_closes_after_passed_trials() {
+        let breaker = breaker();
+        open(&breaker);
+        let first = breaker.acquire().unwrap();
+        let second = breaker.acquire().unwrap();
+        assert!(first.trial && second.trial);
+        assert_eq!(state(&breaker), CircuitState::HalfOpen);
+        // Both trial slots are taken
+        assert!(breaker.acquire().is_err());
+
+        breaker.record(first, false, Duration::ZERO);
+        assert_eq!(state(&breaker), CircuitState::HalfOpen);
+        breaker.record(second, false, Duration::ZERO);
+        assert_eq!(state(&breaker), CircuitState::Closed);
+        assert!(!breaker.acquire().unwrap().trial);
+    }
+
+    #[test]
+    fn failed_trial_reopens() {
+        let breaker = breaker();
+        open(&breaker);
+        let trial = breaker.acquire().unwrap();
+        breaker.record(trial, true, Duration::ZERO);
+        assert_eq!(state(&breaker), CircuitState::Open);
+        assert_eq!(breaker.status().opened_total, 2);
+    }
+
+    #[test]
+    fn released_trial_frees_its_slot() {
+        let breaker = breaker();
+        open(&breaker);
+        let first = breaker.acquire().unwrap();
+        let _second = breaker.acquire().unwrap();
+        assert!(breaker.acquire().is_err());
+        breaker.release(first);
+        assert!(breaker.acquire().unwrap().trial);
+    }
+
+    #[test]
+    fn late_trial_from_earlier_cycle_is_ignored() {
+        let breaker = breaker();
+        open(&breaker);
+        let a = breaker.acquire().unwrap();
+        let b = breaker.acquire().unwrap();
+        // A fails and reopens the circuit while B is still in flight
+        breaker.record(a, true, Duration::ZERO);
+        assert_eq!(state(&breaker), CircuitState::Open);
+
+        // The next cycle lets C through, then the stale B comes back
+        let c = breaker.acquire().unwrap();
+        assert!(c.trial);
+        breaker.record(b, false, Duration::ZERO);
+        assert_eq!(breaker.circuit().trials, 1);
+        assert_eq!(breaker.circuit().passed, 0);
+        breaker.release(b);
+        assert_eq!(breaker.circuit().trials, 1);
+
+        breaker.record(c, false, Duration::ZERO);
+        assert_eq!(breaker.circuit().trials, 0);
+        assert_eq!(state(&breaker), CircuitState::HalfOpen);
+        // Both trial slots of the cycle are free again
+        let d = breaker.acquire().unwrap();
+        let e = breaker.acquire().unwrap();
+        breaker.record(d, false, Duration::ZERO);
+        assert_eq!(state(&breaker), CircuitState::Closed);
+        breaker.record(e, false, Duration::ZERO);
+        assert_eq!(state(&breaker), CircuitState::Closed);
+    }
+}
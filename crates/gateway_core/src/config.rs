@@ -1,4 +1,24 @@
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use ipnet::IpNet;
+
+use crate::authz::AccessRule;
+use crate::balance::{Balance, MAX_WEIGHT};
+use crate::circuit::CircuitBreakerConfig;
+use crate::config_file;
+use crate::middleware::MiddlewareKind;
+use crate::rate_limit::RateLimitConfig;
+use crate::retry::{RetryBudgetConfig, RetryPolicy};
+use crate::upstream::HealthCheckConfig;
+use crate::wasm::PluginConfig;
+
+pub(crate) const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:8080";
+pub(crate) const DEFAULT_RATE_LIMIT_PER_MINUTE: u32 = 100;
+pub(crate) const DEFAULT_HEALTH_PATH: &str = "/readyz";
 
 /// Mode for handling a route - either embed the handler or proxy to upstream
 #[derive(Debug, Clone, PartialEq)]
@@ -9,43 +29,140 @@ pub enum RouteMode {
     Proxy,
 }
 
-impl RouteMode {
-    pub fn from_str(s: &str) -> Self {
+impl FromStr for RouteMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
-            "proxy" => RouteMode::Proxy,
-            _ => RouteMode::Embedded,
+            "proxy" => Ok(RouteMode::Proxy),
+            "embedded" => Ok(RouteMode::Embedded),
+            other => Err(format!("unknown mode '{other}' (expected proxy or embedded)")),
+        }
+    }
+}
+
+/// Upstream timeouts for a proxied route (`None` means no limit)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteTimeouts {
+    /// Time allowed to establish the upstream connection
+    pub connect: Option<Duration>,
+    /// Time allowed for the whole exchange, across all attempts and up to
+    /// the end of the response body
+    pub request: Option<Duration>,
+    /// Time allowed until the upstream's response headers arrive
+    pub first_byte: Option<Duration>,
+    /// Time allowed between chunks of the response body
+    pub idle: Option<Duration>,
+    /// Time an upgraded connection (e.g. a WebSocket) may go without data in
+    /// either direction; `None` uses `upgrade::DEFAULT_UPGRADE_IDLE`
+    pub upgrade_idle: Option<Duration>,
+}
+
+/// One upstream of a proxied route
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamTarget {
+    pub url: String,
+    /// Share of the route's traffic relative to its other upstreams
+    pub weight: u32,
+}
+
+impl UpstreamTarget {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            weight: 1,
         }
     }
 }
 
 /// Configuration for a single route
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct RouteConfig {
     pub mode: RouteMode,
-    pub upstream_base: String,
+    /// Upstreams requests are balanced across when the route is proxied
+    pub upstreams: Vec<UpstreamTarget>,
+    /// How an upstream is picked for each request
+    pub balance: Balance,
+    /// Remove the route prefix from the path before forwarding upstream
+    pub strip_prefix: bool,
+    /// Reject requests without valid credentials
+    pub auth: bool,
+    /// Service the caller's identity is signed for. `None` forwards no
+    /// identity headers, so only services that verify them get any.
+    pub identity_audience: Option<String>,
+    /// Middleware steps run for this route, in order
+    pub middleware: Vec<MiddlewareKind>,
+    pub timeouts: RouteTimeouts,
+    /// Route-specific rate limit; falls back to `GatewayConfig::rate_limit`
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Role/scope requirements for paths under the route, checked by `auth`
+    pub access: Vec<AccessRule>,
+    /// Upstream path probed by active health checks
+    pub health_path: String,
+    /// Active and passive upstream health checks; `None` disables both
+    pub health_check: Option<HealthCheckConfig>,
+    /// Circuit breaker per upstream; `None` disables it
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Retries of failed upstream requests; `None` makes a single attempt
+    pub retry: Option<RetryPolicy>,
 }
 
 impl RouteConfig {
     pub fn embedded() -> Self {
         Self {
             mode: RouteMode::Embedded,
-            upstream_base: String::new(),
+            upstreams: Vec::new(),
+            balance: Balance::default(),
+            strip_prefix: true,
+            auth: true,
+            identity_audience: None,
+            middleware: MiddlewareKind::defaults(),
+            timeouts: RouteTimeouts::default(),
+            rate_limit: None,
+            access: Vec::new(),
+            health_path: DEFAULT_HEALTH_PATH.to_string(),
+            health_check: None,
+            circuit_breaker: None,
+            retry: None,
         }
     }
 
     pub fn proxy(upstream_base: impl Into<String>) -> Self {
         Self {
             mode: RouteMode::Proxy,
-            upstream_base: upstream_base.into(),
+            upstreams: vec![UpstreamTarget::new(upstream_base)],
+            ..Self::embedded()
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct GatewayConfig {
     pub listen_addr: String,
     /// Route configurations keyed by base path (e.g., "/admin", "/auth")
     pub routes: HashMap<String, RouteConfig>,
+    /// Default rate limit for routes without their own (`None` disables it)
+    pub rate_limit: Option<RateLimitConfig>,
+    /// WASM plugins by name, referenced from route middleware as `wasm:<name>`
+    pub plugins: HashMap<String, PluginConfig>,
+    /// Headers removed from every inbound request on top of
+    /// `common::identity::IDENTITY_HEADERS`, which are always removed
+    pub identity_headers: Vec<String>,
+    /// Shared secret used to sign identity headers for backend services
+    pub identity_secret: String,
+    /// Limit on retries across all routes
+    pub retry_budget: RetryBudgetConfig,
+    /// Time any request may take until its response starts (`None` means no
+    /// limit)
+    pub request_timeout: Option<Duration>,
+    /// Peers (IPs or CIDR ranges) whose `x-forwarded-for` is believed when
+    /// working out the client address; empty ignores the header
+    pub trusted_proxies: Vec<String>,
+}
+
+/// Extra reserved headers stripped from inbound requests by default
+pub fn default_identity_headers() -> Vec<String> {
+    vec!["x-gateway".to_string()]
 }
 
 impl Default for GatewayConfig {
@@ -53,42 +170,257 @@ impl Default for GatewayConfig {
         let mut routes = HashMap::new();
 
         // Admin route configuration
-        let admin_mode = std::env::var("GATEWAY_ADMIN_MODE")
-            .map(|s| RouteMode::from_str(&s))
-            .unwrap_or(RouteMode::Embedded);
-        let admin_upstream = std::env::var("GATEWAY_ADMIN_UPSTREAM")
-            .unwrap_or_else(|_| "http://localhost:4001".to_string());
         routes.insert(
             "/admin".to_string(),
             RouteConfig {
-                mode: admin_mode,
-                upstream_base: admin_upstream,
+                upstreams: vec![UpstreamTarget::new("http://localhost:4001")],
+                identity_audience: Some(common::identity::ADMIN_AUDIENCE.to_string()),
+                ..RouteConfig::embedded()
             },
         );
 
-        // Auth route configuration
-        let auth_mode = std::env::var("GATEWAY_AUTH_MODE")
-            .map(|s| RouteMode::from_str(&s))
-            .unwrap_or(RouteMode::Embedded);
-        let auth_upstream = std::env::var("GATEWAY_AUTH_UPSTREAM")
-            .unwrap_or_else(|_| "http://localhost:4002".to_string());
+        // Auth route configuration (login, register, etc. are public)
         routes.insert(
             "/auth".to_string(),
             RouteConfig {
-                mode: auth_mode,
-                upstream_base: auth_upstream,
+                upstreams: vec![UpstreamTarget::new("http://localhost:4002")],
+                auth: false,
+                ..RouteConfig::embedded()
             },
         );
 
-        Self {
-            listen_addr: std::env::var("GATEWAY_LISTEN_ADDR")
-                .unwrap_or_else(|_| "0.0.0.0:8080".to_string()),
+        let mut config = Self {
+            listen_addr: DEFAULT_LISTEN_ADDR.to_string(),
             routes,
-        }
+            rate_limit: Some(RateLimitConfig::per_minute(DEFAULT_RATE_LIMIT_PER_MINUTE)),
+            plugins: HashMap::new(),
+            identity_headers: default_identity_headers(),
+            identity_secret: common::identity::DEFAULT_IDENTITY_SECRET.to_string(),
+            retry_budget: RetryBudgetConfig::default(),
+            request_timeout: None,
+            trusted_proxies: Vec::new(),
+        };
+        config.apply_env_overrides();
+        config
     }
 }
 
 impl GatewayConfig {
+    /// Load configuration from the file named by `GATEWAY_CONFIG`, or fall back
+    /// to the env-driven defaults when it is not set
+    pub fn load() -> anyhow::Result<Self> {
+        match std::env::var("GATEWAY_CONFIG") {
+            Ok(path) if !path.is_empty() => Self::from_file(path),
+            _ => {
+                let config = Self::default();
+                config.validate()?;
+                Ok(config)
+            }
+        }
+    }
+
+    /// Load a TOML or YAML route table (chosen by file extension).
+    /// Env vars are applied on top of the file, then the result is validated.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut config = config_file::load(path.as_ref())?;
+        config.apply_env_overrides();
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Apply `GATEWAY_*` env vars on top of the current values.
+    ///
+    /// Per-route overrides use the route path upper-cased, e.g. `/admin` reads
+    /// `GATEWAY_ADMIN_MODE` and `GATEWAY_ADMIN_UPSTREAM`. The upstream may be
+    /// a comma-separated list of equally weighted replicas.
+    pub fn apply_env_overrides(&mut self) {
+        if let Ok(addr) = std::env::var("GATEWAY_LISTEN_ADDR") {
+            self.listen_addr = addr;
+        }
+        if let Ok(headers) = std::env::var("GATEWAY_IDENTITY_HEADERS") {
+            self.identity_headers = headers
+                .split(',')
+                .map(|h| h.trim().to_lowercase())
+                .filter(|h| !h.is_empty())
+                .collect();
+        }
+        if let Ok(secret) = std::env::var("GATEWAY_IDENTITY_SECRET") {
+            self.identity_secret = secret;
+        }
+        if let Ok(proxies) = std::env::var("GATEWAY_TRUSTED_PROXIES") {
+            self.trusted_proxies = proxies
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(secs) = std::env::var("GATEWAY_REQUEST_TIMEOUT_SECS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+        {
+            self.request_timeout = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+
+        for (route, route_config) in self.routes.iter_mut() {
+            let name = env_name(route);
+            if let Some(Ok(mode)) = std::env::var(format!("GATEWAY_{name}_MODE"))
+                .ok()
+                .map(|mode| mode.parse())
+            {
+                route_config.mode = mode;
+            }
+            if let Ok(upstream) = std::env::var(format!("GATEWAY_{name}_UPSTREAM")) {
+                route_config.upstreams = upstream
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())
+                    .map(UpstreamTarget::new)
+                    .collect();
+            }
+            if let Ok(balance) = std::env::var(format!("GATEWAY_{name}_BALANCE")) {
+                let hash_on = std::env::var(format!("GATEWAY_{name}_HASH_ON")).ok();
+                if let Ok(balance) = Balance::parse(&balance, hash_on.as_deref()) {
+                    route_config.balance = balance;
+                }
+            }
+        }
+
+        if let Some(limit) = std::env::var("GATEWAY_RATE_LIMIT_PER_MINUTE")
+            .ok()
+            .and_then(|s| s.parse::<u32>().ok())
+        {
+            self.rate_limit = match (limit, self.rate_limit.take()) {
+                (0, _) => None,
+                (limit, Some(mut existing)) => {
+                    existing.limit = limit;
+                    existing.window = Duration::from_secs(60);
+                    Some(existing)
+                }
+                (limit, None) => Some(RateLimitConfig::per_minute(limit)),
+            };
+        }
+        if let Some(rate_limit) = self.rate_limit.as_mut() {
+            if let Some(algorithm) = std::env::var("GATEWAY_RATE_LIMIT_ALGORITHM")
+                .ok()
+                .and_then(|s| s.parse().ok())
+            {
+                rate_limit.algorithm = algorithm;
+            }
+            if let Some(key) = std::env::var("GATEWAY_RATE_LIMIT_KEY")
+                .ok()
+                .and_then(|s| s.parse().ok())
+            {
+                rate_limit.key = key;
+            }
+        }
+    }
+
+    /// Check the configuration for mistakes, reporting every problem found
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let errors = self.validation_errors();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("invalid gateway config:\n  - {}", errors.join("\n  - "))
+        }
+    }
+
+    pub(crate) fn validation_errors(&self) -> Vec<String> {
+        let mut errors = self.env_override_errors();
+
+        let port = self
+            .listen_addr
+            .rsplit_once(':')
+            .and_then(|(_, port)| port.parse::<u16>().ok());
+        if port.is_none() {
+            errors.push(format!(
+                "listen_addr '{}' must be in host:port form",
+                self.listen_addr
+            ));
+        }
+
+        for header in &self.identity_headers {
+            if reqwest::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                errors.push(format!("identity_headers: '{header}' is not a valid header name"));
+            }
+        }
+        if let Err(err) = common::secret::check(
+            "GATEWAY_IDENTITY_SECRET",
+            &self.identity_secret,
+            common::identity::DEFAULT_IDENTITY_SECRET,
+        ) {
+            errors.push(err);
+        }
+        for proxy in &self.trusted_proxies {
+            if parse_proxy_range(proxy).is_none() {
+                errors.push(format!(
+                    "trusted_proxies: '{proxy}' is not an IP address or CIDR range"
+                ));
+            }
+        }
+
+        if let Some(rate_limit) = &self.rate_limit {
+            validate_rate_limit("rate_limit", rate_limit, &mut errors);
+        }
+        if !(0.0..=1.0).contains(&self.retry_budget.ratio) {
+            errors.push("retry_budget: ratio must be between 0 and 1".to_string());
+        }
+        if self.retry_budget.window.is_zero() {
+            errors.push("retry_budget: window must be greater than zero".to_string());
+        }
+
+        let mut plugins: Vec<_> = self.plugins.iter().collect();
+        plugins.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, plugin) in plugins {
+            validate_plugin(name, plugin, &mut errors);
+        }
+
+        let mut routes: Vec<_> = self.routes.iter().collect();
+        routes.sort_by(|a, b| a.0.cmp(b.0));
+        for (route, route_config) in routes {
+            validate_route(route, route_config, &mut errors);
+            for step in &route_config.middleware {
+                if let MiddlewareKind::Wasm(name) = step
+                    && !self.plugins.contains_key(name)
+                {
+                    errors.push(format!("route '{route}': wasm plugin '{name}' is not defined"));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Per-route env overrides that don't parse. `apply_env_overrides` leaves
+    /// those settings as they were; validation reports them.
+    fn env_override_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let mut routes: Vec<_> = self.routes.keys().collect();
+        routes.sort();
+        for route in routes {
+            let name = env_name(route);
+            let var = |suffix: &str| std::env::var(format!("GATEWAY_{name}_{suffix}")).ok();
+            if let Some(mode) = var("MODE")
+                && let Err(err) = mode.parse::<RouteMode>()
+            {
+                errors.push(format!("GATEWAY_{name}_MODE: {err}"));
+            }
+            let hash_on = var("HASH_ON");
+            match var("BALANCE") {
+                Some(balance) => {
+                    if let Err(err) = Balance::parse(&balance, hash_on.as_deref()) {
+                        errors.push(format!("GATEWAY_{name}_BALANCE: {err}"));
+                    }
+                }
+                None if hash_on.is_some() => errors.push(format!(
+                    "GATEWAY_{name}_HASH_ON needs GATEWAY_{name}_BALANCE=consistent_hash"
+                )),
+                None => {}
+            }
+        }
+        errors
+    }
+
     /// Check if a route should be proxied
     pub fn is_proxy(&self, route: &str) -> bool {
         self.routes
@@ -97,11 +429,11 @@ impl GatewayConfig {
             .unwrap_or(false)
     }
 
-    /// Get upstream URL for a route (if in proxy mode)
+    /// Get the first upstream URL for a route (if in proxy mode)
     pub fn get_upstream(&self, route: &str) -> Option<&str> {
         self.routes.get(route).and_then(|r| {
             if r.mode == RouteMode::Proxy {
-                Some(r.upstream_base.as_str())
+                r.upstreams.first().map(|u| u.url.as_str())
             } else {
                 None
             }
@@ -115,6 +447,22 @@ impl GatewayConfig {
         }
     }
 
+    /// Find the configured route a request path belongs to (longest prefix wins)
+    pub fn route_for(&self, path: &str) -> Option<&str> {
+        self.routes
+            .keys()
+            .filter(|route| path_matches(route, path))
+            .max_by_key(|route| route.len())
+            .map(|route| route.as_str())
+    }
+
+    /// Override the rate limit for a route
+    pub fn set_rate_limit(&mut self, route: &str, rate_limit: Option<RateLimitConfig>) {
+        if let Some(r) = self.routes.get_mut(route) {
+            r.rate_limit = rate_limit;
+        }
+    }
+
     /// Set a route to proxy mode with upstream URL
     pub fn set_proxy(&mut self, route: &str, upstream: impl Into<String>) {
         self.routes.insert(
@@ -123,3 +471,218 @@ impl GatewayConfig {
         );
     }
 }
+
+/// A trusted proxy entry: a CIDR range, or a single address
+pub(crate) fn parse_proxy_range(s: &str) -> Option<IpNet> {
+    s.parse::<IpNet>()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// Whether `path` is `route` itself or nested below it
+pub fn path_matches(route: &str, path: &str) -> bool {
+    match path.strip_prefix(route) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || route.ends_with('/'),
+        None => false,
+    }
+}
+
+/// Env var infix for a route path: `/api/v1` -> `API_V1`
+fn env_name(route: &str) -> String {
+    route
+        .trim_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn validate_route(route: &str, config: &RouteConfig, errors: &mut Vec<String>) {
+    let ctx = format!("route '{route}'");
+
+    if !route.starts_with('/') {
+        errors.push(format!("{ctx}: path must start with '/'"));
+    }
+    if route.len() > 1 && route.ends_with('/') {
+        errors.push(format!("{ctx}: path must not end with '/'"));
+    }
+    if route.contains(['{', '}', '*']) {
+        errors.push(format!("{ctx}: path must be a plain prefix without wildcards"));
+    }
+    if crate::health::PATHS.contains(&route) || path_matches(crate::health::ADMIN_PREFIX, route) {
+        errors.push(format!("{ctx}: path is reserved for the gateway's own endpoints"));
+    }
+
+    if config.mode == RouteMode::Proxy && config.upstreams.is_empty() {
+        errors.push(format!("{ctx}: proxy mode requires an upstream"));
+    }
+    for (i, upstream) in config.upstreams.iter().enumerate() {
+        match reqwest::Url::parse(&upstream.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            Ok(url) => errors.push(format!(
+                "{ctx}: upstream '{}' must use http or https, not '{}'",
+                upstream.url,
+                url.scheme()
+            )),
+            Err(err) => errors.push(format!(
+                "{ctx}: upstream '{}' is not a valid URL: {err}",
+                upstream.url
+            )),
+        }
+        if upstream.weight == 0 || upstream.weight > MAX_WEIGHT {
+            errors.push(format!(
+                "{ctx}: upstream '{}' weight must be between 1 and {MAX_WEIGHT}",
+                upstream.url
+            ));
+        }
+        let url = upstream.url.trim_end_matches('/');
+        if config.upstreams[..i]
+            .iter()
+            .any(|other| other.url.trim_end_matches('/') == url)
+        {
+            errors.push(format!("{ctx}: upstream '{}' is listed more than once", upstream.url));
+        }
+    }
+
+    if let Some(audience) = &config.identity_audience
+        && (audience.is_empty() || audience.contains(char::is_whitespace))
+    {
+        errors.push(format!("{ctx}: identity_audience must be a non-empty name without spaces"));
+    }
+    if !config.health_path.starts_with('/') {
+        errors.push(format!("{ctx}: health_path must start with '/'"));
+    }
+    if let Some(checks) = &config.health_check {
+        validate_health_check(&ctx, checks, errors);
+    }
+    if let Some(circuit) = &config.circuit_breaker {
+        validate_circuit_breaker(&ctx, circuit, errors);
+    }
+    if let Some(retry) = &config.retry {
+        validate_retry(&ctx, retry, errors);
+    }
+
+    for (i, step) in config.middleware.iter().enumerate() {
+        if config.middleware[..i].contains(step) {
+            errors.push(format!("{ctx}: middleware '{step}' is listed more than once"));
+        }
+    }
+    if config.auth && !config.middleware.contains(&MiddlewareKind::Auth) {
+        errors.push(format!(
+            "{ctx}: auth is required but the 'auth' middleware is not in the middleware list"
+        ));
+    }
+
+    for (name, timeout) in [
+        ("connect", config.timeouts.connect),
+        ("request", config.timeouts.request),
+        ("first_byte", config.timeouts.first_byte),
+        ("idle", config.timeouts.idle),
+        ("upgrade_idle", config.timeouts.upgrade_idle),
+    ] {
+        if timeout == Some(Duration::ZERO) {
+            errors.push(format!("{ctx}: {name} timeout must be greater than zero"));
+        }
+    }
+
+    if let Some(rate_limit) = &config.rate_limit {
+        validate_rate_limit(&format!("{ctx}: rate_limit"), rate_limit, errors);
+    }
+
+    if !config.access.is_empty() && !config.middleware.contains(&MiddlewareKind::Auth) {
+        errors.push(format!(
+            "{ctx}: access rules need the 'auth' middleware in the middleware list"
+        ));
+    }
+    crate::authz::validate(&ctx, route, &config.access, errors);
+}
+
+fn validate_health_check(ctx: &str, config: &HealthCheckConfig, errors: &mut Vec<String>) {
+    let ctx = format!("{ctx}: health_check");
+    if config.interval.is_some() {
+        if config.timeout.is_zero() {
+            errors.push(format!("{ctx}: timeout must be greater than zero"));
+        }
+        if config.healthy_threshold == 0 || config.unhealthy_threshold == 0 {
+            errors.push(format!("{ctx}: thresholds must be greater than zero"));
+        }
+    } else if config.max_failures > 0 && config.ejection.is_zero() {
+        errors.push(format!(
+            "{ctx}: ejection must be greater than zero without active probes"
+        ));
+    }
+}
+
+fn validate_circuit_breaker(ctx: &str, config: &CircuitBreakerConfig, errors: &mut Vec<String>) {
+    let ctx = format!("{ctx}: circuit_breaker");
+    if !(config.failure_rate > 0.0 && config.failure_rate <= 1.0) {
+        errors.push(format!("{ctx}: failure_rate must be between 0 and 1"));
+    }
+    if config.min_requests == 0 || config.half_open_requests == 0 {
+        errors.push(format!(
+            "{ctx}: min_requests and half_open_requests must be greater than zero"
+        ));
+    }
+    if config.window.is_zero() || config.cool_down.is_zero() {
+        errors.push(format!("{ctx}: window and cool_down must be greater than zero"));
+    }
+    if config.slow_call == Some(Duration::ZERO) {
+        errors.push(format!("{ctx}: slow_call must be greater than zero"));
+    }
+}
+
+fn validate_retry(ctx: &str, config: &RetryPolicy, errors: &mut Vec<String>) {
+    let ctx = format!("{ctx}: retry");
+    if config.max_retries == 0 {
+        errors.push(format!("{ctx}: max_retries must be greater than zero"));
+    }
+    if !config.connect_errors && config.statuses.is_empty() {
+        errors.push(format!("{ctx}: nothing to retry on (connect_errors is off and statuses is empty)"));
+    }
+    for status in &config.statuses {
+        if !(100..=599).contains(status) {
+            errors.push(format!("{ctx}: {status} is not an HTTP status code"));
+        }
+    }
+    for method in &config.methods {
+        if axum::http::Method::from_bytes(method.as_bytes()).is_err() {
+            errors.push(format!("{ctx}: '{method}' is not a valid method"));
+        }
+    }
+    if config.max_backoff < config.backoff {
+        errors.push(format!("{ctx}: max_backoff must not be less than backoff"));
+    }
+}
+
+fn validate_plugin(name: &str, config: &PluginConfig, errors: &mut Vec<String>) {
+    let ctx = format!("plugin '{name}'");
+    if config.path.as_os_str().is_empty() {
+        errors.push(format!("{ctx}: path is required"));
+    }
+    if config.fuel == 0 {
+        errors.push(format!("{ctx}: fuel must be greater than zero"));
+    }
+    if config.max_memory_bytes == 0 {
+        errors.push(format!("{ctx}: max_memory_bytes must be greater than zero"));
+    }
+    if config.max_body_bytes == 0 {
+        errors.push(format!("{ctx}: max_body_bytes must be greater than zero"));
+    }
+}
+
+fn validate_rate_limit(ctx: &str, config: &RateLimitConfig, errors: &mut Vec<String>) {
+    if config.limit == 0 {
+        errors.push(format!("{ctx}: limit must be greater than zero"));
+    }
+    if config.window.is_zero() {
+        errors.push(format!("{ctx}: window must be greater than zero"));
+    }
+    if config.burst == Some(0) {
+        errors.push(format!("{ctx}: burst must be greater than zero"));
+    }
+}
@@ -0,0 +1,801 @@
+//! On-disk route table format for `GatewayConfig`
+//!
+//! The same schema is accepted as TOML or YAML:
+//!
+//! ```toml
+//! listen_addr = "0.0.0.0:8080"
+//! request_timeout_ms = 60000   # until the response starts, for every request (off by default)
+//! # Stripped from inbound requests on top of the x-user-*/x-auth family (default x-gateway)
+//! identity_headers = ["x-gateway", "x-tenant-id"]
+//! # Load balancers whose x-forwarded-for is believed (by default the header is ignored)
+//! trusted_proxies = ["10.0.0.0/8", "127.0.0.1"]
+//!
+//! [rate_limit]
+//! algorithm = "token_bucket"
+//! key = "client_ip"
+//! limit = 100
+//! window_secs = 60
+//!
+//! # Retries across all routes stay within 20% of requests (or 10/s)
+//! [retry_budget]
+//! ratio = 0.2
+//! min_per_sec = 10
+//!
+//! [[routes]]
+//! path = "/billing"
+//! mode = "proxy"
+//! upstream = "http://billing:9000"
+//! strip_prefix = true
+//! auth = true
+//! middleware = ["logging", "auth", "header_injection"]
+//! timeouts = { connect_ms = 2000, first_byte_ms = 10000, request_ms = 30000, idle_ms = 5000 }
+//! health_path = "/health"   # probed by active health checks (default /readyz)
+//! health_check = { interval_ms = 5000, unhealthy_threshold = 3, max_failures = 5 }
+//! circuit_breaker = { failure_rate = 0.5, min_requests = 20, slow_call_ms = 5000, cool_down_ms = 30000 }
+//! retry = { max_retries = 2, statuses = [502, 503, 504], backoff_ms = 25 }
+//! access = [
+//!   { path = "/billing/invoices/*", methods = ["DELETE"], min_role = "ADMIN" },
+//!   { path = "/billing/**", scopes = ["billing:read"] },
+//! ]
+//!
+//! [[routes]]
+//! path = "/admin"
+//! upstream = "http://admin:4001"
+//! identity_audience = "admin"   # sign the caller's identity for the admin service
+//!
+//! [[routes]]
+//! path = "/search"
+//! # several replicas instead of a single `upstream`; weight defaults to 1
+//! upstreams = ["http://search-1:9000", { url = "http://search-2:9000", weight = 2 }]
+//! balance = "consistent_hash"   # round_robin (default), least_outstanding, random_two_choices
+//! hash_on = "header:x-user-id"  # or "cookie:<name>"; only for consistent_hash
+//!
+//! [[routes]]
+//! path = "/live"
+//! mode = "proxy"
+//! upstream = "http://notifications:9000"
+//! # WebSockets are closed after 10 minutes without traffic (default 5)
+//! timeouts = { upgrade_idle_ms = 600000 }
+//!
+//! [plugins.rewrite]
+//! path = "plugins/rewrite.wasm"   # relative to this file
+//! fuel = 10000000
+//! max_memory_bytes = 16777216
+//! max_body_bytes = 1048576
+//! ```
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::authz::AccessRule;
+use crate::balance::Balance;
+use crate::circuit::CircuitBreakerConfig;
+use crate::config::{
+    DEFAULT_HEALTH_PATH, DEFAULT_LISTEN_ADDR, DEFAULT_RATE_LIMIT_PER_MINUTE,
+    GatewayConfig, RouteConfig, RouteMode, RouteTimeouts,
+    UpstreamTarget, default_identity_headers,
+};
+use crate::middleware::MiddlewareKind;
+use crate::rate_limit::RateLimitConfig;
+use crate::retry::{RetryBudgetConfig, RetryPolicy};
+use crate::upstream::HealthCheckConfig;
+use crate::wasm::PluginConfig;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GatewayFile {
+    listen_addr: Option<String>,
+    /// Gateway-wide request timeout; unset or `0` means none
+    request_timeout_ms: Option<u64>,
+    identity_headers: Option<Vec<String>>,
+    #[serde(default)]
+    trusted_proxies: Vec<String>,
+    rate_limit: Option<RateLimitFile>,
+    retry_budget: Option<RetryBudgetFile>,
+    #[serde(default)]
+    routes: Vec<RouteFile>,
+    #[serde(default)]
+    plugins: HashMap<String, PluginFile>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RouteFile {
+    path: String,
+    /// `proxy` or `embedded`; defaults to `proxy` when an upstream is given
+    mode: Option<String>,
+    upstream: Option<String>,
+    /// Several upstreams to balance across; excludes `upstream`
+    upstreams: Option<Vec<UpstreamFile>>,
+    balance: Option<String>,
+    hash_on: Option<String>,
+    #[serde(default = "default_true")]
+    strip_prefix: bool,
+    #[serde(default = "default_true")]
+    auth: bool,
+    /// Service that verifies the signed caller identity, e.g. `admin`
+    identity_audience: Option<String>,
+    middleware: Option<Vec<String>>,
+    #[serde(default)]
+    timeouts: TimeoutsFile,
+    rate_limit: Option<RateLimitFile>,
+    #[serde(default)]
+    access: Vec<AccessFile>,
+    /// Upstream path probed by active health checks
+    health_path: Option<String>,
+    health_check: Option<HealthCheckFile>,
+    circuit_breaker: Option<CircuitBreakerFile>,
+    retry: Option<RetryFile>,
+}
+
+/// A bare URL, or a URL with a weight
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum UpstreamFile {
+    Url(String),
+    Weighted { url: String, weight: u32 },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HealthCheckFile {
+    /// `0` disables active probes
+    interval_ms: Option<u64>,
+    timeout_ms: Option<u64>,
+    healthy_threshold: Option<u32>,
+    unhealthy_threshold: Option<u32>,
+    /// `0` disables passive checks
+    max_failures: Option<u32>,
+    ejection_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CircuitBreakerFile {
+    failure_rate: Option<f64>,
+    min_requests: Option<u32>,
+    window_ms: Option<u64>,
+    slow_call_ms: Option<u64>,
+    cool_down_ms: Option<u64>,
+    half_open_requests: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RetryFile {
+    max_retries: Option<u32>,
+    connect_errors: Option<bool>,
+    statuses: Option<Vec<u16>>,
+    /// Defaults to the idempotent methods
+    methods: Option<Vec<String>>,
+    backoff_ms: Option<u64>,
+    max_backoff_ms: Option<u64>,
+    other_upstream: Option<bool>,
+    max_body_bytes: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RetryBudgetFile {
+    ratio: Option<f64>,
+    min_per_sec: Option<u32>,
+    window_secs: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccessFile {
+    path: String,
+    #[serde(default)]
+    methods: Vec<String>,
+    min_role: Option<String>,
+    #[serde(default)]
+    scopes: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TimeoutsFile {
+    connect_ms: Option<u64>,
+    request_ms: Option<u64>,
+    first_byte_ms: Option<u64>,
+    idle_ms: Option<u64>,
+    upgrade_idle_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RateLimitFile {
+    algorithm: Option<String>,
+    key: Option<String>,
+    /// Requests per window; `0` disables the gateway-wide default
+    limit: u32,
+    #[serde(default = "default_window_secs")]
+    window_secs: u64,
+    burst: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PluginFile {
+    path: String,
+    fuel: Option<u64>,
+    max_memory_bytes: Option<usize>,
+    max_body_bytes: Option<usize>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_window_secs() -> u64 {
+    60
+}
+
+/// Read and parse a config file, choosing the format from its extension
+pub fn load(path: &Path) -> anyhow::Result<GatewayConfig> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("read gateway config {}", path.display()))?;
+
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    let file: GatewayFile = match extension.as_deref() {
+        Some("toml") => toml::from_str(&contents)
+            .with_context(|| format!("parse gateway config {}", path.display()))?,
+        Some("yaml" | "yml") => serde_yaml::from_str(&contents)
+            .with_context(|| format!("parse gateway config {}", path.display()))?,
+        _ => anyhow::bail!(
+            "gateway config {} must have a .toml, .yaml or .yml extension",
+            path.display()
+        ),
+    };
+
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let (config, mut errors) = file.into_config(base_dir);
+    if !errors.is_empty() {
+        // Report schema problems together with everything validation finds
+        errors.extend(config.validation_errors());
+        anyhow::bail!(
+            "invalid gateway config {}:\n  - {}",
+            path.display(),
+            errors.join("\n  - ")
+        );
+    }
+    Ok(config)
+}
+
+impl GatewayFile {
+    /// Convert to a `GatewayConfig`, collecting schema errors instead of
+    /// stopping at the first one
+    fn into_config(self, base_dir: &Path) -> (GatewayConfig, Vec<String>) {
+        let mut errors = Vec::new();
+
+        let rate_limit = match self.rate_limit {
+            Some(rl) if rl.limit == 0 => None,
+            Some(rl) => rl.into_config("rate_limit", &mut errors),
+            None => Some(RateLimitConfig::per_minute(DEFAULT_RATE_LIMIT_PER_MINUTE)),
+        };
+
+        let mut routes = HashMap::new();
+        for route in self.routes {
+            let path = route.path.clone();
+            if routes.contains_key(&path) {
+                errors.push(format!("route '{path}': defined more than once"));
+                continue;
+            }
+            routes.insert(path, route.into_config(&mut errors));
+        }
+
+        let config = GatewayConfig {
+            listen_addr: self
+                .listen_addr
+                .unwrap_or_else(|| DEFAULT_LISTEN_ADDR.to_string()),
+            routes,
+            rate_limit,
+            plugins: self
+                .plugins
+                .into_iter()
+                .map(|(name, plugin)| (name, plugin.into_config(base_dir)))
+                .collect(),
+            identity_headers: self
+                .identity_headers
+                .map(|headers| headers.iter().map(|h| h.to_lowercase()).collect())
+                .unwrap_or_else(default_identity_headers),
+            identity_secret: common::identity::DEFAULT_IDENTITY_SECRET.to_string(),
+            trusted_proxies: self.trusted_proxies,
+            retry_budget: self
+                .retry_budget
+                .map(RetryBudgetFile::into_config)
+                .unwrap_or_default(),
+            request_timeout: self
+                .request_timeout_ms
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis),
+        };
+        (config, errors)
+    }
+}
+
+impl RouteFile {
+    fn into_config(self, errors: &mut Vec<String>) -> RouteConfig {
+        let ctx = format!("route '{}'", self.path);
+
+        let mode = match self.mode.as_deref().map(str::parse) {
+            Some(Ok(mode)) => mode,
+            None if self.upstream.is_some() || self.upstreams.is_some() => RouteMode::Proxy,
+            None => RouteMode::Embedded,
+            Some(Err(err)) => {
+                errors.push(format!("{ctx}: {err}"));
+                RouteMode::Embedded
+            }
+        };
+
+        let middleware = match self.middleware {
+            Some(names) => names
+                .iter()
+                .filter_map(|name| {
+                    name.parse::<MiddlewareKind>()
+                        .map_err(|err| errors.push(format!("{ctx}: {err}")))
+                        .ok()
+                })
+                .collect(),
+            None => MiddlewareKind::defaults(),
+        };
+
+        let upstreams = match (self.upstream, self.upstreams) {
+            (Some(_), Some(_)) => {
+                errors.push(format!("{ctx}: set either upstream or upstreams, not both"));
+                Vec::new()
+            }
+            (Some(url), None) => vec![UpstreamTarget::new(url)],
+            (None, Some(upstreams)) => upstreams
+                .into_iter()
+                .map(|upstream| match upstream {
+                    UpstreamFile::Url(url) => UpstreamTarget::new(url),
+                    UpstreamFile::Weighted { url, weight } => UpstreamTarget { url, weight },
+                })
+                .collect(),
+            (None, None) => Vec::new(),
+        };
+
+        let balance = match &self.balance {
+            Some(name) => Balance::parse(name, self.hash_on.as_deref()),
+            None if self.hash_on.is_some() => {
+                Err("hash_on needs balance = \"consistent_hash\"".to_string())
+            }
+            None => Ok(Balance::default()),
+        }
+        .unwrap_or_else(|err| {
+            errors.push(format!("{ctx}: {err}"));
+            Balance::default()
+        });
+
+        let rate_limit = self
+            .rate_limit
+            .and_then(|rl| rl.into_config(&format!("{ctx}: rate_limit"), errors));
+
+        let access = self
+            .access
+            .into_iter()
+            .map(|rule| rule.into_config(&ctx, errors))
+            .collect();
+
+        RouteConfig {
+            mode,
+            upstreams,
+            balance,
+            strip_prefix: self.strip_prefix,
+            auth: self.auth,
+            identity_audience: self.identity_audience,
+            middleware,
+            timeouts: RouteTimeouts {
+                connect: self.timeouts.connect_ms.map(Duration::from_millis),
+                request: self.timeouts.request_ms.map(Duration::from_millis),
+                first_byte: self.timeouts.first_byte_ms.map(Duration::from_millis),
+                idle: self.timeouts.idle_ms.map(Duration::from_millis),
+                upgrade_idle: self.timeouts.upgrade_idle_ms.map(Duration::from_millis),
+            },
+            rate_limit,
+            access,
+            health_path: self
+                .health_path
+                .unwrap_or_else(|| DEFAULT_HEALTH_PATH.to_string()),
+            health_check: self.health_check.map(HealthCheckFile::into_config),
+            circuit_breaker: self.circuit_breaker.map(CircuitBreakerFile::into_config),
+            retry: self.retry.map(RetryFile::into_config),
+        }
+    }
+}
+
+impl HealthCheckFile {
+    fn into_config(self) -> HealthCheckConfig {
+        let defaults = HealthCheckConfig::default();
+        HealthCheckConfig {
+            interval: match self.interval_ms {
+                Some(0) => None,
+                Some(ms) => Some(Duration::from_millis(ms)),
+                None => defaults.interval,
+            },
+            timeout: self.timeout_ms.map(Duration::from_millis).unwrap_or(defaults.timeout),
+            healthy_threshold: self.healthy_threshold.unwrap_or(defaults.healthy_threshold),
+            unhealthy_threshold: self.unhealthy_threshold.unwrap_or(defaults.unhealthy_threshold),
+            max_failures: self.max_failures.unwrap_or(defaults.max_failures),
+            ejection: self.ejection_ms.map(Duration::from_millis).unwrap_or(defaults.ejection),
+        }
+    }
+}
+
+impl CircuitBreakerFile {
+    fn into_config(self) -> CircuitBreakerConfig {
+        let defaults = CircuitBreakerConfig::default();
+        CircuitBreakerConfig {
+            failure_rate: self.failure_rate.unwrap_or(defaults.failure_rate),
+            min_requests: self.min_requests.unwrap_or(defaults.min_requests),
+            window: self.window_ms.map(Duration::from_millis).unwrap_or(defaults.window),
+            slow_call: self.slow_call_ms.map(Duration::from_millis).or(defaults.slow_call),
+            cool_down: self.cool_down_ms.map(Duration::from_millis).unwrap_or(defaults.cool_down),
+            half_open_requests: self.half_open_requests.unwrap_or(defaults.half_open_requests),
+        }
+    }
+}
+
+impl RetryFile {
+    fn into_config(self) -> RetryPolicy {
+        let defaults = RetryPolicy::default();
+        RetryPolicy {
+            max_retries: self.max_retries.unwrap_or(defaults.max_retries),
+            connect_errors: self.connect_errors.unwrap_or(defaults.connect_errors),
+            statuses: self.statuses.unwrap_or(defaults.statuses),
+            methods: self
+                .methods
+                .map(|methods| methods.iter().map(|m| m.to_uppercase()).collect())
+                .unwrap_or(defaults.methods),
+            backoff: self.backoff_ms.map(Duration::from_millis).unwrap_or(defaults.backoff),
+            max_backoff: self.max_backoff_ms.map(Duration::from_millis).unwrap_or(defaults.max_backoff),
+            other_upstream: self.other_upstream.unwrap_or(defaults.other_upstream),
+            max_body_bytes: self.max_body_bytes.unwrap_or(defaults.max_body_bytes),
+        }
+    }
+}
+
+impl RetryBudgetFile {
+    fn into_config(self) -> RetryBudgetConfig {
+        let defaults = RetryBudgetConfig::default();
+        RetryBudgetConfig {
+            ratio: self.ratio.unwrap_or(defaults.ratio),
+            min_per_sec: self.min_per_sec.unwrap_or(defaults.min_per_sec),
+            window: self.window_secs.map(Duration::from_secs).unwrap_or(defaults.window),
+        }
+    }
+}
+
+impl AccessFile {
+    fn into_config(self, ctx: &str, errors: &mut Vec<String>) -> AccessRule {
+        let min_role = self.min_role.and_then(|name| {
+            let role = contracts::Role::parse(&name);
+            if role.is_none() {
+                errors.push(format!(
+                    "{ctx}: access rule '{}': unknown role '{name}'",
+                    self.path
+                ));
+            }
+            role
+        });
+        AccessRule {
+            path: self.path,
+            methods: self.methods.iter().map(|m| m.to_uppercase()).collect(),
+            min_role,
+            scopes: self.scopes,
+        }
+    }
+}
+
+impl PluginFile {
+    fn into_config(self, base_dir: &Path) -> PluginConfig {
+        let mut config = PluginConfig::new(base_dir.join(&self.path));
+        if self.path.is_empty() {
+            config.path = Default::default();
+        }
+        if let Some(fuel) = self.fuel {
+            config.fuel = fuel;
+        }
+        if let Some(max_memory_bytes) = self.max_memory_bytes {
+            config.max_memory_bytes = max_memory_bytes;
+        }
+        if let Some(max_body_bytes) = self.max_body_bytes {
+            config.max_body_bytes = max_body_bytes;
+        }
+        config
+    }
+}
+
+impl RateLimitFile {
+    fn into_config(self, ctx: &str, errors: &mut Vec<String>) -> Option<RateLimitConfig> {
+        let mut config = RateLimitConfig::per_minute(self.limit);
+        config.window = Duration::from_secs(self.window_secs);
+        config.burst = self.burst;
+
+        if let Some(algorithm) = self.algorithm {
+            match algorithm.parse() {
+                Ok(algorithm) => config.algorithm = algorithm,
+                Err(err) => {
+                    errors.push(format!("{ctx}: {err}"));
+                    return None;
+                }
+            }
+        }
+        if let Some(key) = self.key {
+            match key.parse() {
+                Ok(key) => config.key = key,
+                Err(err) => {
+                    errors.push(format!("{ctx}: {err}"));
+                    return None;
+                }
+            }
+        }
+        Some(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::balance::HashOn;
+    use contracts::Role;
+
+    const TOML: &str = r#"
+listen_addr = "127.0.0.1:9090"
+request_timeout_ms = 60000
+trusted_proxies = ["10.0.0.0/8"]
+
+[rate_limit]
+algorithm = "sliding_window"
+key = "api_key"
+limit = 50
+window_secs = 10
+
+[[routes]]
+path = "/search"
+upstreams = ["http://search-1:9000", { url = "http://search-2:9000", weight = 2 }]
+balance = "consistent_hash"
+hash_on = "header:X-User-Id"
+middleware = ["logging", "auth"]
+timeouts = { connect_ms = 2000, idle_ms = 5000 }
+retry = { max_retries = 1, statuses = [503] }
+access = [
+  { path = "/search/admin/**", methods = ["delete"], min_role = "ADMIN" },
+]
+
+[[routes]]
+path = "/admin"
+mode = "embedded"
+auth = false
+"#;
+
+    const YAML: &str = r#"
+listen_addr: "127.0.0.1:9090"
+request_timeout_ms: 60000
+trusted_proxies: ["10.0.0.0/8"]
+rate_limit:
+  algorithm: sliding_window
+  key: api_key
+  limit: 50
+  window_secs: 10
+routes:
+  - path: /search
+    upstreams:
+      - http://search-1:9000
+      - url: http://search-2:9000
+        weight: 2
+    balance: consistent_hash
+    hash_on: header:X-User-Id
+    middleware: [logging, auth]
+    timeouts:
+      connect_ms: 2000
+      idle_ms: 5000
+    retry:
+      max_retries: 1
+      statuses: [503]
+    access:
+      - path: /search/admin/**
+        methods: [delete]
+        min_role: ADMIN
+  - path: /admin
+    mode: embedded
+    auth: false
+"#;
+
+    /// Write `contents` to a file of its own under the temp dir
+    fn write(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gateway-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn load_str(name: &str, contents: &str) -> anyhow::Result<GatewayConfig> {
+        load(&write(name, contents))
+    }
+
+    #[test]
+    fn toml_and_yaml_give_the_same_config() {
+        let toml = load_str("same.toml", TOML).unwrap();
+        let yaml = load_str("same.yaml", YAML).unwrap();
+        assert_eq!(toml, yaml);
+
+        assert_eq!(toml.listen_addr, "127.0.0.1:9090");
+        assert_eq!(toml.request_timeout, Some(Duration::from_secs(60)));
+        let rate_limit = toml.rate_limit.as_ref().unwrap();
+        assert_eq!((rate_limit.limit, rate_limit.window), (50, Duration::from_secs(10)));
+
+        let search = &toml.routes["/search"];
+        assert_eq!(search.mode, RouteMode::Proxy);
+        assert_eq!(
+            search.upstreams,
+            vec![
+                UpstreamTarget::new("http://search-1:9000"),
+                UpstreamTarget {
+                    url: "http://search-2:9000".to_string(),
+                    weight: 2
+                },
+            ]
+        );
+        assert_eq!(
+            search.balance,
+            Balance::ConsistentHash(HashOn::Header("x-user-id".to_string()))
+        );
+        assert_eq!(search.middleware, vec![MiddlewareKind::Logging, MiddlewareKind::Auth]);
+        assert_eq!(search.timeouts.connect, Some(Duration::from_secs(2)));
+        assert_eq!(search.timeouts.request, None);
+        assert_eq!(search.retry.as_ref().unwrap().statuses, vec![503]);
+        assert_eq!(search.access[0].methods, vec!["DELETE".to_string()]);
+        assert_eq!(search.access[0].min_role, Some(Role::Admin));
+        assert_eq!(search.health_path, DEFAULT_HEALTH_PATH);
+
+        let admin = &toml.routes["/admin"];
+        assert_eq!(admin.mode, RouteMode::Embedded);
+        assert!(!admin.auth);
+        assert_eq!(admin.middleware, MiddlewareKind::defaults());
+    }
+
+    #[test]
+    fn schema_errors_are_reported_together() {
+        let err = load_str(
+            "invalid.toml",
+            r#"
+[[routes]]
+path = "/billing"
+mode = "forward"
+upstream = "http://billing:9000"
+upstreams = ["http://billing-2:9000"]
+middleware = ["logging", "cache"]
+access = [{ path = "/billing/**", min_role = "OWNER" }]
+
+[[routes]]
+path = "/billing"
+upstream = "http://billing:9000"
+
+[[routes]]
+path = "/search"
+upstream = "ftp://search:21"
+balance = "round_robin"
+hash_on = "header:x-user-id"
+"#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        for expected in [
+            "route '/billing': unknown mode 'forward' (expected proxy or embedded)",
+            "route '/billing': set either upstream or upstreams, not both",
+            "route '/billing': unknown middleware 'cache'",
+            "route '/billing': access rule '/billing/**': unknown role 'OWNER'",
+            "route '/billing': defined more than once",
+            "route '/search': hash_on only applies to consistent_hash, not round_robin",
+            // Validation problems come along with the schema errors
+            "route '/search': upstream 'ftp://search:21' must use http or https, not 'ftp'",
+        ] {
+            assert!(err.contains(expected), "missing {expected:?} in:\n{err}");
+        }
+    }
+
+    #[test]
+    fn unreadable_files_name_the_file() {
+        let err = load_str("unknown-field.yaml", "listen_adr: 0.0.0.0:1\n").unwrap_err();
+        assert!(format!("{err:#}").contains("unknown field `listen_adr`"), "{err:#}");
+        assert!(err.to_string().contains("unknown-field.yaml"), "{err}");
+
+        let err = load_str("routes.json", "{}").unwrap_err();
+        assert!(err.to_string().contains("must have a .toml, .yaml or .yml extension"), "{err}");
+
+        let err = load(Path::new("/nonexistent/gateway.toml")).unwrap_err();
+        assert!(err.to_string().contains("read gateway config /nonexistent/gateway.toml"), "{err}");
+    }
+
+    #[test]
+    fn env_vars_override_the_file() {
+        let mut config = load_str(
+            "env.toml",
+            r#"
+[[routes]]
+path = "/cfgenv"
+upstream = "http://from-file:9000"
+"#,
+        )
+        .unwrap();
+
+        // Only this test reads these, so setting them can't race other tests
+        unsafe {
+            std::env::set_var("GATEWAY_CFGENV_UPSTREAM", "http://env-1:9000, http://env-2:9000");
+            std::env::set_var("GATEWAY_CFGENV_BALANCE", "least_outstanding");
+        }
+        config.apply_env_overrides();
+        unsafe {
+            std::env::remove_var("GATEWAY_CFGENV_UPSTREAM");
+            std::env::remove_var("GATEWAY_CFGENV_BALANCE");
+        }
+
+        let route = &config.routes["/cfgenv"];
+        assert_eq!(
+            route.upstreams,
+            vec![
+                UpstreamTarget::new("http://env-1:9000"),
+                UpstreamTarget::new("http://env-2:9000"),
+            ]
+        );
+        assert_eq!(route.balance, Balance::LeastOutstanding);
+    }
+
+    #[test]
+    fn invalid_env_overrides_are_reported() {
+        let config = load_str(
+            "env-invalid.toml",
+            r#"
+[[routes]]
+path = "/cfgbad"
+upstream = "http://from-file:9000"
+
+[[routes]]
+path = "/cfghash"
+upstream = "http://from-file:9000"
+"#,
+        )
+        .unwrap();
+
+        unsafe {
+            std::env::set_var("GATEWAY_CFGBAD_MODE", "forward");
+            std::env::set_var("GATEWAY_CFGBAD_BALANCE", "fastest");
+            std::env::set_var("GATEWAY_CFGHASH_HASH_ON", "header:x-user-id");
+        }
+        let mut overridden = config.clone();
+        overridden.apply_env_overrides();
+        let errors = overridden.validation_errors();
+        unsafe {
+            std::env::remove_var("GATEWAY_CFGBAD_MODE");
+            std::env::remove_var("GATEWAY_CFGBAD_BALANCE");
+            std::env::remove_var("GATEWAY_CFGHASH_HASH_ON");
+        }
+
+        // The bad values are reported and leave the file's settings alone
+        assert_eq!(overridden.routes, config.routes);
+        for expected in [
+            "GATEWAY_CFGBAD_MODE: unknown mode 'forward' (expected proxy or embedded)",
+            "GATEWAY_CFGBAD_BALANCE: unknown balance 'fastest'",
+            "GATEWAY_CFGHASH_HASH_ON needs GATEWAY_CFGHASH_BALANCE=consistent_hash",
+        ] {
+            assert!(
+                errors.iter().any(|err| err.starts_with(expected)),
+                "missing {expected:?} in {errors:#?}"
+            );
+        }
+    }
+}
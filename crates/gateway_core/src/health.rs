@@ -0,0 +1,189 @@
+//! Gateway liveness and readiness.
+//!
+//! `/healthz` and `/readyz` are answered by the gateway itself, ahead of the
+//! route table, rate limits and auth. `/readyz` reports what the health
+//! checks last found about every proxied route's upstreams, without probing
+//! them again; a dead upstream is reported but only degrades the gateway,
+//! which still serves its other routes. Dependencies of embedded modules
+//! (see [`set_readiness_checks`]) are required.
+//!
+//! Anyone gets the aggregate status only. `/readyz?verbose` lists every check
+//! with the upstream's health state and needs the same access as
+//! `/_gateway/upstreams`.
+//!
+//! `/_gateway/upstreams` shows what active and passive health checks know
+//! about each upstream. It covers every route of the gateway, not one
+//! organisation, so it needs a super admin token, or an API key of the super
+//! admin role that also carries the `gateway:admin` scope.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock};
+
+use axum::body::Body;
+use axum::extract::RawQuery;
+use axum::http::{HeaderMap, Response, StatusCode, Uri};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{Json, Router};
+use common::health::{self, Check, CheckStatus, Dependency, Report};
+use common::shutdown::Shutdown;
+use contracts::Role;
+use serde::Serialize;
+
+use crate::authz::AccessRule;
+use crate::middleware::{Auth, Middleware};
+use crate::server::{self, Gateway};
+use crate::types::{Request as GatewayRequest, Response as GatewayResponse};
+use crate::upstream::UpstreamStatus;
+
+/// Paths served by the gateway itself; routes can't use them
+pub const PATHS: &[&str] = &["/healthz", "/readyz"];
+/// Prefix of the gateway's own operator endpoints; routes can't use it either
+pub const ADMIN_PREFIX: &str = "/_gateway";
+
+static CHECKS: OnceLock<Vec<Dependency>> = OnceLock::new();
+
+/// Dependencies the embedded modules need, e.g. the database. Must be called
+/// before the gateway starts.
+pub fn set_readiness_checks(checks: Vec<Dependency>) {
+    let _ = CHECKS.set(checks);
+}
+
+pub(crate) fn routes(gateway: Arc<Gateway>, shutdown: Shutdown) -> Router {
+    let upstream_gateway = gateway.clone();
+    Router::new()
+        .route("/healthz", get(|| async { health::liveness("gateway") }))
+        .route(
+            "/readyz",
+            get(move |RawQuery(query): RawQuery, uri: Uri, headers: HeaderMap| {
+                readiness(gateway.clone(), shutdown.clone(), query, uri, headers)
+            }),
+        )
+        .route(
+            &format!("{ADMIN_PREFIX}/upstreams"),
+            get(move |uri: Uri, headers: HeaderMap| {
+                upstreams(upstream_gateway.clone(), uri, headers)
+            }),
+        )
+}
+
+/// Readiness from the embedded modules' dependencies and the cached upstream
+/// health; the checks themselves only with `?verbose`, for admins
+async fn readiness(
+    gateway: Arc<Gateway>,
+    shutdown: Shutdown,
+    query: Option<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let verbose = health::is_verbose(query.as_deref());
+    if verbose && let Err(res) = require_admin(&uri, &headers).await {
+        return server::middleware_response(res);
+    }
+
+    let dependencies = CHECKS.get().cloned().unwrap_or_default();
+    let mut checks = health::check_all(&dependencies).await;
+    checks.extend(upstream_checks(&gateway));
+    let report = Report::new("gateway", &shutdown, checks, verbose);
+    if verbose {
+        return report.into_response();
+    }
+
+    #[derive(Serialize)]
+    struct Summary {
+        status: health::ReadyStatus,
+        service: String,
+    }
+    let status = if report.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let summary = Summary {
+        status: report.status,
+        service: report.service,
+    };
+    (status, Json(summary)).into_response()
+}
+
+/// A check per upstream of every proxied route, from what active and passive
+/// health checks last found
+fn upstream_checks(gateway: &Gateway) -> Vec<Check> {
+    let mut checks = Vec::new();
+    for (route, upstreams) in gateway.upstream_status() {
+        let replicas = upstreams.len() > 1;
+        for upstream in upstreams {
+            // Replicas are told apart by URL
+            let name = if replicas {
+                format!("{route} {}", upstream.url)
+            } else {
+                route.clone()
+            };
+            checks.push(Check {
+                name,
+                status: if upstream.healthy {
+                    CheckStatus::Up
+                } else {
+                    CheckStatus::Down
+                },
+                required: false,
+                latency_ms: 0,
+                error: (!upstream.healthy)
+                    .then(|| upstream.last_error.clone())
+                    .flatten(),
+                details: serde_json::to_value(&upstream).ok(),
+            });
+        }
+    }
+    checks
+}
+
+/// Upstream health by route
+async fn upstreams(gateway: Arc<Gateway>, uri: Uri, headers: HeaderMap) -> Response<Body> {
+    if let Err(res) = require_admin(&uri, &headers).await {
+        return server::middleware_response(res);
+    }
+
+    #[derive(Serialize)]
+    struct Upstreams {
+        routes: BTreeMap<String, Vec<UpstreamStatus>>,
+    }
+    Json(Upstreams {
+        routes: gateway.upstream_status(),
+    })
+    .into_response()
+}
+
+/// Who may see the gateway's operator views: super admin tokens, and API keys
+/// that both belong to the super admin role and carry the `gateway:admin`
+/// scope. An organisation's admins are refused, and so is a key with a lower
+/// role whatever its scopes, since key scopes are chosen by whoever issues
+/// the key.
+pub(crate) fn admin_rule() -> AccessRule {
+    AccessRule {
+        // Guards `/readyz?verbose` as well as the operator endpoints
+        path: "/**".to_string(),
+        methods: Vec::new(),
+        min_role: Some(Role::SuperAdmin),
+        scopes: vec!["gateway:admin".to_string()],
+    }
+}
+
+/// Enforce [`admin_rule`]
+async fn require_admin(uri: &Uri, headers: &HeaderMap) -> Result<(), GatewayResponse> {
+    let auth = Auth {
+        required: true,
+        rules: vec![admin_rule()],
+    };
+    let mut gateway_req = GatewayRequest::new(uri.path());
+    gateway_req.headers = headers
+        .iter()
+        .filter_map(|(name, value)| {
+            value
+                .to_str()
+                .ok()
+                .map(|v| (name.to_string(), v.to_string()))
+        })
+        .collect();
+    auth.process(gateway_req).await.map(|_| ())
+}
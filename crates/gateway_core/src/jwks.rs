@@ -0,0 +1,236 @@
+//! Access token verification against the auth service's JWKS.
+//!
+//! The gateway holds no signing material. Public keys are fetched from
+//! `GATEWAY_JWKS_URL` (or an in-process provider when auth is embedded) and
+//! cached; a token signed with an unknown `kid` triggers an early refresh so
+//! new keys are picked up without waiting for the cache to expire. While auth
+//! is unreachable the last keys keep being used, but only for `MAX_STALE`
+//! after they were fetched, so a retired key doesn't stay valid for good.
+
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+
+use crate::middleware::Claims;
+
+const DEFAULT_JWKS_URL: &str = "http://localhost:4002/auth/.well-known/jwks.json";
+const DEFAULT_CACHE_TTL_SECS: u64 = 60;
+/// Unknown `kid`s (and failed fetches) can't force refetches more often than
+/// this; short enough that tokens from a freshly rotated key are accepted
+/// almost at once
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// How long after their last successful fetch keys may be served while auth
+/// can't be reached
+const MAX_STALE: Duration = Duration::from_secs(300);
+
+/// Source of public keys when auth runs in the same process
+pub type JwksProvider = Arc<dyn Fn() -> JwkSet + Send + Sync>;
+
+static PROVIDER: OnceLock<JwksProvider> = OnceLock::new();
+static CACHE: OnceLock<JwksCache> = OnceLock::new();
+
+/// Read keys from `provider` instead of `GATEWAY_JWKS_URL`. Must be called
+/// before the gateway starts.
+pub fn set_jwks_provider(provider: JwksProvider) {
+    let _ = PROVIDER.set(provider);
+}
+
+fn cache() -> &'static JwksCache {
+    CACHE.get_or_init(|| {
+        let ttl = std::env::var("GATEWAY_JWKS_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_CACHE_TTL_SECS);
+        JwksCache {
+            url: std::env::var("GATEWAY_JWKS_URL").unwrap_or_else(|_| DEFAULT_JWKS_URL.to_string()),
+            ttl: Duration::from_secs(ttl),
+            max_stale: MAX_STALE,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap_or_default(),
+            keys: RwLock::new(Keys::default()),
+            refresh: tokio::sync::Mutex::new(()),
+        }
+    })
+}
+
+/// Verify `token`. `Ok(None)` means the token is not valid; `Err` means no
+/// keys could be loaded to check it.
+pub async fn verify(token: &str) -> anyhow::Result<Option<Claims>> {
+    let Ok(header) = jsonwebtoken::decode_header(token) else {
+        return Ok(None);
+    };
+    let Some(kid) = header.kid else {
+        return Ok(None);
+    };
+    let Some((algorithm, key)) = cache().key(&kid).await? else {
+        return Ok(None);
+    };
+
+    // The algorithm comes from the key, never from the token
+    let validation = Validation::new(algorithm);
+    Ok(jsonwebtoken::decode::<Claims>(token, &key, &validation)
+        .ok()
+        .map(|data| data.claims))
+}
+
+#[derive(Default)]
+struct Keys {
+    keys: HashMap<String, (Algorithm, DecodingKey)>,
+    fetched_at: Option<Instant>,
+    /// Last failed refresh, to hold off the next attempt
+    failed_at: Option<Instant>,
+}
+
+struct JwksCache {
+    url: String,
+    ttl: Duration,
+    max_stale: Duration,
+    client: reqwest::Client,
+    keys: RwLock<Keys>,
+    /// Lets one request refresh while the others wait for its result
+    refresh: tokio::sync::Mutex<()>,
+}
+
+impl JwksCache {
+    async fn key(&self, kid: &str) -> anyhow::Result<Option<(Algorithm, DecodingKey)>> {
+        if let Some(found) = self.lookup(kid, false) {
+            return Ok(found);
+        }
+
+        let _guard = self.refresh.lock().await;
+        if let Some(found) = self.lookup(kid, false) {
+            return Ok(found);
+        }
+        let failed_recently = self
+            .read()
+            .failed_at
+            .is_some_and(|at| at.elapsed() < MIN_REFRESH_INTERVAL);
+        if !failed_recently {
+            match self.refresh().await {
+                Ok(()) => return Ok(self.lookup(kid, true).flatten()),
+                Err(err) => {
+                    eprintln!("[gateway] jwks refresh failed: {err:#}");
+                    self.write().failed_at = Some(Instant::now());
+                }
+            }
+        }
+
+        // Stale keys are better than none while auth is unreachable, for a while
+        let fetched_at = self.read().fetched_at;
+        match fetched_at {
+            Some(at) if at.elapsed() < self.max_stale => Ok(self.lookup(kid, true).flatten()),
+            Some(at) => anyhow::bail!("jwks not refreshed for {:?}", at.elapsed()),
+            None => anyhow::bail!("jwks could not be fetched"),
+        }
+    }
+
+    /// `Some` when the cache can answer for `kid` without a refresh
+    fn lookup(&self, kid: &str, force: bool) -> Option<Option<(Algorithm, DecodingKey)>> {
+        let keys = self.read();
+        let fetched_at = keys.fetched_at?;
+        let found = keys.keys.get(kid).cloned();
+        let age = fetched_at.elapsed();
+        if force || (age < self.ttl && (found.is_some() || age < MIN_REFRESH_INTERVAL)) {
+            Some(found)
+        } else {
+            None
+        }
+    }
+
+    async fn refresh(&self) -> anyhow::Result<()> {
+        let set = match PROVIDER.get() {
+            Some(provider) => provider(),
+            None => self
+                .client
+                .get(&self.url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<JwkSet>()
+                .await?,
+        };
+
+        let keys: HashMap<_, _> = set
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                let algorithm = match &jwk.algorithm {
+                    AlgorithmParameters::RSA(_) => Algorithm::RS256,
+                    AlgorithmParameters::OctetKeyPair(params)
+                        if params.curve == EllipticCurve::Ed25519 =>
+                    {
+                        Algorithm::EdDSA
+                    }
+                    _ => return None,
+                };
+                let key = DecodingKey::from_jwk(jwk).ok()?;
+                Some((kid, (algorithm, key)))
+            })
+            .collect();
+
+        *self.write() = Keys {
+            keys,
+            fetched_at: Some(Instant::now()),
+            failed_at: None,
+        };
+        Ok(())
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Keys> {
+        self.keys.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Keys> {
+        self.keys.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cache whose keys are due for a refresh that can't succeed
+    fn unreachable(max_stale: Duration) -> JwksCache {
+        let mut keys = HashMap::new();
+        keys.insert(
+            "old".to_string(),
+            (Algorithm::RS256, DecodingKey::from_secret(b"unused")),
+        );
+        JwksCache {
+            url: "http://127.0.0.1:1/jwks.json".to_string(),
+            ttl: Duration::ZERO,
+            max_stale,
+            client: reqwest::Client::new(),
+            keys: RwLock::new(Keys {
+                keys,
+                fetched_at: Some(Instant::now()),
+                failed_at: None,
+            }),
+            refresh: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_serves_stale_keys_without_renewing_them() {
+        let cache = unreachable(Duration::from_secs(60));
+        let fetched_at = cache.read().fetched_at;
+
+        assert!(cache.key("old").await.unwrap().is_some());
+        assert!(cache.key("other").await.unwrap().is_none());
+        let keys = cache.read();
+        assert_eq!(keys.fetched_at, fetched_at);
+        assert!(keys.failed_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn keys_past_max_stale_are_not_served() {
+        let cache = unreachable(Duration::ZERO);
+        assert!(cache.key("old").await.is_err());
+    }
+}
@@ -1,36 +1,61 @@
 use std::collections::HashMap;
 
+use common::shutdown::Shutdown;
+
+pub mod api_key;
+pub mod authz;
+pub mod balance;
+pub mod circuit;
 pub mod config;
+pub mod config_file;
+pub mod health;
+pub mod jwks;
 pub mod middleware;
 pub mod proxy;
 pub mod rate_limit;
+pub mod reload;
+pub mod retry;
 pub mod server;
 pub mod types;
+pub mod upgrade;
+pub mod upstream;
 pub mod wasm;
 
-pub use config::{GatewayConfig, RouteConfig, RouteMode};
-pub use middleware::set_jwt_secret;
+pub use api_key::set_api_key_service;
+pub use authz::AccessRule;
+pub use config::{GatewayConfig, RouteConfig, RouteMode, RouteTimeouts, UpstreamTarget};
+pub use health::set_readiness_checks;
+pub use jwks::set_jwks_provider;
+pub use server::Gateway;
 
-/// Run gateway with default configuration (all routes proxied based on env config)
+/// Run gateway with configuration from `GATEWAY_CONFIG` (or env defaults)
 pub async fn run() -> anyhow::Result<()> {
-    let config = config::GatewayConfig::default();
+    let config = config::GatewayConfig::load()?;
     wasm::init();
-    server::run(&config).await
+    server::run(&config, signal_shutdown()).await
 }
 
 /// Run gateway with embedded routers for specific routes
 /// Routes not in `routers` map will be proxied based on config
 pub async fn run_with_routers(routers: HashMap<String, axum::Router>) -> anyhow::Result<()> {
-    let config = config::GatewayConfig::default();
+    let config = config::GatewayConfig::load()?;
     wasm::init();
-    server::run_with_routers(&config, routers).await
+    server::run_with_routers(&config, routers, signal_shutdown()).await
 }
 
-/// Run gateway with custom config and embedded routers
+/// Run gateway with custom config and embedded routers until `shutdown`
+/// is triggered
 pub async fn run_with_config_and_routers(
     config: GatewayConfig,
     routers: HashMap<String, axum::Router>,
+    shutdown: Shutdown,
 ) -> anyhow::Result<()> {
     wasm::init();
-    server::run_with_routers(&config, routers).await
+    server::run_with_routers(&config, routers, shutdown).await
+}
+
+fn signal_shutdown() -> Shutdown {
+    let shutdown = Shutdown::new();
+    shutdown.trigger_on_signals();
+    shutdown
 }
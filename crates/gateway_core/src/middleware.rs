@@ -1,7 +1,15 @@
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use contracts::Role;
 use serde::{Deserialize, Serialize};
 
+use crate::authz::{self, AccessRule, Principal};
+use crate::{api_key, jwks};
 use crate::types::{Request, Response};
+use crate::wasm::{PluginConfig, WasmPlugin};
 
 /// JWT Claims structure (must match auth_core Claims)
 #[derive(Debug, Serialize, Deserialize)]
@@ -16,39 +24,44 @@ pub struct Claims {
     pub org_id: Option<String>,
 }
 
+#[async_trait]
 pub trait Middleware: Send + Sync {
-    fn process(&self, req: Request) -> Result<Request, Response>;
+    async fn process(&self, req: Request) -> Result<Request, Response>;
+
+    /// Maximum request body this step needs buffered into `Request::body`;
+    /// `None` leaves the body streaming
+    fn body_limit(&self) -> Option<usize> {
+        None
+    }
 }
 
 pub struct Logging;
 
+#[async_trait]
 impl Middleware for Logging {
-    fn process(&self, req: Request) -> Result<Request, Response> {
+    async fn process(&self, req: Request) -> Result<Request, Response> {
         println!("[gateway] request path: {}", req.path);
         Ok(req)
     }
 }
 
-/// JWT secret for token validation (should match auth_core)
-static JWT_SECRET: std::sync::OnceLock<String> = std::sync::OnceLock::new();
-
-pub fn set_jwt_secret(secret: String) {
-    let _ = JWT_SECRET.set(secret);
+/// Authenticates requests via JWT bearer token or API key.
+/// When `required` is false, anonymous requests pass through unchanged.
+/// The route's access rules are then checked against the caller.
+pub struct Auth {
+    pub required: bool,
+    pub rules: Vec<AccessRule>,
 }
 
-fn get_jwt_secret() -> &'static str {
-    JWT_SECRET.get().map(|s| s.as_str()).unwrap_or("secret")
+impl Auth {
+    fn authorize(&self, req: &Request, principal: Option<&Principal>) -> Result<(), Response> {
+        authz::check(&self.rules, &req.method, &req.path, principal)
+    }
 }
 
-pub struct Auth;
-
+#[async_trait]
 impl Middleware for Auth {
-    fn process(&self, mut req: Request) -> Result<Request, Response> {
-        // Skip API key check for auth routes (login, register, etc.)
-        if req.path.starts_with("/auth") {
-            return Ok(req);
-        }
-
+    async fn process(&self, mut req: Request) -> Result<Request, Response> {
         // Try to extract JWT token from Authorization header
         let auth_header = req
             .headers
@@ -56,54 +69,97 @@ impl Middleware for Auth {
             .find(|(k, _)| k.eq_ignore_ascii_case("authorization"))
             .map(|(_, v)| v.clone());
 
-        if let Some(auth) = auth_header {
-            if let Some(token) = auth.strip_prefix("Bearer ").or_else(|| auth.strip_prefix("bearer ")) {
-                // Decode JWT and extract claims
-                let mut validation = Validation::new(Algorithm::HS256);
-                validation.validate_exp = true;
-                
-                if let Ok(token_data) = decode::<Claims>(
-                    token,
-                    &DecodingKey::from_secret(get_jwt_secret().as_bytes()),
-                    &validation,
-                ) {
-                    let claims = token_data.claims;
-                    
-                    // Add user info headers
-                    req.headers.push(("x-user-id".to_string(), claims.sub));
-                    req.headers.push(("x-user-email".to_string(), claims.email));
-                    req.headers.push(("x-user-name".to_string(), claims.name));
-                    req.headers.push(("x-user-role".to_string(), claims.role));
-                    
-                    if let Some(org_id) = claims.org_id {
-                        req.headers.push(("x-organisation-id".to_string(), org_id));
+        if let Some(auth) = auth_header
+            && let Some(token) = auth
+                .strip_prefix("Bearer ")
+                .or_else(|| auth.strip_prefix("bearer "))
+        {
+            // Verify JWT against the auth service's public keys
+            let verified = match jwks::verify(token).await {
+                Ok(verified) => verified,
+                Err(err) => {
+                    eprintln!("[gateway] token verification failed: {err:#}");
+                    if self.required {
+                        return Err(auth_unavailable());
                     }
-                    
-                    req.headers.push(("x-auth".to_string(), "jwt".to_string()));
-                    return Ok(req);
+                    None
+                }
+            };
+
+            if let Some(claims) = verified {
+                let principal = Principal {
+                    role: Role::parse(&claims.role).unwrap_or_default(),
+                    scopes: None,
+                };
+                self.authorize(&req, Some(&principal))?;
+
+                // Add user info headers
+                req.headers.push(("x-user-id".to_string(), claims.sub));
+                req.headers.push(("x-user-email".to_string(), claims.email));
+                req.headers.push(("x-user-name".to_string(), claims.name));
+                req.headers.push(("x-user-role".to_string(), claims.role));
+
+                if let Some(org_id) = claims.org_id {
+                    req.headers.push(("x-organisation-id".to_string(), org_id));
                 }
+
+                req.headers.push(("x-auth".to_string(), "jwt".to_string()));
+                return Ok(req);
             }
         }
 
         // Fallback: check for API key
-        let has_key = req
+        let api_key = req
             .headers
             .iter()
-            .any(|(k, v)| k.eq_ignore_ascii_case("x-api-key") && !v.is_empty());
+            .find(|(k, v)| k.eq_ignore_ascii_case("x-api-key") && !v.is_empty())
+            .map(|(_, v)| v.clone());
 
-        if !has_key {
+        let Some(api_key) = api_key else {
+            if !self.required {
+                self.authorize(&req, None)?;
+                return Ok(req);
+            }
             return Err(Response::unauthorized("missing authentication"));
-        }
+        };
 
+        let info = match api_key::verify(&api_key).await {
+            Ok(Some(info)) => info,
+            Ok(None) => return Err(Response::unauthorized("invalid api key")),
+            Err(err) => {
+                eprintln!("[gateway] api key verification failed: {err}");
+                return Err(auth_unavailable());
+            }
+        };
+
+        let principal = Principal {
+            role: info.role,
+            scopes: Some(&info.scopes),
+        };
+        self.authorize(&req, Some(&principal))?;
+
+        req.headers.push(("x-organisation-id".to_string(), info.organisation_id.to_string()));
+        req.headers.push(("x-user-role".to_string(), info.role.as_str().to_string()));
+        req.headers.push(("x-api-key-id".to_string(), info.id.to_string()));
+        req.headers.push(("x-api-key-scopes".to_string(), info.scopes.join(",")));
         req.headers.push(("x-auth".to_string(), "api-key".to_string()));
         Ok(req)
     }
 }
 
+fn auth_unavailable() -> Response {
+    Response {
+        status: 503,
+        headers: Vec::new(),
+        body: "authentication unavailable".to_string(),
+    }
+}
+
 pub struct HeaderInjection;
 
+#[async_trait]
 impl Middleware for HeaderInjection {
-    fn process(&self, mut req: Request) -> Result<Request, Response> {
+    async fn process(&self, mut req: Request) -> Result<Request, Response> {
         req.headers
             .push(("x-gateway".to_string(), "apisentinel".to_string()));
         Ok(req)
@@ -112,14 +168,104 @@ impl Middleware for HeaderInjection {
 
 pub type Pipeline = Vec<Box<dyn Middleware>>;
 
+/// Middleware that can be named in route configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MiddlewareKind {
+    Logging,
+    Auth,
+    HeaderInjection,
+    /// WASM plugin defined in `GatewayConfig::plugins`, written `wasm:<name>`
+    Wasm(String),
+}
+
+impl MiddlewareKind {
+    /// Steps used when a route does not list its own
+    pub fn defaults() -> Vec<Self> {
+        vec![Self::Logging, Self::Auth, Self::HeaderInjection]
+    }
+
+}
+
+impl fmt::Display for MiddlewareKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Logging => f.write_str("logging"),
+            Self::Auth => f.write_str("auth"),
+            Self::HeaderInjection => f.write_str("header_injection"),
+            Self::Wasm(name) => write!(f, "wasm:{name}"),
+        }
+    }
+}
+
+impl FromStr for MiddlewareKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(name) = s.strip_prefix("wasm:") {
+            return match name.trim() {
+                "" => Err("wasm middleware needs a plugin name (wasm:<name>)".to_string()),
+                name => Ok(Self::Wasm(name.to_string())),
+            };
+        }
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "logging" => Ok(Self::Logging),
+            "auth" => Ok(Self::Auth),
+            "header_injection" => Ok(Self::HeaderInjection),
+            other => Err(format!(
+                "unknown middleware '{other}' (expected logging, auth, header_injection or wasm:<name>)"
+            )),
+        }
+    }
+}
+
+/// Build a pipeline from configured steps, loading any WASM plugins it names
+pub fn build_pipeline(
+    steps: &[MiddlewareKind],
+    auth_required: bool,
+    access: &[AccessRule],
+    plugins: &HashMap<String, PluginConfig>,
+) -> anyhow::Result<Pipeline> {
+    steps
+        .iter()
+        .map(|step| -> anyhow::Result<Box<dyn Middleware>> {
+            Ok(match step {
+                MiddlewareKind::Logging => Box::new(Logging),
+                MiddlewareKind::Auth => Box::new(Auth {
+                    required: auth_required,
+                    rules: access.to_vec(),
+                }),
+                MiddlewareKind::HeaderInjection => Box::new(HeaderInjection),
+                MiddlewareKind::Wasm(name) => {
+                    let Some(config) = plugins.get(name) else {
+                        anyhow::bail!("wasm plugin '{name}' is not defined");
+                    };
+                    Box::new(WasmPlugin::load(name.clone(), config)?)
+                }
+            })
+        })
+        .collect()
+}
+
 pub fn default_pipeline() -> Pipeline {
-    vec![Box::new(Logging), Box::new(Auth), Box::new(HeaderInjection)]
+    vec![
+        Box::new(Logging),
+        Box::new(Auth {
+            required: true,
+            rules: Vec::new(),
+        }),
+        Box::new(HeaderInjection),
+    ]
+}
+
+/// Largest body any step in the pipeline needs buffered
+pub fn body_limit(pipeline: &Pipeline) -> Option<usize> {
+    pipeline.iter().filter_map(|step| step.body_limit()).max()
 }
 
-pub fn apply(pipeline: &Pipeline, req: Request) -> Result<Request, Response> {
+pub async fn apply(pipeline: &Pipeline, req: Request) -> Result<Request, Response> {
     let mut current = req;
     for step in pipeline {
-        current = step.process(current)?;
+        current = step.process(current).await?;
     }
     Ok(current)
 }
@@ -1,29 +1,177 @@
-use axum::body::Body;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::body::{Body, Bytes, HttpBody};
+use axum::http::header::UPGRADE;
+use axum::http::request::Parts;
 use axum::http::{Request, Response, StatusCode};
-use http_body_util::BodyExt;
+use futures_util::StreamExt;
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
 use reqwest::Client;
 
-#[derive(Debug, Clone)]
+use crate::balance::{Balance, Pool, Unavailable};
+use crate::config::{RouteConfig, RouteTimeouts};
+use crate::retry::{RetryBudget, RetryPolicy};
+use crate::upgrade::{self, DEFAULT_UPGRADE_IDLE};
+use crate::upstream::{InFlight, Upstream};
+
+#[derive(Clone)]
 pub struct Proxy {
-    upstream_base: String,
+    pool: Arc<Pool>,
     client: Client,
+    retry: Option<RetryPolicy>,
+    timeouts: RouteTimeouts,
+}
+
+/// Why a request couldn't be forwarded
+#[derive(Debug)]
+pub enum ProxyError {
+    /// No upstream would take it
+    Unavailable(Unavailable),
+    /// The upstream didn't answer in time
+    Timeout(TimeoutKind, Duration),
+    /// The upstream exchange failed
+    Upstream(anyhow::Error),
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unavailable(_) => f.write_str("no upstream available"),
+            Self::Timeout(kind, after) => write!(f, "{kind} timeout after {after:?}"),
+            Self::Upstream(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+/// Which of a route's timeouts ran out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutKind {
+    Connect,
+    FirstByte,
+    Request,
+}
+
+impl fmt::Display for TimeoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Connect => "connect",
+            Self::FirstByte => "first_byte",
+            Self::Request => "request",
+        })
+    }
+}
+
+/// Request body as sent upstream: kept in memory when it may be sent again
+enum OutboundBody {
+    Buffered(Bytes),
+    Streaming(Option<Body>),
+}
+
+impl OutboundBody {
+    fn take(&mut self) -> reqwest::Body {
+        match self {
+            Self::Buffered(bytes) => reqwest::Body::from(bytes.clone()),
+            Self::Streaming(body) => match body.take() {
+                Some(body) => reqwest::Body::wrap_stream(body.into_data_stream()),
+                None => reqwest::Body::from(Bytes::new()),
+            },
+        }
+    }
 }
 
 impl Proxy {
     pub fn new(upstream_base: impl Into<String>) -> Self {
+        let upstream = Arc::new(Upstream::new(upstream_base, "/", None, None));
         Self {
-            upstream_base: upstream_base.into(),
+            pool: Arc::new(Pool::new(vec![(upstream, 1)], Balance::default())),
             client: Client::new(),
+            retry: None,
+            timeouts: RouteTimeouts::default(),
         }
     }
 
+    /// Build a proxy for a route, applying its upstream timeouts. Each
+    /// upstream's health state is taken over from `previous` when its
+    /// settings are unchanged, so a reload doesn't reinstate a dead upstream.
+    pub fn for_route(route: &RouteConfig, previous: Option<&Proxy>) -> anyhow::Result<Self> {
+        // No total timeout on the client: `request` is enforced up to the
+        // response headers in `send` and on the body in `response_body`.
+        let mut builder = Client::builder();
+        if let Some(connect) = route.timeouts.connect {
+            builder = builder.connect_timeout(connect);
+        }
+        let client = builder.build()?;
+
+        let upstreams = route
+            .upstreams
+            .iter()
+            .map(|target| {
+                let existing = previous.and_then(|p| {
+                    p.pool.upstreams().find(|u| {
+                        u.same_settings(
+                            &target.url,
+                            &route.health_path,
+                            route.health_check.as_ref(),
+                            route.circuit_breaker.as_ref(),
+                        )
+                    })
+                });
+                let upstream = match existing {
+                    Some(upstream) => upstream.clone(),
+                    None => {
+                        let upstream = Arc::new(Upstream::new(
+                            &target.url,
+                            &route.health_path,
+                            route.health_check.clone(),
+                            route.circuit_breaker.clone(),
+                        ));
+                        upstream.spawn_probes(client.clone());
+                        upstream
+                    }
+                };
+                (upstream, target.weight)
+            })
+            .collect();
+
+        Ok(Self {
+            pool: Arc::new(Pool::new(upstreams, route.balance.clone())),
+            client,
+            retry: route.retry.clone(),
+            timeouts: route.timeouts.clone(),
+        })
+    }
+
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    /// Forward a request to one of the route's upstreams, streaming bodies in
+    /// both directions.
+    ///
+    /// Neither the request nor the response body is buffered: chunks are passed
+    /// through as they arrive, so backpressure from either side propagates to
+    /// the other. The exception is a request the route's retry policy covers,
+    /// whose (small) body is kept so it can be sent again. A request counts as
+    /// outstanding on its upstream until the response body is finished or
+    /// dropped.
+    ///
+    /// The response is cut off when the upstream sends no body data for the
+    /// route's `idle` timeout, or has not finished it by the `request`
+    /// deadline; before the response starts, timeouts end in
+    /// `ProxyError::Timeout`.
+    ///
+    /// Requests to switch protocols (e.g. WebSocket handshakes) are never
+    /// retried; see [`crate::upgrade`].
     pub async fn forward(
         &self,
         req: Request<Body>,
         strip_prefix: &str,
-    ) -> Result<Response<Body>, anyhow::Error> {
-        let (parts, body) = req.into_parts();
-        let body_bytes = body.collect().await?.to_bytes();
+        budget: &RetryBudget,
+    ) -> Result<Response<Body>, ProxyError> {
+        let (mut parts, body) = req.into_parts();
 
         let path_and_query = parts
             .uri
@@ -31,35 +179,311 @@ impl Proxy {
             .map(|pq| pq.as_str())
             .unwrap_or("/");
 
-        let mut stripped = path_and_query
-            .strip_prefix(strip_prefix)
-            .unwrap_or(path_and_query)
-            .to_string();
+        let stripped = strip_route_prefix(path_and_query, strip_prefix);
+
+        budget.record_request();
+        let deadline = self.timeouts.request.map(|request| Instant::now() + request);
+        if upgrade::is_upgrade(&parts.headers)
+            && let Some(client) = parts.extensions.remove::<OnUpgrade>()
+        {
+            return self.upgrade(parts, &stripped, body, client, deadline).await;
+        }
+
+        let retry = self
+            .retry
+            .as_ref()
+            .filter(|policy| policy.allows_method(&parts.method))
+            .filter(|policy| is_replayable(&body, policy.max_body_bytes));
+        let mut body = match retry {
+            Some(policy) => OutboundBody::Buffered(
+                axum::body::to_bytes(body, policy.max_body_bytes)
+                    .await
+                    .map_err(|err| ProxyError::Upstream(anyhow::anyhow!("read request body: {err}")))?,
+            ),
+            None => OutboundBody::Streaming(Some(body)),
+        };
+
+        let mut in_flight = self
+            .pool
+            .pick(&parts.headers, &[])
+            .map_err(ProxyError::Unavailable)?;
+        let mut tried: Vec<Arc<Upstream>> = Vec::new();
+        let mut retries = 0;
+        loop {
+            let result = self
+                .send(&mut in_flight, &parts, &stripped, body.take(), deadline)
+                .await;
+
+            let Some(policy) = retry else {
+                return self.finish(result, in_flight, deadline);
+            };
+            let reason = match &result {
+                Ok(sent) if policy.statuses.contains(&sent.status().as_u16()) => {
+                    format!("HTTP {}", sent.status().as_u16())
+                }
+                // Nothing reached the upstream, or it dropped the connection
+                Err(ProxyError::Upstream(err)) if policy.connect_errors => err.to_string(),
+                Err(ProxyError::Timeout(TimeoutKind::Connect, after)) if policy.connect_errors => {
+                    format!("connect timed out after {after:?}")
+                }
+                _ => return self.finish(result, in_flight, deadline),
+            };
+            if retries >= policy.max_retries {
+                return self.finish(result, in_flight, deadline);
+            }
+            if !budget.try_retry() {
+                println!(
+                    "[gateway] retry budget used up, not retrying {} {}",
+                    parts.method,
+                    parts.uri.path()
+                );
+                return self.finish(result, in_flight, deadline);
+            }
+
+            let backoff = policy.backoff(retries + 1);
+            if deadline.is_some_and(|at| Instant::now() + backoff >= at) {
+                return self.finish(result, in_flight, deadline);
+            }
+            retries += 1;
+            tokio::time::sleep(backoff).await;
+            tried.push(in_flight.upstream().clone());
+            let next = if policy.other_upstream {
+                self.pool.pick(&parts.headers, &tried)
+            } else {
+                in_flight.upstream().start_request().map_err(Unavailable::CircuitOpen)
+            };
+            let Ok(next) = next else {
+                return self.finish(result, in_flight, deadline);
+            };
+            println!(
+                "[gateway] retrying {} {} on {} ({retries}/{}): {reason}",
+                parts.method,
+                parts.uri.path(),
+                next.upstream().url,
+                policy.max_retries
+            );
+            in_flight = next;
+        }
+    }
 
-        if stripped.is_empty() {
-            stripped = "/".to_string();
+    /// Send a request to switch protocols upstream. If the upstream agrees,
+    /// the client gets its `101` and the two connections are spliced in the
+    /// background, counting as outstanding on the upstream until they close;
+    /// any other answer is passed on as a normal response.
+    async fn upgrade(
+        &self,
+        parts: Parts,
+        path_and_query: &str,
+        body: Body,
+        client: OnUpgrade,
+        deadline: Option<Instant>,
+    ) -> Result<Response<Body>, ProxyError> {
+        let mut in_flight = self
+            .pool
+            .pick(&parts.headers, &[])
+            .map_err(ProxyError::Unavailable)?;
+        let body = OutboundBody::Streaming(Some(body)).take();
+        let sent = match self
+            .send(&mut in_flight, &parts, path_and_query, body, deadline)
+            .await
+        {
+            Ok(sent) if sent.status() == StatusCode::SWITCHING_PROTOCOLS => sent,
+            other => return self.finish(other, in_flight, deadline),
+        };
+
+        let mut response = Response::builder().status(StatusCode::SWITCHING_PROTOCOLS);
+        for (name, value) in sent.headers().iter() {
+            response = response.header(name, value);
         }
+        let protocol = sent
+            .headers()
+            .get(UPGRADE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("upgraded")
+            .to_string();
+        let upstream = sent
+            .upgrade()
+            .await
+            .map_err(|err| ProxyError::Upstream(anyhow::anyhow!("upgrade upstream connection: {err}")))?;
+
+        let idle = self.timeouts.upgrade_idle.unwrap_or(DEFAULT_UPGRADE_IDLE);
+        let path = parts.uri.path().to_string();
+        tokio::spawn(async move {
+            let url = &in_flight.upstream().url;
+            let client = match client.await {
+                Ok(client) => TokioIo::new(client),
+                Err(err) => {
+                    eprintln!("[gateway] {protocol} {path}: client connection not upgraded: {err}");
+                    return;
+                }
+            };
+            let started = Instant::now();
+            match upgrade::splice(client, upstream, idle).await {
+                Ok(spliced) => println!(
+                    "[gateway] {protocol} {path} to {url} closed after {:?} ({} bytes up, {} down)",
+                    started.elapsed(),
+                    spliced.client_to_upstream,
+                    spliced.upstream_to_client
+                ),
+                Err(err) => eprintln!(
+                    "[gateway] {protocol} {path} to {url} closed after {:?}: {err}",
+                    started.elapsed()
+                ),
+            }
+        });
+
+        response
+            .body(Body::empty())
+            .map_err(|err| ProxyError::Upstream(anyhow::anyhow!("build response: {err}")))
+    }
 
-        let target = format!("{}{}", self.upstream_base, stripped);
+    /// One attempt, up to the response headers; records the outcome on the
+    /// upstream. Limited by `first_byte` and by what is left until the
+    /// request's `deadline`, whichever comes first.
+    async fn send(
+        &self,
+        in_flight: &mut InFlight,
+        parts: &Parts,
+        path_and_query: &str,
+        body: reqwest::Body,
+        deadline: Option<Instant>,
+    ) -> Result<reqwest::Response, ProxyError> {
+        let target = format!("{}{}", in_flight.upstream().url, path_and_query);
 
-        let mut builder = self.client.request(parts.method, target);
+        let mut builder = self.client.request(parts.method.clone(), target);
         for (name, value) in parts.headers.iter() {
             builder = builder.header(name, value);
         }
 
-        let upstream = builder.body(body_bytes).send().await?;
-        let status = upstream.status();
-        let headers = upstream.headers().clone();
-        let upstream_body = upstream.bytes().await?;
+        // (time allowed, which timeout, its configured length)
+        let mut limit = self
+            .timeouts
+            .first_byte
+            .map(|first_byte| (first_byte, TimeoutKind::FirstByte, first_byte));
+        if let (Some(at), Some(request)) = (deadline, self.timeouts.request) {
+            let left = at.saturating_duration_since(Instant::now());
+            if limit.is_none_or(|(after, ..)| left < after) {
+                limit = Some((left, TimeoutKind::Request, request));
+            }
+        }
+
+        let send = builder.body(body).send();
+        let sent = match limit {
+            Some((after, kind, configured)) => match tokio::time::timeout(after, send).await {
+                Ok(sent) => sent.map_err(|err| self.send_error(err)),
+                Err(_) => Err(ProxyError::Timeout(kind, configured)),
+            },
+            None => send.await.map_err(|err| self.send_error(err)),
+        };
+        match &sent {
+            Ok(sent) => in_flight.finish(Ok(sent.status().as_u16())),
+            Err(err) => in_flight.finish(Err(&err.to_string())),
+        }
+        sent
+    }
+
+    /// Tell reqwest's connect timeout apart from other errors
+    fn send_error(&self, err: reqwest::Error) -> ProxyError {
+        if err.is_timeout()
+            && err.is_connect()
+            && let Some(connect) = self.timeouts.connect
+        {
+            return ProxyError::Timeout(TimeoutKind::Connect, connect);
+        }
+        ProxyError::Upstream(err.into())
+    }
 
-        let mut response = Response::builder().status(status);
-        for (name, value) in headers.iter() {
+    /// Turn the final attempt into the client's response
+    fn finish(
+        &self,
+        result: Result<reqwest::Response, ProxyError>,
+        in_flight: InFlight,
+        deadline: Option<Instant>,
+    ) -> Result<Response<Body>, ProxyError> {
+        let sent = result?;
+
+        let mut response = Response::builder().status(sent.status());
+        for (name, value) in sent.headers().iter() {
             response = response.header(name, value);
         }
 
         response
-            .body(Body::from(upstream_body))
-            .map_err(|err| anyhow::anyhow!("build response: {err}"))
+            .body(response_body(sent, in_flight, &self.timeouts, deadline))
+            .map_err(|err| ProxyError::Upstream(anyhow::anyhow!("build response: {err}")))
+    }
+}
+
+/// Stream the upstream's response body, holding `in_flight` until it ends.
+/// Gives up when no data arrives for the `idle` timeout, or when the body
+/// isn't done by the request's `deadline`.
+fn response_body(
+    sent: reqwest::Response,
+    in_flight: InFlight,
+    timeouts: &RouteTimeouts,
+    deadline: Option<Instant>,
+) -> Body {
+    let (idle, request) = (timeouts.idle, timeouts.request);
+    let stream = Box::pin(sent.bytes_stream());
+    Body::from_stream(futures_util::stream::unfold(
+        Some((stream, in_flight)),
+        move |state| async move {
+            let (mut stream, in_flight) = state?;
+            let idle_at = idle.map(|idle| Instant::now() + idle);
+            let give_up = match (idle_at, deadline) {
+                (Some(idle_at), Some(deadline)) => Some(idle_at.min(deadline)),
+                (idle_at, deadline) => idle_at.or(deadline),
+            };
+            let next = match give_up {
+                Some(at) => match tokio::time::timeout_at(at.into(), stream.next()).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        let url = &in_flight.upstream().url;
+                        let err: axum::BoxError = match (deadline, request, idle) {
+                            (Some(deadline), Some(request), _) if deadline <= at => {
+                                eprintln!(
+                                    "[gateway] upstream {url} did not finish within {request:?}, closing the response"
+                                );
+                                format!("request timeout after {request:?}").into()
+                            }
+                            (.., idle) => {
+                                let idle = idle.unwrap_or_default();
+                                eprintln!(
+                                    "[gateway] upstream {url} sent nothing for {idle:?}, closing the response"
+                                );
+                                format!("upstream idle for {idle:?}").into()
+                            }
+                        };
+                        return Some((Err(err), None));
+                    }
+                },
+                None => stream.next().await,
+            };
+            match next? {
+                Ok(chunk) => Some((Ok(chunk), Some((stream, in_flight)))),
+                Err(err) => Some((Err(err.into()), None)),
+            }
+        },
+    ))
+}
+
+/// Whether the request body can be kept in memory for another attempt. Only
+/// bodies whose length is known up front (from `Content-Length`, or empty)
+/// qualify: one that turned out too large halfway would be lost for the
+/// streaming fallback.
+fn is_replayable(body: &Body, max_body_bytes: usize) -> bool {
+    body.size_hint()
+        .exact()
+        .is_some_and(|len| len <= max_body_bytes as u64)
+}
+
+/// The path and query an upstream receives once `prefix` is removed:
+/// `/admin/users?x=1` under `/admin` becomes `/users?x=1`, `/admin` itself `/`
+pub(crate) fn strip_route_prefix(path_and_query: &str, prefix: &str) -> String {
+    let stripped = path_and_query.strip_prefix(prefix).unwrap_or(path_and_query);
+    if stripped.starts_with('/') {
+        stripped.to_string()
+    } else {
+        format!("/{stripped}")
     }
 }
 
@@ -75,3 +499,55 @@ pub fn bad_gateway(message: impl Into<String>) -> Response<Body> {
                 .unwrap()
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry::RetryBudgetConfig;
+
+    /// Upstream that answers right away, then sends one byte every 20ms forever
+    async fn dripping_upstream() -> String {
+        let app = axum::Router::new().route(
+            "/",
+            axum::routing::get(|| async {
+                let drip = futures_util::stream::unfold((), |()| async {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Some((Ok::<_, std::io::Error>(Bytes::from_static(b".")), ()))
+                });
+                Body::from_stream(drip)
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn request_deadline_ends_a_dripping_body() {
+        let mut route = RouteConfig::proxy(dripping_upstream().await);
+        route.timeouts = RouteTimeouts {
+            request: Some(Duration::from_millis(300)),
+            idle: Some(Duration::from_millis(200)),
+            ..RouteTimeouts::default()
+        };
+        let proxy = Proxy::for_route(&route, None).unwrap();
+        let budget = RetryBudget::new(RetryBudgetConfig::default());
+
+        let started = Instant::now();
+        let response = proxy
+            .forward(Request::new(Body::empty()), "", &budget)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Each byte comes well within `idle`, so only the deadline stops it
+        let err = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("request timeout after 300ms"), "{err}");
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(300), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    }
+}
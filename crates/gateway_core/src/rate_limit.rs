@@ -1,15 +1,494 @@
-#[derive(Debug, Clone)]
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+/// Number of independently locked shards; keeps contention low under load
+const SHARDS: usize = 16;
+
+/// Stand-in for a zero window, which validation rejects but a config built
+/// in code could still carry; rates and intervals divide by the window
+const MIN_WINDOW: Duration = Duration::from_millis(1);
+
+/// Rate limiting algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitAlgorithm {
+    /// Tokens refill continuously; allows bursts up to the bucket capacity
+    TokenBucket,
+    /// Counts requests in fixed, aligned windows
+    FixedWindow,
+    /// Weighted blend of the previous and current window (sliding window counter)
+    SlidingWindow,
+}
+
+impl FromStr for RateLimitAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "token_bucket" => Ok(Self::TokenBucket),
+            "fixed_window" => Ok(Self::FixedWindow),
+            "sliding_window" => Ok(Self::SlidingWindow),
+            other => Err(format!(
+                "unknown rate limit algorithm '{other}' (expected token_bucket, fixed_window or sliding_window)"
+            )),
+        }
+    }
+}
+
+/// What identifies a client for rate limiting purposes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKey {
+    ClientIp,
+    ApiKey,
+    UserId,
+    OrganisationId,
+}
+
+impl RateLimitKey {
+    /// Whether the key is only known after the auth middleware has run
+    pub fn requires_identity(&self) -> bool {
+        matches!(self, Self::ApiKey | Self::UserId | Self::OrganisationId)
+    }
+}
+
+impl FromStr for RateLimitKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "ip" | "client_ip" => Ok(Self::ClientIp),
+            "api_key" => Ok(Self::ApiKey),
+            "user" | "user_id" => Ok(Self::UserId),
+            "org" | "organisation" | "organisation_id" => Ok(Self::OrganisationId),
+            other => Err(format!(
+                "unknown rate limit key '{other}' (expected client_ip, api_key, user_id or organisation_id)"
+            )),
+        }
+    }
+}
+
+/// Rate limit settings for a route (or the gateway default)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitConfig {
+    pub algorithm: RateLimitAlgorithm,
+    pub key: RateLimitKey,
+    /// Requests allowed per window
+    pub limit: u32,
+    pub window: Duration,
+    /// Token bucket capacity; defaults to `limit`
+    pub burst: Option<u32>,
+}
+
+impl RateLimitConfig {
+    pub fn per_minute(limit: u32) -> Self {
+        Self {
+            algorithm: RateLimitAlgorithm::TokenBucket,
+            key: RateLimitKey::ClientIp,
+            limit,
+            window: Duration::from_secs(60),
+            burst: None,
+        }
+    }
+}
+
+/// Outcome of a rate limit check, used to build `X-RateLimit-*` headers
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Time until the limit fully resets
+    pub reset_after: Duration,
+    /// Time until the next request would be allowed (only set when rejected)
+    pub retry_after: Option<Duration>,
+}
+
+#[derive(Debug)]
+enum BucketState {
+    Token { tokens: f64, refilled_at: Instant },
+    Fixed { started_at: Instant, count: u32 },
+    Sliding { started_at: Instant, current: u32, previous: u32 },
+}
+
+#[derive(Debug)]
+struct Bucket {
+    state: BucketState,
+    last_seen: Instant,
+}
+
+/// Concurrent per-client rate limiter
+#[derive(Debug)]
 pub struct RateLimiter {
-    max_per_minute: u32,
+    config: RateLimitConfig,
+    shards: Vec<Mutex<HashMap<String, Bucket>>>,
 }
 
 impl RateLimiter {
-    pub fn new(max_per_minute: u32) -> Self {
-        Self { max_per_minute }
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    pub fn allow(&self, key: &str) -> bool {
+        self.check(key).allowed
+    }
+
+    /// Record a request for `key` and decide whether it may proceed
+    pub fn check(&self, key: &str) -> RateLimitDecision {
+        let now = Instant::now();
+        let mut shard = self.shard(key).lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = shard.entry(key.to_string()).or_insert_with(|| Bucket {
+            state: self.initial_state(now),
+            last_seen: now,
+        });
+        bucket.last_seen = now;
+        self.apply(&mut bucket.state, now)
+    }
+
+    /// Drop buckets that have not been used for `idle`
+    pub fn evict_idle(&self, idle: Duration) -> usize {
+        self.evict_idle_at(Instant::now(), idle)
+    }
+
+    fn evict_idle_at(&self, now: Instant, idle: Duration) -> usize {
+        let mut evicted = 0;
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+            let before = shard.len();
+            shard.retain(|_, bucket| now.duration_since(bucket.last_seen) < idle);
+            evicted += before - shard.len();
+        }
+        evicted
+    }
+
+    /// Number of tracked clients
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.lock().unwrap_or_else(|e| e.into_inner()).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Periodically evict idle buckets in the background.
+    /// The task stops once the limiter is dropped.
+    pub fn spawn_eviction(self: &Arc<Self>) {
+        let limiter: Weak<Self> = Arc::downgrade(self);
+        // A bucket idle for two windows carries no state worth keeping
+        let idle = self.window() * 2;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(idle);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(limiter) = limiter.upgrade() else {
+                    break;
+                };
+                limiter.evict_idle(idle);
+            }
+        });
+    }
+
+    fn shard(&self, key: &str) -> &Mutex<HashMap<String, Bucket>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
     }
 
-    pub fn allow(&self, _ip: &str) -> bool {
-        let _ = self.max_per_minute;
-        true
+    fn window(&self) -> Duration {
+        self.config.window.max(MIN_WINDOW)
+    }
+
+    fn capacity(&self) -> u32 {
+        self.config.burst.unwrap_or(self.config.limit).max(1)
+    }
+
+    fn initial_state(&self, now: Instant) -> BucketState {
+        match self.config.algorithm {
+            RateLimitAlgorithm::TokenBucket => BucketState::Token {
+                tokens: self.capacity() as f64,
+                refilled_at: now,
+            },
+            RateLimitAlgorithm::FixedWindow => BucketState::Fixed {
+                started_at: now,
+                count: 0,
+            },
+            RateLimitAlgorithm::SlidingWindow => BucketState::Sliding {
+                started_at: now,
+                current: 0,
+                previous: 0,
+            },
+        }
+    }
+
+    fn apply(&self, state: &mut BucketState, now: Instant) -> RateLimitDecision {
+        let limit = self.config.limit;
+        let window = self.window();
+
+        match state {
+            BucketState::Token {
+                tokens,
+                refilled_at,
+            } => {
+                let capacity = self.capacity() as f64;
+                let rate = limit.max(1) as f64 / window.as_secs_f64();
+                let elapsed = now.duration_since(*refilled_at).as_secs_f64();
+                *tokens = (*tokens + elapsed * rate).min(capacity);
+                *refilled_at = now;
+
+                let allowed = *tokens >= 1.0;
+                if allowed {
+                    *tokens -= 1.0;
+                }
+                let reset_after = Duration::from_secs_f64((capacity - *tokens) / rate);
+                let retry_after =
+                    (!allowed).then(|| Duration::from_secs_f64((1.0 - *tokens) / rate));
+                RateLimitDecision {
+                    allowed,
+                    limit: self.capacity(),
+                    remaining: *tokens as u32,
+                    reset_after,
+                    retry_after,
+                }
+            }
+            BucketState::Fixed { started_at, count } => {
+                let mut elapsed = now.duration_since(*started_at);
+                if elapsed >= window {
+                    // Keep windows aligned to the first request of the bucket
+                    *started_at = now - window_offset(elapsed, window);
+                    *count = 0;
+                    elapsed = now.duration_since(*started_at);
+                }
+
+                let allowed = *count < limit;
+                if allowed {
+                    *count += 1;
+                }
+                let reset_after = window.saturating_sub(elapsed);
+                RateLimitDecision {
+                    allowed,
+                    limit,
+                    remaining: limit.saturating_sub(*count),
+                    reset_after,
+                    retry_after: (!allowed).then_some(reset_after),
+                }
+            }
+            BucketState::Sliding {
+                started_at,
+                current,
+                previous,
+            } => {
+                let mut elapsed = now.duration_since(*started_at);
+                if elapsed >= window {
+                    let skipped = elapsed.as_nanos() / window.as_nanos();
+                    *previous = if skipped == 1 { *current } else { 0 };
+                    *current = 0;
+                    *started_at = now - window_offset(elapsed, window);
+                    elapsed = now.duration_since(*started_at);
+                }
+
+                let progress = elapsed.as_secs_f64() / window.as_secs_f64();
+                let weighted_previous = *previous as f64 * (1.0 - progress);
+                let estimated = weighted_previous + *current as f64;
+
+                let allowed = estimated + 1.0 <= limit as f64;
+                if allowed {
+                    *current += 1;
+                }
+                let used = (weighted_previous + *current as f64).ceil() as u32;
+                let until_next_window = window.saturating_sub(elapsed);
+
+                let retry_after = (!allowed).then(|| {
+                    let headroom = limit as f64 - *current as f64 - 1.0;
+                    if headroom < 0.0 || *previous == 0 {
+                        until_next_window
+                    } else {
+                        // Wait until enough of the previous window has slid out
+                        let target = 1.0 - headroom / *previous as f64;
+                        window
+                            .mul_f64(target.clamp(0.0, 1.0))
+                            .saturating_sub(elapsed)
+                    }
+                });
+                RateLimitDecision {
+                    allowed,
+                    limit,
+                    remaining: limit.saturating_sub(used),
+                    reset_after: until_next_window,
+                    retry_after,
+                }
+            }
+        }
+    }
+}
+
+/// Position of `elapsed` within the current window
+fn window_offset(elapsed: Duration, window: Duration) -> Duration {
+    Duration::from_nanos((elapsed.as_nanos() % window.as_nanos().max(1)) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(algorithm: RateLimitAlgorithm, limit: u32, window_secs: u64) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            algorithm,
+            key: RateLimitKey::ClientIp,
+            limit,
+            window: Duration::from_secs(window_secs),
+            burst: None,
+        })
+    }
+
+    fn secs(s: f64) -> Duration {
+        Duration::from_secs_f64(s)
+    }
+
+    fn assert_close(actual: Option<Duration>, expected: Duration) {
+        let actual = actual.expect("rejected requests carry a retry_after");
+        let diff = actual.abs_diff(expected);
+        assert!(diff < Duration::from_millis(1), "{actual:?} != {expected:?}");
+    }
+
+    /// Run `n` requests at `at` and return how many were allowed
+    fn allowed(limiter: &RateLimiter, state: &mut BucketState, at: Instant, n: u32) -> u32 {
+        (0..n).filter(|_| limiter.apply(state, at).allowed).count() as u32
+    }
+
+    #[test]
+    fn token_bucket_allows_a_burst_then_refills() {
+        // 10 per 10s refills one token a second, up to a burst of 3
+        let limiter = RateLimiter::new(RateLimitConfig {
+            burst: Some(3),
+            ..limiter(RateLimitAlgorithm::TokenBucket, 10, 10).config
+        });
+        let t0 = Instant::now();
+        let mut state = limiter.initial_state(t0);
+
+        let first = limiter.apply(&mut state, t0);
+        assert!(first.allowed);
+        assert_eq!((first.limit, first.remaining), (3, 2));
+        assert_close(Some(first.reset_after), secs(1.0));
+        assert_eq!(allowed(&limiter, &mut state, t0, 2), 2);
+
+        let rejected = limiter.apply(&mut state, t0);
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.remaining, 0);
+        assert_close(rejected.retry_after, secs(1.0));
+
+        // Half a token short after 1.5s: one request, then half a second to wait
+        let later = t0 + secs(1.5);
+        assert_eq!(allowed(&limiter, &mut state, later, 1), 1);
+        assert_close(limiter.apply(&mut state, later).retry_after, secs(0.5));
+
+        // A long pause refills no more than the burst
+        let idle = t0 + secs(60.0);
+        assert_eq!(allowed(&limiter, &mut state, idle, 5), 3);
+    }
+
+    #[test]
+    fn fixed_window_resets_on_aligned_boundaries() {
+        let limiter = limiter(RateLimitAlgorithm::FixedWindow, 2, 10);
+        let t0 = Instant::now();
+        let mut state = limiter.initial_state(t0);
+
+        assert_eq!(allowed(&limiter, &mut state, t0, 3), 2);
+        let rejected = limiter.apply(&mut state, t0 + secs(4.0));
+        assert!(!rejected.allowed);
+        assert_close(rejected.retry_after, secs(6.0));
+
+        // 25s in is 5s into the third window, not the start of a new one
+        let decision = limiter.apply(&mut state, t0 + secs(25.0));
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 1);
+        assert_close(Some(decision.reset_after), secs(5.0));
+        assert_eq!(allowed(&limiter, &mut state, t0 + secs(29.0), 2), 1);
+        assert_eq!(allowed(&limiter, &mut state, t0 + secs(30.0), 2), 2);
+    }
+
+    #[test]
+    fn sliding_window_weighs_the_previous_window() {
+        let limiter = limiter(RateLimitAlgorithm::SlidingWindow, 10, 10);
+        let t0 = Instant::now();
+        let mut state = limiter.initial_state(t0);
+
+        assert_eq!(allowed(&limiter, &mut state, t0, 10), 10);
+        // Nothing to slide out yet: wait for the next window
+        assert_close(limiter.apply(&mut state, t0 + secs(1.0)).retry_after, secs(9.0));
+
+        // A quarter into the next window 7.5 of the previous 10 still count
+        let quarter = t0 + secs(12.5);
+        assert_eq!(allowed(&limiter, &mut state, quarter, 2), 2);
+        let rejected = limiter.apply(&mut state, quarter);
+        assert!(!rejected.allowed);
+        // Room for one more once the previous window weighs 7: at 30%, in 0.5s
+        assert_close(rejected.retry_after, secs(0.5));
+        assert!(!limiter.apply(&mut state, t0 + secs(12.9)).allowed);
+        assert!(limiter.apply(&mut state, t0 + secs(13.1)).allowed);
+    }
+
+    #[test]
+    fn sliding_window_forgets_after_an_idle_window() {
+        let limiter = limiter(RateLimitAlgorithm::SlidingWindow, 10, 10);
+        let t0 = Instant::now();
+        let mut state = limiter.initial_state(t0);
+        assert_eq!(allowed(&limiter, &mut state, t0, 10), 10);
+
+        // Two windows on, the full window before carries no weight
+        let later = t0 + secs(25.0);
+        assert_eq!(allowed(&limiter, &mut state, later, 11), 10);
+        let BucketState::Sliding { previous, .. } = state else {
+            panic!("sliding window state expected");
+        };
+        assert_eq!(previous, 0);
+        assert_close(limiter.apply(&mut state, later).retry_after, secs(5.0));
+    }
+
+    #[test]
+    fn zero_window_does_not_panic() {
+        for algorithm in [
+            RateLimitAlgorithm::TokenBucket,
+            RateLimitAlgorithm::FixedWindow,
+            RateLimitAlgorithm::SlidingWindow,
+        ] {
+            let limiter = limiter(algorithm, 1, 0);
+            limiter.check("client");
+            limiter.check("client");
+        }
+    }
+
+    #[test]
+    fn evict_idle_drops_only_idle_buckets() {
+        let limiter = limiter(RateLimitAlgorithm::FixedWindow, 10, 60);
+        let start = Instant::now();
+        limiter.check("idle");
+        limiter.check("active");
+        assert_eq!(limiter.len(), 2);
+
+        // Two minutes on, only "active" was seen in the last minute. Moving
+        // forward from `start` rather than back keeps clear of the clock's
+        // origin on freshly booted machines.
+        for shard in &limiter.shards {
+            if let Some(bucket) = shard.lock().unwrap().get_mut("active") {
+                bucket.last_seen = start + secs(90.0);
+            }
+        }
+
+        assert_eq!(limiter.evict_idle_at(start + secs(120.0), secs(60.0)), 1);
+        assert_eq!(limiter.len(), 1);
+        assert!(limiter.shard("active").lock().unwrap().contains_key("active"));
     }
 }
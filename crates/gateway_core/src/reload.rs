@@ -0,0 +1,99 @@
+//! Runtime reload of the gateway route table.
+//!
+//! The config is re-read on `SIGHUP` and, when `GATEWAY_CONFIG` points at a
+//! file, whenever that file changes. A config that fails to load or validate is
+//! logged and discarded; the last good route table keeps serving.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::config::GatewayConfig;
+use crate::server::Gateway;
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Start the background reload triggers for `gateway`
+pub fn spawn(gateway: Arc<Gateway>) {
+    #[cfg(unix)]
+    spawn_sighup(gateway.clone());
+
+    if let Some(path) = config_path() {
+        let interval = poll_interval();
+        if !interval.is_zero() {
+            spawn_file_watch(gateway, path, interval);
+        }
+    }
+}
+
+/// Re-read the config and swap it in, keeping the current table on failure
+pub fn reload(gateway: &Gateway) -> bool {
+    let result = GatewayConfig::load().and_then(|config| gateway.reload(&config));
+    match result {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("[gateway] config reload rejected, keeping last good config: {err:#}");
+            false
+        }
+    }
+}
+
+/// How often watched files are checked for changes (`GATEWAY_CONFIG_POLL_SECS`)
+pub(crate) fn poll_interval() -> Duration {
+    std::env::var("GATEWAY_CONFIG_POLL_SECS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_POLL_INTERVAL)
+}
+
+fn config_path() -> Option<PathBuf> {
+    std::env::var("GATEWAY_CONFIG")
+        .ok()
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+}
+
+#[cfg(unix)]
+fn spawn_sighup(gateway: Arc<Gateway>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            eprintln!("[gateway] SIGHUP reload unavailable: {err}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            println!("[gateway] SIGHUP received");
+            reload(&gateway);
+        }
+    });
+}
+
+/// Poll the file's metadata; editors often replace files on save, which
+/// breaks inode-based watchers but still shows up as a new mtime or size
+fn spawn_file_watch(gateway: Arc<Gateway>, path: PathBuf, interval: Duration) {
+    tokio::spawn(async move {
+        let mut last = fingerprint(&path);
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let current = fingerprint(&path);
+            if current.is_none() || current == last {
+                continue;
+            }
+            last = current;
+            println!("[gateway] {} changed", path.display());
+            reload(&gateway);
+        }
+    });
+}
+
+fn fingerprint(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
@@ -0,0 +1,252 @@
+//! Retrying proxied requests.
+//!
+//! A route with a `retry` policy sends a request again when the upstream
+//! couldn't be reached or dropped the connection before answering, or when it
+//! answered with one of the policy's status codes. Only idempotent methods are
+//! retried unless the policy lists others, and only requests whose body is
+//! small enough to keep for a second attempt. Attempts wait an exponentially
+//! growing, jittered backoff and go to another upstream of the route when
+//! there is one.
+//!
+//! All routes share one [`RetryBudget`] so that during an outage retries can't
+//! multiply the load on upstreams that are already struggling.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::http::Method;
+
+/// Methods that are safe to send twice
+pub const IDEMPOTENT_METHODS: &[&str] = &["GET", "HEAD", "OPTIONS", "TRACE", "PUT", "DELETE"];
+
+/// Retry settings for a proxied route
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts after the first
+    pub max_retries: u32,
+    /// Retry when the upstream couldn't be reached or dropped the connection
+    pub connect_errors: bool,
+    /// Response statuses that are retried
+    pub statuses: Vec<u16>,
+    /// Methods that are retried (upper case)
+    pub methods: Vec<String>,
+    /// Backoff before the first retry; doubles for every further one
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Retry on another of the route's upstreams when there is one
+    pub other_upstream: bool,
+    /// Larger request bodies, or bodies of unknown length, aren't retried
+    pub max_body_bytes: usize,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            connect_errors: true,
+            statuses: vec![502, 503, 504],
+            methods: IDEMPOTENT_METHODS.iter().map(|m| m.to_string()).collect(),
+            backoff: Duration::from_millis(25),
+            max_backoff: Duration::from_secs(1),
+            other_upstream: true,
+            max_body_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn allows_method(&self, method: &Method) -> bool {
+        self.methods.iter().any(|m| m == method.as_str())
+    }
+
+    /// Random delay up to the exponential backoff for retry number `retry`
+    /// (starting at 1), so clients that failed together don't retry together
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff);
+        ceiling.mul_f64(fastrand::f64())
+    }
+}
+
+/// Gateway-wide limit on retries
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryBudgetConfig {
+    /// Retries allowed as a share of requests in the window
+    pub ratio: f64,
+    /// Retries per second allowed even when there is little traffic
+    pub min_per_sec: u32,
+    pub window: Duration,
+}
+
+impl Default for RetryBudgetConfig {
+    fn default() -> Self {
+        Self {
+            ratio: 0.2,
+            min_per_sec: 10,
+            window: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Counts requests and retries over a window; a retry is only allowed while
+/// retries stay within the budget
+pub struct RetryBudget {
+    config: RetryBudgetConfig,
+    state: Mutex<BudgetWindow>,
+}
+
+struct BudgetWindow {
+    started: Instant,
+    requests: u64,
+    retries: u64,
+}
+
+impl RetryBudget {
+    pub fn new(config: RetryBudgetConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BudgetWindow {
+                started: Instant::now(),
+                requests: 0,
+                retries: 0,
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &RetryBudgetConfig {
+        &self.config
+    }
+
+    fn window(&self) -> std::sync::MutexGuard<'_, BudgetWindow> {
+        let mut window = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if window.started.elapsed() >= self.config.window {
+            *window = BudgetWindow {
+                started: Instant::now(),
+                requests: 0,
+                retries: 0,
+            };
+        }
+        window
+    }
+
+    /// Count a proxied request
+    pub fn record_request(&self) {
+        self.window().requests += 1;
+    }
+
+    /// Take a retry from the budget; `false` when it is used up
+    pub fn try_retry(&self) -> bool {
+        let mut window = self.window();
+        let floor = self.config.min_per_sec as f64 * self.config.window.as_secs_f64();
+        let allowed = (window.requests as f64 * self.config.ratio).max(floor);
+        if (window.retries as f64) < allowed {
+            window.retries += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(policy: &RetryPolicy, retry: u32) -> Vec<Duration> {
+        (0..200).map(|_| policy.backoff(retry)).collect()
+    }
+
+    #[test]
+    fn backoff_ceiling_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(350),
+            ..RetryPolicy::default()
+        };
+        for (retry, ceiling) in [(1, 100), (2, 200), (3, 350), (10, 350), (u32::MAX, 350)] {
+            let ceiling = Duration::from_millis(ceiling);
+            let samples = samples(&policy, retry);
+            assert!(samples.iter().all(|d| *d <= ceiling), "retry {retry}: {samples:?}");
+            // Jittered over the range rather than always the ceiling
+            assert!(samples.iter().any(|d| *d > ceiling / 2), "retry {retry}");
+            assert!(samples.iter().any(|d| *d < ceiling / 2), "retry {retry}");
+        }
+    }
+
+    #[test]
+    fn zero_backoff_retries_at_once() {
+        let policy = RetryPolicy {
+            backoff: Duration::ZERO,
+            ..RetryPolicy::default()
+        };
+        assert!(samples(&policy, 3).iter().all(|d| d.is_zero()));
+    }
+
+    #[test]
+    fn idempotent_methods_are_retried_by_default() {
+        let policy = RetryPolicy::default();
+        for method in [Method::GET, Method::HEAD, Method::OPTIONS, Method::PUT, Method::DELETE] {
+            assert!(policy.allows_method(&method), "{method}");
+        }
+        for method in [Method::POST, Method::PATCH] {
+            assert!(!policy.allows_method(&method), "{method}");
+        }
+
+        let policy = RetryPolicy {
+            methods: vec!["POST".to_string()],
+            ..RetryPolicy::default()
+        };
+        assert!(policy.allows_method(&Method::POST));
+        assert!(!policy.allows_method(&Method::GET));
+    }
+
+    fn budget(ratio: f64, min_per_sec: u32, window: Duration) -> RetryBudget {
+        RetryBudget::new(RetryBudgetConfig {
+            ratio,
+            min_per_sec,
+            window,
+        })
+    }
+
+    fn retries_allowed(budget: &RetryBudget) -> usize {
+        std::iter::from_fn(|| budget.try_retry().then_some(())).take(1_000).count()
+    }
+
+    #[test]
+    fn budget_floor_allows_retries_without_traffic() {
+        let budget = budget(0.2, 2, Duration::from_secs(10));
+        assert_eq!(retries_allowed(&budget), 20);
+    }
+
+    #[test]
+    fn budget_limits_retries_to_a_share_of_requests() {
+        let budget = budget(0.2, 0, Duration::from_secs(10));
+        assert!(!budget.try_retry());
+        for _ in 0..100 {
+            budget.record_request();
+        }
+        assert_eq!(retries_allowed(&budget), 20);
+
+        // More traffic makes room for more retries
+        for _ in 0..10 {
+            budget.record_request();
+        }
+        assert_eq!(retries_allowed(&budget), 2);
+    }
+
+    #[test]
+    fn budget_resets_with_the_window() {
+        let budget = budget(1.0, 0, Duration::from_millis(50));
+        budget.record_request();
+        assert!(budget.try_retry());
+        assert!(!budget.try_retry());
+
+        std::thread::sleep(Duration::from_millis(60));
+        // The new window has seen no requests yet
+        assert!(!budget.try_retry());
+        budget.record_request();
+        assert!(budget.try_retry());
+    }
+}
@@ -1,75 +1,300 @@
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::Instant;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use arc_swap::ArcSwap;
 use axum::Router;
 use axum::body::Body;
-use axum::extract::Extension;
-use axum::http::{HeaderName, HeaderValue, Request, Response, StatusCode};
+use axum::extract::{ConnectInfo, Extension};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Uri};
 use axum::middleware::Next;
 use axum::response::IntoResponse;
 use axum::routing::any;
+use common::identity::{self, IdentitySigner};
+use common::service_auth;
+use common::shutdown::Shutdown;
+use contracts::Role;
+use ipnet::IpNet;
+use tower::{Layer, ServiceExt};
 
-use crate::config::{GatewayConfig, RouteMode};
+use crate::api_key;
+use crate::authz::{self, Principal};
+use crate::balance::Unavailable;
+use crate::config::{GatewayConfig, RouteMode, parse_proxy_range};
+use crate::health;
 use crate::middleware;
-use crate::proxy::{Proxy, bad_gateway};
-use crate::rate_limit::RateLimiter;
-use crate::types::Request as GatewayRequest;
+use crate::proxy::{Proxy, ProxyError, bad_gateway, strip_route_prefix};
+use crate::rate_limit::{RateLimitConfig, RateLimitDecision, RateLimitKey, RateLimiter};
+use crate::reload;
+use crate::retry::RetryBudget;
+use crate::types::{Request as GatewayRequest, Response as GatewayResponse};
+use crate::upstream::UpstreamStatus;
+
+/// Per-route runtime: middleware pipeline, rate limiter and upstream proxy
+struct RouteState {
+    pipeline: middleware::Pipeline,
+    limiter: Option<Arc<RateLimiter>>,
+    /// Present when the route is proxied rather than embedded
+    proxy: Option<Proxy>,
+    strip_prefix: bool,
+}
 
-#[derive(Clone)]
 struct GatewayState {
-    limiter: Arc<RateLimiter>,
-    pipeline: Arc<middleware::Pipeline>,
-    proxies: HashMap<String, Proxy>,
+    config: GatewayConfig,
+    signer: IdentitySigner,
+    routes: HashMap<String, RouteState>,
+    /// Used for paths outside every configured route
+    default_pipeline: middleware::Pipeline,
+    default_limiter: Option<Arc<RateLimiter>>,
+    retry_budget: Arc<RetryBudget>,
+    /// `config.trusted_proxies`, parsed
+    trusted_proxies: Vec<IpNet>,
 }
 
-/// Run gateway with default configuration (uses env vars for route modes)
-pub async fn run(config: &GatewayConfig) -> anyhow::Result<()> {
-    run_with_routers(config, HashMap::new()).await
+impl GatewayState {
+    /// Build the runtime for `config`. Rate limiters whose settings did not
+    /// change are carried over from `previous` so a reload keeps client counts.
+    fn build(
+        config: &GatewayConfig,
+        routers: &HashMap<String, Router>,
+        previous: Option<&GatewayState>,
+    ) -> anyhow::Result<Self> {
+        let default_limiter = reuse_limiter(
+            config.rate_limit.as_ref(),
+            previous.and_then(|p| p.default_limiter.as_ref()),
+        );
+
+        let mut routes = HashMap::new();
+        for (route, route_config) in &config.routes {
+            // Build proxies for routes that are in proxy mode and not embedded
+            let should_proxy =
+                route_config.mode == RouteMode::Proxy || !routers.contains_key(route);
+            let proxy = if should_proxy && !route_config.upstreams.is_empty() {
+                let urls: Vec<&str> = route_config.upstreams.iter().map(|u| u.url.as_str()).collect();
+                if urls.len() == 1 {
+                    println!("  route {} -> proxy to {}", route, urls[0]);
+                } else {
+                    println!(
+                        "  route {} -> proxy to {} ({})",
+                        route,
+                        urls.join(", "),
+                        route_config.balance
+                    );
+                }
+                let previous = previous
+                    .and_then(|p| p.routes.get(route))
+                    .and_then(|r| r.proxy.as_ref());
+                Some(Proxy::for_route(route_config, previous)?)
+            } else if routers.contains_key(route) {
+                println!("  route {} -> embedded", route);
+                None
+            } else {
+                println!("  route {} -> skipped (embedded but no router available)", route);
+                continue;
+            };
+
+            let limiter = match &route_config.rate_limit {
+                Some(rl) => reuse_limiter(
+                    Some(rl),
+                    previous
+                        .and_then(|p| p.routes.get(route))
+                        .and_then(|r| r.limiter.as_ref()),
+                ),
+                None => default_limiter.clone(),
+            };
+
+            let pipeline = middleware::build_pipeline(
+                &route_config.middleware,
+                route_config.auth,
+                &route_config.access,
+                &config.plugins,
+            )
+            .map_err(|err| anyhow::anyhow!("route {route}: {err:#}"))?;
+
+            routes.insert(
+                route.clone(),
+                RouteState {
+                    pipeline,
+                    limiter,
+                    proxy,
+                    strip_prefix: route_config.strip_prefix,
+                },
+            );
+        }
+
+        Ok(Self {
+            config: config.clone(),
+            signer: IdentitySigner::new(&config.identity_secret),
+            routes,
+            default_pipeline: middleware::default_pipeline(),
+            default_limiter,
+            // Keep the budget's counts across reloads unless its settings changed
+            retry_budget: match previous.map(|p| &p.retry_budget) {
+                Some(budget) if budget.config() == &config.retry_budget => budget.clone(),
+                _ => Arc::new(RetryBudget::new(config.retry_budget.clone())),
+            },
+            trusted_proxies: config
+                .trusted_proxies
+                .iter()
+                .filter_map(|p| parse_proxy_range(p))
+                .collect(),
+        })
+    }
+
+    fn route_for(&self, path: &str) -> Option<&RouteState> {
+        self.config
+            .route_for(path)
+            .and_then(|route| self.routes.get(route))
+    }
+
+    fn pipeline_for(&self, path: &str) -> &middleware::Pipeline {
+        self.route_for(path)
+            .map(|r| &r.pipeline)
+            .unwrap_or(&self.default_pipeline)
+    }
+
+    fn limiter_for(&self, path: &str) -> Option<&Arc<RateLimiter>> {
+        match self.route_for(path) {
+            Some(route) => route.limiter.as_ref(),
+            None => self.default_limiter.as_ref(),
+        }
+    }
 }
 
-/// Run gateway with embedded routers for specific routes
-/// Routes not in `routers` map will be proxied based on config
-pub async fn run_with_routers(
-    config: &GatewayConfig,
+/// Keep the existing limiter when its settings are unchanged, otherwise start a new one
+fn reuse_limiter(
+    config: Option<&RateLimitConfig>,
+    existing: Option<&Arc<RateLimiter>>,
+) -> Option<Arc<RateLimiter>> {
+    let config = config?;
+    if let Some(existing) = existing.filter(|l| l.config() == config) {
+        return Some(existing.clone());
+    }
+    let limiter = Arc::new(RateLimiter::new(config.clone()));
+    limiter.spawn_eviction();
+    Some(limiter)
+}
+
+/// A complete route table: the state and the router built from it
+struct Snapshot {
+    state: Arc<GatewayState>,
+    router: Router,
+}
+
+/// Running gateway whose route table can be replaced without a restart.
+///
+/// Each request is dispatched to the snapshot that was current when it
+/// arrived, so in-flight requests finish on the table they started with.
+pub struct Gateway {
     routers: HashMap<String, Router>,
-) -> anyhow::Result<()> {
-    println!("gateway listening on {}", config.listen_addr);
+    current: ArcSwap<Snapshot>,
+    /// Serialises reloads so concurrent triggers cannot interleave
+    reload_lock: Mutex<()>,
+}
+
+impl Gateway {
+    pub fn new(config: &GatewayConfig, routers: HashMap<String, Router>) -> anyhow::Result<Self> {
+        let snapshot = build_snapshot(config, &routers, None)?;
+        Ok(Self {
+            routers,
+            current: ArcSwap::from_pointee(snapshot),
+            reload_lock: Mutex::new(()),
+        })
+    }
+
+    /// Configuration of the active route table
+    pub fn config(&self) -> GatewayConfig {
+        self.current.load().state.config.clone()
+    }
+
+    /// Health of the upstreams of every proxied route in the active table
+    pub fn upstream_status(&self) -> BTreeMap<String, Vec<UpstreamStatus>> {
+        self.current
+            .load()
+            .state
+            .routes
+            .iter()
+            .filter_map(|(route, r)| {
+                let proxy = r.proxy.as_ref()?;
+                Some((route.clone(), proxy.pool().status()))
+            })
+            .collect()
+    }
 
-    let limiter = Arc::new(RateLimiter::new(100));
-    let pipeline = Arc::new(middleware::default_pipeline());
+    /// Validate `config` and atomically swap it in. On error the current
+    /// route table stays active.
+    pub fn reload(&self, config: &GatewayConfig) -> anyhow::Result<()> {
+        config.validate()?;
+        let _guard = self.reload_lock.lock().unwrap_or_else(|e| e.into_inner());
 
-    // Build proxies for routes that are in proxy mode and not embedded
-    let mut proxies = HashMap::new();
-    for (route, route_config) in &config.routes {
-        let should_proxy = route_config.mode == RouteMode::Proxy || !routers.contains_key(route);
-        if should_proxy && !route_config.upstream_base.is_empty() {
+        let current = self.current.load_full();
+        if config.listen_addr != current.state.config.listen_addr {
             println!(
-                "  route {} -> proxy to {}",
-                route, route_config.upstream_base
+                "[gateway] listen_addr change to {} ignored until restart",
+                config.listen_addr
             );
-            proxies.insert(route.clone(), Proxy::new(&route_config.upstream_base));
-        } else if routers.contains_key(route) {
-            println!("  route {} -> embedded", route);
         }
+
+        println!("[gateway] reloading route table");
+        let snapshot = build_snapshot(config, &self.routers, Some(&current.state))?;
+        self.current.store(Arc::new(snapshot));
+        println!("[gateway] route table reloaded");
+        Ok(())
     }
 
-    let state = Arc::new(GatewayState {
-        limiter,
-        pipeline,
-        proxies,
-    });
+    /// Route a request on the current table, answering 504 when it isn't
+    /// answered within the gateway's `request_timeout`
+    async fn dispatch(self: Arc<Self>, req: Request<Body>) -> Response<Body> {
+        let (router, request_timeout) = {
+            let current = self.current.load();
+            (current.router.clone(), current.state.config.request_timeout)
+        };
+        let Some(request_timeout) = request_timeout else {
+            return match router.oneshot(req).await {
+                Ok(response) => response,
+                Err(err) => match err {},
+            };
+        };
+        let path = req.uri().path().to_string();
+        match tokio::time::timeout(request_timeout, router.oneshot(req)).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(err)) => match err {},
+            Err(_) => {
+                println!("[gateway] {path} not answered within {request_timeout:?}");
+                gateway_timeout(
+                    &format!("request not answered within {request_timeout:?}"),
+                    &path,
+                    "gateway",
+                    request_timeout,
+                )
+            }
+        }
+    }
+}
+
+fn build_snapshot(
+    config: &GatewayConfig,
+    routers: &HashMap<String, Router>,
+    previous: Option<&GatewayState>,
+) -> anyhow::Result<Snapshot> {
+    let state = Arc::new(GatewayState::build(config, routers, previous)?);
 
     // Build the router
     let mut app = Router::new();
 
-    // Add embedded routers
+    // Add embedded routers, unless the route has been switched to proxy mode
     for (route, router) in routers {
-        app = app.nest(&route, router);
+        if !config.is_proxy(route) {
+            app = app.nest(route, router.clone());
+        }
     }
 
     // Add proxy routes for remaining routes
-    for (route, _) in &state.proxies {
+    let proxied = state.routes.iter().filter(|(_, r)| r.proxy.is_some());
+    for (route, _) in proxied {
         let route_path = route.clone();
         let route_any = route.clone();
         let route_wildcard = format!("{}{{*path}}", route);
@@ -88,37 +313,103 @@ pub async fn run_with_routers(
             );
     }
 
-    let app = app
-        .layer(axum::middleware::from_fn(gateway_checks))
+    // Checks run before routing so pipeline steps can rewrite the path
+    let checked = axum::middleware::from_fn(gateway_checks).layer(app);
+    let router = Router::new()
+        .fallback_service(checked)
         .layer(Extension(state.clone()));
+    Ok(Snapshot { state, router })
+}
+
+/// Run gateway with default configuration (uses env vars for route modes)
+pub async fn run(config: &GatewayConfig, shutdown: Shutdown) -> anyhow::Result<()> {
+    run_with_routers(config, HashMap::new(), shutdown).await
+}
+
+/// Run gateway with embedded routers for specific routes
+/// Routes not in `routers` map will be proxied based on config
+pub async fn run_with_routers(
+    config: &GatewayConfig,
+    routers: HashMap<String, Router>,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
+    api_key::check_service_secret()?;
+    println!("gateway listening on {}", config.listen_addr);
+
+    let gateway = Arc::new(Gateway::new(config, routers)?);
+    reload::spawn(gateway.clone());
+
+    let app = health::routes(gateway.clone(), shutdown.clone())
+        .fallback(move |req: Request<Body>| gateway.clone().dispatch(req));
 
     let listener = tokio::net::TcpListener::bind(&config.listen_addr).await?;
-    axum::serve(listener, app).await?;
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.wait());
+    shutdown.drain("gateway", server).await?;
     Ok(())
 }
 
 async fn gateway_checks(
-    mut req: Request<Body>,
+    req: Request<Body>,
     next: Next,
 ) -> Result<Response<Body>, Response<Body>> {
     let start = Instant::now();
     let path = req.uri().path().to_string();
-    let Some(state) = req.extensions().get::<Arc<GatewayState>>() else {
+    let Some(state) = req.extensions().get::<Arc<GatewayState>>().cloned() else {
         return Err((StatusCode::INTERNAL_SERVER_ERROR, "gateway state missing").into_response());
     };
-    let client_ip = req
-        .headers()
-        .get("x-forwarded-for")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("unknown");
+    // Upstreams resolve `..`, so access rules would judge a different path
+    // from the one served
+    if authz::has_dot_segments(&path) {
+        return Err(middleware_response(authz::problem(
+            400,
+            "Bad Request",
+            "path must not contain '.' or '..' segments",
+            &path,
+        )));
+    }
+    if is_internal_path(&state.config, &path) {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    }
+    let client_ip = client_ip(&req, &state.trusted_proxies);
+    let limiter = state.limiter_for(&path).cloned();
 
-    if !state.limiter.allow(client_ip) {
-        return Err((StatusCode::TOO_MANY_REQUESTS, "rate limited").into_response());
+    // Limits keyed by IP or API key apply before authentication; identity-based
+    // limits have to wait until the auth middleware has resolved the caller
+    let mut decision = None;
+    if let Some(limiter) = limiter.as_ref().filter(|l| !l.config().key.requires_identity()) {
+        let key = rate_limit_key(limiter.config().key, req.headers(), &client_ip);
+        let checked = limiter.check(&key);
+        if !checked.allowed {
+            return Err(rate_limited(&checked));
+        }
+        decision = Some(checked);
     }
 
-    let mut gateway_req = GatewayRequest::new(req.uri().path());
-    gateway_req.headers = req
-        .headers()
+    let pipeline = state.pipeline_for(&path);
+    let (mut parts, body) = req.into_parts();
+
+    // Identity is only ever asserted by the gateway itself; the configured
+    // list can add headers to strip but never lets an identity header through
+    for name in identity::IDENTITY_HEADERS {
+        parts.headers.remove(*name);
+    }
+    for name in &state.config.identity_headers {
+        parts.headers.remove(name.as_str());
+    }
+    parts.headers.remove(identity::TIMESTAMP_HEADER);
+    parts.headers.remove(identity::SIGNATURE_HEADER);
+    for name in service_auth::SERVICE_HEADERS {
+        parts.headers.remove(*name);
+    }
+
+    let mut gateway_req = GatewayRequest::new(parts.uri.path());
+    gateway_req.method = parts.method.to_string();
+    gateway_req.headers = parts
+        .headers
         .iter()
         .filter_map(|(name, value)| {
             value
@@ -128,42 +419,407 @@ async fn gateway_checks(
         })
         .collect();
 
-    let updated = match middleware::apply(state.pipeline.as_ref(), gateway_req) {
+    // Only buffer the body when a step (e.g. a WASM plugin) has to see it
+    let mut body = Some(body);
+    if let Some(limit) = middleware::body_limit(pipeline)
+        && let Some(streaming) = body.take()
+    {
+        match axum::body::to_bytes(streaming, limit).await {
+            Ok(bytes) => gateway_req.body = Some(bytes.to_vec()),
+            Err(_) => {
+                return Err(
+                    (StatusCode::PAYLOAD_TOO_LARGE, "request body too large").into_response()
+                );
+            }
+        }
+    }
+
+    let original_headers: Vec<String> =
+        gateway_req.headers.iter().map(|(name, _)| name.clone()).collect();
+    let updated = match middleware::apply(pipeline, gateway_req).await {
         Ok(updated) => updated,
         Err(res) => {
-            let status = StatusCode::from_u16(res.status).unwrap_or(StatusCode::UNAUTHORIZED);
-            return Err((status, res.body).into_response());
+            // Rejected callers never get an identity; count them by IP so
+            // guessing keys or tokens is limited too
+            if let Some(limiter) = limiter.as_ref().filter(|l| l.config().key.requires_identity()) {
+                let checked = limiter.check(&format!("ip:{client_ip}"));
+                if !checked.allowed {
+                    return Err(rate_limited(&checked));
+                }
+            }
+            return Err(middleware_response(res));
         }
     };
 
+    // Headers dropped by a step are removed; everything else is set
+    for name in original_headers {
+        if !updated.headers.iter().any(|(n, _)| n.eq_ignore_ascii_case(&name)) {
+            parts.headers.remove(&name);
+        }
+    }
     for (name, value) in updated.headers {
         if let (Ok(name), Ok(value)) = (
             HeaderName::from_bytes(name.as_bytes()),
             HeaderValue::from_str(&value),
         ) {
-            req.headers_mut().insert(name, value);
+            parts.headers.insert(name, value);
         }
     }
 
-    let response = next.run(req).await;
+    let rewritten = updated.method != parts.method.as_str() || updated.path != parts.uri.path();
+    if updated.method != parts.method.as_str() {
+        match Method::from_bytes(updated.method.as_bytes()) {
+            Ok(method) => parts.method = method,
+            Err(_) => return Err(bad_gateway("pipeline produced an invalid method")),
+        }
+    }
+    if updated.path != parts.uri.path() {
+        if authz::has_dot_segments(&updated.path) {
+            return Err(bad_gateway("pipeline produced an invalid path"));
+        }
+        if is_internal_path(&state.config, &updated.path) {
+            return Err(StatusCode::NOT_FOUND.into_response());
+        }
+        match rewrite_path(&parts.uri, &updated.path) {
+            Some(uri) => parts.uri = uri,
+            None => return Err(bad_gateway("pipeline produced an invalid path")),
+        }
+    }
+    if rewritten {
+        recheck_access(&state, &path, &parts).map_err(middleware_response)?;
+    }
+
+    // Keyed on the identity the pipeline resolved, before it may be dropped
+    if let Some(limiter) = limiter.as_ref().filter(|l| l.config().key.requires_identity()) {
+        let key = rate_limit_key(limiter.config().key, &parts.headers, &client_ip);
+        let checked = limiter.check(&key);
+        if !checked.allowed {
+            return Err(rate_limited(&checked));
+        }
+        decision = Some(checked);
+    }
+
+    sign_identity(&state, &mut parts);
+
+    let body = match (body, updated.body) {
+        (Some(streaming), _) => streaming,
+        (None, Some(bytes)) => {
+            parts.headers.remove(TRANSFER_ENCODING);
+            parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+            Body::from(bytes)
+        }
+        (None, None) => {
+            parts.headers.remove(TRANSFER_ENCODING);
+            parts.headers.remove(CONTENT_LENGTH);
+            Body::empty()
+        }
+    };
+    let req = Request::from_parts(parts, body);
+
+    let mut response = next.run(req).await;
+    if let Some(decision) = decision {
+        insert_rate_limit_headers(response.headers_mut(), &decision);
+    }
     let status = response.status();
     let elapsed_ms = start.elapsed().as_millis();
     println!("[gateway] {} {} {}ms", status.as_u16(), path, elapsed_ms);
     Ok(response)
 }
 
+/// A pipeline step rewrote the method or path after the Auth step judged the
+/// original request. The rewritten request has to stay on its route and pass
+/// the route's access rules as the caller the pipeline resolved.
+fn recheck_access(
+    state: &GatewayState,
+    original_path: &str,
+    parts: &Parts,
+) -> Result<(), GatewayResponse> {
+    let path = parts.uri.path();
+    let route = state.config.route_for(path);
+    if route != state.config.route_for(original_path) {
+        return Err(authz::problem(
+            502,
+            "Bad Gateway",
+            "pipeline moved the request to another route",
+            original_path,
+        ));
+    }
+    let Some(route_config) = route.and_then(|route| state.config.routes.get(route)) else {
+        return Ok(());
+    };
+
+    let header = |name: &str| parts.headers.get(name).and_then(|v| v.to_str().ok());
+    let scopes: Option<Vec<String>> = (header("x-auth") == Some("api-key")).then(|| {
+        header("x-api-key-scopes")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect()
+    });
+    let principal = header("x-user-role").and_then(Role::parse).map(|role| Principal {
+        role,
+        scopes: scopes.as_deref(),
+    });
+    authz::check(&route_config.access, parts.method.as_str(), path, principal.as_ref())
+}
+
+/// `<route>/internal/...` is the service-to-service API of whatever runs behind
+/// the route. It is never reachable through the gateway, embedded or proxied.
+/// Paths with dot segments count as internal, as `/admin/x/../internal`
+/// resolves to it upstream.
+fn is_internal_path(config: &GatewayConfig, path: &str) -> bool {
+    if authz::has_dot_segments(path) {
+        return true;
+    }
+    let rest = config
+        .route_for(path)
+        .and_then(|route| path.strip_prefix(route))
+        .unwrap_or(path);
+    let Some(segment) = rest.split('/').find(|s| !s.is_empty()) else {
+        return false;
+    };
+    // `/%69nternal` must not slip past
+    urlencoding::decode(segment)
+        .map(|s| s.eq_ignore_ascii_case("internal"))
+        .unwrap_or(false)
+}
+
+/// Sign the identity headers set by the pipeline for the service behind the
+/// request's route, bound to the method and path it will receive. Routes
+/// without an `identity_audience` get no identity headers at all, so an
+/// arbitrary upstream never holds a signature it could replay elsewhere.
+fn sign_identity(state: &GatewayState, parts: &mut Parts) {
+    // A pipeline step must not hand over a signature of its own
+    parts.headers.remove(identity::TIMESTAMP_HEADER);
+    parts.headers.remove(identity::SIGNATURE_HEADER);
+
+    let target = state.config.route_for(parts.uri.path()).and_then(|route| {
+        let audience = state.config.routes.get(route)?.identity_audience.as_deref()?;
+        let route_state = state.routes.get(route)?;
+        // Embedded routers are nested, which strips the prefix as well
+        let prefix = if route_state.proxy.is_none() || route_state.strip_prefix {
+            route
+        } else {
+            ""
+        };
+        Some((audience, prefix))
+    });
+    let Some((audience, prefix)) = target else {
+        for name in identity::IDENTITY_HEADERS {
+            parts.headers.remove(*name);
+        }
+        return;
+    };
+    if !identity::IDENTITY_HEADERS.iter().any(|name| parts.headers.contains_key(*name)) {
+        return;
+    }
+
+    let path_and_query = parts.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let path_and_query = strip_route_prefix(path_and_query, prefix);
+    let headers = &parts.headers;
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let timestamp = identity::unix_now();
+    let signature = state.signer.sign(
+        timestamp,
+        audience,
+        parts.method.as_str(),
+        &path_and_query,
+        header,
+    );
+    parts.headers.insert(identity::TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+    if let Ok(value) = HeaderValue::from_str(&signature) {
+        parts.headers.insert(identity::SIGNATURE_HEADER, value);
+    }
+}
+
+/// Turn a pipeline rejection into an HTTP response
+pub(crate) fn middleware_response(res: GatewayResponse) -> Response<Body> {
+    let status = StatusCode::from_u16(res.status).unwrap_or(StatusCode::UNAUTHORIZED);
+    let mut response = (status, res.body).into_response();
+    for (name, value) in res.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            // A step's content type replaces the text/plain default
+            if name == CONTENT_TYPE {
+                response.headers_mut().insert(name, value);
+            } else {
+                response.headers_mut().append(name, value);
+            }
+        }
+    }
+    response
+}
+
+/// Replace the path of `uri`, keeping its query string
+fn rewrite_path(uri: &Uri, path: &str) -> Option<Uri> {
+    if !path.starts_with('/') {
+        return None;
+    }
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// Client address: the TCP peer, unless it is a trusted proxy. Then
+/// `x-forwarded-for` is walked from the right, skipping further trusted
+/// proxies, so hops a client prepended itself are never used.
+fn client_ip(req: &Request<Body>, trusted: &[IpNet]) -> String {
+    let Some(peer) = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip())
+    else {
+        return "unknown".to_string();
+    };
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return peer.to_string();
+    }
+
+    // The last trusted hop stands in when the chain runs out or is garbled
+    let mut client = peer;
+    let hops: Vec<&str> = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+    for hop in hops.iter().rev() {
+        match hop.parse::<IpAddr>() {
+            Ok(ip) if is_trusted(&ip) => client = ip,
+            Ok(ip) => return ip.to_string(),
+            Err(_) => break,
+        }
+    }
+    client.to_string()
+}
+
+/// Build the limiter key for a request, falling back to the client IP
+/// when the configured identity is not present
+fn rate_limit_key(key: RateLimitKey, headers: &HeaderMap, client_ip: &str) -> String {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+    };
+    let identity = match key {
+        RateLimitKey::ClientIp => None,
+        // Set by the auth middleware once the key checks out, so made-up keys
+        // are counted against the client IP
+        RateLimitKey::ApiKey => header("x-api-key-id").map(|v| format!("key:{v}")),
+        RateLimitKey::UserId => header("x-user-id").map(|v| format!("user:{v}")),
+        RateLimitKey::OrganisationId => header("x-organisation-id").map(|v| format!("org:{v}")),
+    };
+    identity.unwrap_or_else(|| format!("ip:{client_ip}"))
+}
+
+fn rate_limited(decision: &RateLimitDecision) -> Response<Body> {
+    let mut response = (StatusCode::TOO_MANY_REQUESTS, "rate limited").into_response();
+    let headers = response.headers_mut();
+    insert_rate_limit_headers(headers, decision);
+    let retry_after = decision.retry_after.unwrap_or(decision.reset_after);
+    headers.insert("retry-after", HeaderValue::from(ceil_secs(retry_after)));
+    response
+}
+
+fn insert_rate_limit_headers(headers: &mut HeaderMap, decision: &RateLimitDecision) {
+    headers.insert("x-ratelimit-limit", HeaderValue::from(decision.limit));
+    headers.insert("x-ratelimit-remaining", HeaderValue::from(decision.remaining));
+    headers.insert(
+        "x-ratelimit-reset",
+        HeaderValue::from(ceil_secs(decision.reset_after)),
+    );
+}
+
+/// Whole seconds, rounded up so clients never retry too early
+fn ceil_secs(duration: Duration) -> u64 {
+    let secs = duration.as_secs();
+    if duration.subsec_nanos() > 0 { secs + 1 } else { secs }
+}
+
 async fn proxy_route(
     Extension(state): Extension<Arc<GatewayState>>,
     req: Request<Body>,
     route: String,
 ) -> Response<Body> {
-    let Some(proxy) = state.proxies.get(&route) else {
+    let Some((proxy, route_state)) = state
+        .routes
+        .get(&route)
+        .and_then(|r| r.proxy.as_ref().map(|p| (p, r)))
+    else {
         return (StatusCode::BAD_GATEWAY, format!("proxy not configured for {}", route))
             .into_response();
     };
 
-    match proxy.forward(req, &route).await {
+    let path = req.uri().path().to_string();
+    let strip_prefix = if route_state.strip_prefix { route.as_str() } else { "" };
+    match proxy.forward(req, strip_prefix, &state.retry_budget).await {
         Ok(response) => response,
-        Err(err) => bad_gateway(format!("upstream error: {err}")),
+        Err(ProxyError::Unavailable(unavailable)) => unavailable_response(&route, &path, unavailable),
+        Err(ProxyError::Timeout(kind, after)) => gateway_timeout(
+            &format!("upstream for {route} did not answer within {after:?} ({kind} timeout)"),
+            &path,
+            &kind.to_string(),
+            after,
+        ),
+        Err(ProxyError::Upstream(err)) => bad_gateway(format!("upstream error: {err}")),
     }
 }
+
+/// 503 for a route none of whose upstreams can take the request right now
+fn unavailable_response(route: &str, path: &str, unavailable: Unavailable) -> Response<Body> {
+    match unavailable {
+        Unavailable::Unhealthy => middleware_response(authz::problem(
+            503,
+            "Service Unavailable",
+            &format!("no healthy upstream for {route}"),
+            path,
+        )),
+        Unavailable::CircuitOpen(wait) => {
+            let mut response = middleware_response(authz::problem(
+                503,
+                "Service Unavailable",
+                &format!("circuit open for {route}"),
+                path,
+            ));
+            response
+                .headers_mut()
+                .insert("retry-after", HeaderValue::from(ceil_secs(wait)));
+            response
+        }
+    }
+}
+
+/// 504 problem response; `timeout` names the limit that ran out
+fn gateway_timeout(detail: &str, path: &str, timeout: &str, after: Duration) -> Response<Body> {
+    let body = serde_json::json!({
+        "type": "about:blank",
+        "title": "Gateway Timeout",
+        "status": 504,
+        "detail": detail,
+        "instance": path,
+        "timeout": timeout,
+        "timeout_ms": after.as_millis() as u64,
+    });
+    let mut response = (StatusCode::GATEWAY_TIMEOUT, body.to_string()).into_response();
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/problem+json"),
+    );
+    response
+}
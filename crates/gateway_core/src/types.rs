@@ -1,14 +1,19 @@
 #[derive(Debug, Clone)]
 pub struct Request {
+    pub method: String,
     pub path: String,
     pub headers: Vec<(String, String)>,
+    /// Buffered request body; only present when a pipeline step needs it
+    pub body: Option<Vec<u8>>,
 }
 
 impl Request {
     pub fn new(path: impl Into<String>) -> Self {
         Self {
+            method: "GET".to_string(),
             path: path.into(),
             headers: Vec::new(),
+            body: None,
         }
     }
 }
@@ -16,6 +21,7 @@ impl Request {
 #[derive(Debug, Clone)]
 pub struct Response {
     pub status: u16,
+    pub headers: Vec<(String, String)>,
     pub body: String,
 }
 
@@ -23,6 +29,7 @@ impl Response {
     pub fn ok(body: impl Into<String>) -> Self {
         Self {
             status: 200,
+            headers: Vec::new(),
             body: body.into(),
         }
     }
@@ -30,6 +37,7 @@ impl Response {
     pub fn unauthorized(body: impl Into<String>) -> Self {
         Self {
             status: 401,
+            headers: Vec::new(),
             body: body.into(),
         }
     }
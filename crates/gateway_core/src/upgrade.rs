@@ -0,0 +1,205 @@
+//! Protocol upgrades on proxied routes.
+//!
+//! A request asking to switch protocols (`Connection: upgrade` with an
+//! `Upgrade` header, e.g. a WebSocket handshake) goes through the route's
+//! middleware like any other, so it is authenticated and rate limited, and is
+//! then sent to an upstream. If the upstream answers `101 Switching Protocols`
+//! the gateway passes that on and splices the two connections together,
+//! copying bytes both ways until both sides have closed or neither has sent
+//! anything for the route's `upgrade_idle` timeout.
+
+use std::io;
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use axum::http::header::{CONNECTION, UPGRADE};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// How long an upgraded connection may go without data when the route doesn't say
+pub const DEFAULT_UPGRADE_IDLE: Duration = Duration::from_secs(300);
+
+const SPLICE_BUFFER: usize = 16 * 1024;
+
+/// Whether the request asks to switch to another protocol
+pub fn is_upgrade(headers: &HeaderMap) -> bool {
+    headers.contains_key(UPGRADE)
+        && headers
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// Bytes copied over an upgraded connection
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Spliced {
+    pub client_to_upstream: u64,
+    pub upstream_to_client: u64,
+}
+
+/// Copy data between `client` and `upstream` until both have finished sending.
+/// A side that finishes has the other side's write half shut down. Fails with
+/// `TimedOut` when nothing is read or written for `idle`.
+pub async fn splice<C, U>(client: C, upstream: U, idle: Duration) -> io::Result<Spliced>
+where
+    C: AsyncRead + AsyncWrite,
+    U: AsyncRead + AsyncWrite,
+{
+    let (mut client_read, mut client_write) = tokio::io::split(client);
+    let (mut upstream_read, mut upstream_write) = tokio::io::split(upstream);
+    let mut from_client = vec![0u8; SPLICE_BUFFER];
+    let mut from_upstream = vec![0u8; SPLICE_BUFFER];
+    let (mut client_open, mut upstream_open) = (true, true);
+    let mut spliced = Spliced::default();
+
+    while client_open || upstream_open {
+        tokio::select! {
+            read = client_read.read(&mut from_client), if client_open => match read? {
+                0 => {
+                    client_open = false;
+                    let _ = upstream_write.shutdown().await;
+                }
+                n => {
+                    relay(&mut upstream_write, &from_client[..n], idle).await?;
+                    spliced.client_to_upstream += n as u64;
+                }
+            },
+            read = upstream_read.read(&mut from_upstream), if upstream_open => match read? {
+                0 => {
+                    upstream_open = false;
+                    let _ = client_write.shutdown().await;
+                }
+                n => {
+                    relay(&mut client_write, &from_upstream[..n], idle).await?;
+                    spliced.upstream_to_client += n as u64;
+                }
+            },
+            _ = tokio::time::sleep(idle) => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, format!("idle for {idle:?}")));
+            }
+        }
+    }
+    Ok(spliced)
+}
+
+/// Write a chunk to the other side; a peer that stops reading counts as idle
+async fn relay<W: AsyncWrite + Unpin>(to: &mut W, chunk: &[u8], idle: Duration) -> io::Result<()> {
+    let write = async {
+        to.write_all(chunk).await?;
+        to.flush().await
+    };
+    match tokio::time::timeout(idle, write).await {
+        Ok(written) => written,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, format!("peer not reading for {idle:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn upgrade_needs_the_connection_token_and_the_header() {
+        assert!(is_upgrade(&headers(&[
+            ("connection", "keep-alive, Upgrade"),
+            ("upgrade", "websocket"),
+        ])));
+        assert!(is_upgrade(&headers(&[
+            ("connection", "keep-alive"),
+            ("connection", " upgrade "),
+            ("upgrade", "websocket"),
+        ])));
+        assert!(!is_upgrade(&headers(&[("connection", "keep-alive, Upgrade")])));
+        assert!(!is_upgrade(&headers(&[("connection", "keep-alive"), ("upgrade", "websocket")])));
+        assert!(!is_upgrade(&headers(&[("connection", "upgraded"), ("upgrade", "websocket")])));
+    }
+
+    /// The client's and the upstream's ends of two spliced connections
+    fn spliced(
+        idle: Duration,
+    ) -> (DuplexStream, DuplexStream, tokio::task::JoinHandle<io::Result<Spliced>>) {
+        let (client, client_side) = tokio::io::duplex(64);
+        let (upstream_side, upstream) = tokio::io::duplex(64);
+        let splice = tokio::spawn(splice(client_side, upstream_side, idle));
+        (client, upstream, splice)
+    }
+
+    async fn read_exact(from: &mut DuplexStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        from.read_exact(&mut buf).await.unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn bytes_are_copied_both_ways() {
+        let (mut client, mut upstream, splice) = spliced(Duration::from_secs(5));
+
+        client.write_all(b"ping").await.unwrap();
+        assert_eq!(read_exact(&mut upstream, 4).await, b"ping");
+        // More than the pipe holds at once
+        let large = vec![7u8; 1000];
+        let (_, echoed) = tokio::join!(
+            async { upstream.write_all(&large).await.unwrap() },
+            read_exact(&mut client, large.len())
+        );
+        assert_eq!(echoed, large);
+
+        drop((client, upstream));
+        let spliced = splice.await.unwrap().unwrap();
+        assert_eq!((spliced.client_to_upstream, spliced.upstream_to_client), (4, 1000));
+    }
+
+    #[tokio::test]
+    async fn half_close_reaches_the_other_side() {
+        let (mut client, mut upstream, splice) = spliced(Duration::from_secs(5));
+
+        client.write_all(b"last").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut received = Vec::new();
+        upstream.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"last");
+
+        // The upstream can still answer after the client stopped sending
+        upstream.write_all(b"reply").await.unwrap();
+        upstream.shutdown().await.unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"reply");
+
+        let spliced = splice.await.unwrap().unwrap();
+        assert_eq!((spliced.client_to_upstream, spliced.upstream_to_client), (4, 5));
+    }
+
+    #[tokio::test]
+    async fn idle_connections_time_out() {
+        let (mut client, mut upstream, splice) = spliced(Duration::from_millis(50));
+        client.write_all(b"hi").await.unwrap();
+        assert_eq!(read_exact(&mut upstream, 2).await, b"hi");
+
+        let err = splice.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn peer_that_stops_reading_times_out() {
+        let (mut client, _upstream, splice) = spliced(Duration::from_millis(50));
+        // The upstream never reads, so the pipes fill up and the client's
+        // write only ends when the splice gives up
+        let (_, spliced) = tokio::join!(client.write_all(&[0; 1024]), splice);
+
+        let err = spliced.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,341 @@
+//! Upstream health tracking.
+//!
+//! A proxied route with a `health_check` keeps track of whether its upstream
+//! is usable, in two ways:
+//!
+//! - **Active**: the upstream's `health_path` is probed every `interval`;
+//!   `unhealthy_threshold` failed probes in a row eject it and
+//!   `healthy_threshold` passing probes in a row reinstate it
+//! - **Passive**: `max_failures` consecutive 5xx responses or connection
+//!   errors on live traffic eject it. Without active probes it is put back
+//!   into rotation once `ejection` has passed.
+//!
+//! Requests for an ejected upstream are answered with a 503 straight away.
+//! An upstream can also have a circuit breaker (see [`crate::circuit`]).
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::circuit::{CircuitBreaker, CircuitBreakerConfig, CircuitStatus, Permit};
+
+/// Health checking settings for a proxied route
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthCheckConfig {
+    /// Time between active probes; `None` disables them
+    pub interval: Option<Duration>,
+    /// Time a probe may take before it counts as failed
+    pub timeout: Duration,
+    /// Passing probes in a row that reinstate an ejected upstream
+    pub healthy_threshold: u32,
+    /// Failed probes in a row that eject an upstream
+    pub unhealthy_threshold: u32,
+    /// Consecutive failed requests that eject an upstream; `0` disables
+    /// passive checks
+    pub max_failures: u32,
+    /// How long a passively ejected upstream stays out when there are no
+    /// active probes to reinstate it
+    pub ejection: Duration,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            interval: Some(Duration::from_secs(10)),
+            timeout: Duration::from_secs(2),
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+            max_failures: 5,
+            ejection: Duration::from_secs(30),
+        }
+    }
+}
+
+/// One upstream target and what is known about its health
+pub struct Upstream {
+    pub url: String,
+    health_path: String,
+    checks: Option<HealthCheckConfig>,
+    state: Mutex<HealthState>,
+    circuit: Option<CircuitBreaker>,
+    /// Requests sent and not yet finished, for load balancing
+    outstanding: AtomicUsize,
+}
+
+struct HealthState {
+    healthy: bool,
+    /// Consecutive failed requests
+    failures: u32,
+    /// Consecutive passing / failing probes
+    probe_passes: u32,
+    probe_failures: u32,
+    ejected_at: Option<Instant>,
+    last_error: Option<String>,
+    last_probe: Option<DateTime<Utc>>,
+    changed_at: DateTime<Utc>,
+}
+
+/// Health of an upstream, as shown by the gateway's upstream status endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamStatus {
+    pub url: String,
+    pub weight: u32,
+    pub healthy: bool,
+    pub outstanding_requests: usize,
+    pub active_checks: bool,
+    pub passive_checks: bool,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub last_probe_at: Option<DateTime<Utc>>,
+    pub changed_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit: Option<CircuitStatus>,
+}
+
+impl Upstream {
+    pub fn new(
+        url: impl Into<String>,
+        health_path: impl Into<String>,
+        checks: Option<HealthCheckConfig>,
+        circuit: Option<CircuitBreakerConfig>,
+    ) -> Self {
+        let url = url.into().trim_end_matches('/').to_string();
+        Self {
+            circuit: circuit.map(|config| CircuitBreaker::new(url.clone(), config)),
+            url,
+            health_path: health_path.into(),
+            checks,
+            state: Mutex::new(HealthState {
+                healthy: true,
+                failures: 0,
+                probe_passes: 0,
+                probe_failures: 0,
+                ejected_at: None,
+                last_error: None,
+                last_probe: None,
+                changed_at: Utc::now(),
+            }),
+            outstanding: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether an existing upstream can be kept across a reload
+    pub fn same_settings(
+        &self,
+        url: &str,
+        health_path: &str,
+        checks: Option<&HealthCheckConfig>,
+        circuit: Option<&CircuitBreakerConfig>,
+    ) -> bool {
+        self.url == url.trim_end_matches('/')
+            && self.health_path == health_path
+            && self.checks.as_ref() == checks
+            && self.circuit.as_ref().map(CircuitBreaker::config) == circuit
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, HealthState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether requests may be sent to this upstream
+    pub fn is_available(&self) -> bool {
+        let mut state = self.state();
+        if state.healthy {
+            return true;
+        }
+        // Without probes, an ejection simply runs out
+        if let Some(checks) = &self.checks
+            && checks.interval.is_none()
+            && state.ejected_at.is_some_and(|at| at.elapsed() >= checks.ejection)
+        {
+            self.reinstate(&mut state, "ejection time over");
+            return true;
+        }
+        false
+    }
+
+    pub fn circuit(&self) -> Option<&CircuitBreaker> {
+        self.circuit.as_ref()
+    }
+
+    /// Passive check: record how a proxied request went
+    fn record(&self, result: Result<u16, &str>) {
+        let Some(checks) = &self.checks else {
+            return;
+        };
+        if checks.max_failures == 0 {
+            return;
+        }
+        let mut state = self.state();
+        match result {
+            Ok(status) if status < 500 => state.failures = 0,
+            failed => {
+                state.failures += 1;
+                state.last_error = Some(match failed {
+                    Ok(status) => format!("HTTP {status}"),
+                    Err(err) => err.to_string(),
+                });
+                if state.healthy && state.failures >= checks.max_failures {
+                    let reason = format!("{} failed requests in a row", state.failures);
+                    self.eject(&mut state, &reason);
+                }
+            }
+        }
+    }
+
+    /// Active check: probe `health_path` once
+    async fn probe(&self, client: &reqwest::Client, checks: &HealthCheckConfig) {
+        let url = format!("{}{}", self.url, self.health_path);
+        let result = match client.get(&url).timeout(checks.timeout).send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(format!("probe got HTTP {}", response.status().as_u16())),
+            Err(err) => Err(format!("probe failed: {err}")),
+        };
+
+        let mut state = self.state();
+        state.last_probe = Some(Utc::now());
+        match result {
+            Ok(()) => {
+                state.probe_failures = 0;
+                state.probe_passes += 1;
+                if !state.healthy && state.probe_passes >= checks.healthy_threshold {
+                    let reason = format!("{} probes passed", state.probe_passes);
+                    self.reinstate(&mut state, &reason);
+                }
+            }
+            Err(err) => {
+                state.probe_passes = 0;
+                state.probe_failures += 1;
+                state.last_error = Some(err);
+                if state.healthy && state.probe_failures >= checks.unhealthy_threshold {
+                    let reason = format!("{} probes failed", state.probe_failures);
+                    self.eject(&mut state, &reason);
+                }
+            }
+        }
+    }
+
+    fn eject(&self, state: &mut HealthState, reason: &str) {
+        eprintln!(
+            "[gateway] upstream {} ejected: {reason} (last error: {})",
+            self.url,
+            state.last_error.as_deref().unwrap_or("none")
+        );
+        state.healthy = false;
+        state.probe_passes = 0;
+        state.ejected_at = Some(Instant::now());
+        state.changed_at = Utc::now();
+    }
+
+    fn reinstate(&self, state: &mut HealthState, reason: &str) {
+        println!("[gateway] upstream {} back in rotation: {reason}", self.url);
+        state.healthy = true;
+        state.failures = 0;
+        state.probe_failures = 0;
+        state.ejected_at = None;
+        state.changed_at = Utc::now();
+    }
+
+    pub fn status(&self) -> UpstreamStatus {
+        let state = self.state();
+        UpstreamStatus {
+            url: self.url.clone(),
+            weight: 1,
+            healthy: state.healthy,
+            outstanding_requests: self.outstanding(),
+            active_checks: self.checks.as_ref().is_some_and(|c| c.interval.is_some()),
+            passive_checks: self.checks.as_ref().is_some_and(|c| c.max_failures > 0),
+            consecutive_failures: state.failures,
+            last_error: state.last_error.clone(),
+            last_probe_at: state.last_probe,
+            changed_at: state.changed_at,
+            circuit: self.circuit.as_ref().map(CircuitBreaker::status),
+        }
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+
+    /// Start a request, counting it as outstanding until the returned guard
+    /// is dropped. Fails with the time to wait while the circuit is open.
+    pub fn start_request(self: &Arc<Self>) -> Result<InFlight, Duration> {
+        let permit = match &self.circuit {
+            Some(circuit) => Some(circuit.acquire()?),
+            None => None,
+        };
+        self.outstanding.fetch_add(1, Ordering::Relaxed);
+        Ok(InFlight {
+            upstream: self.clone(),
+            started: Instant::now(),
+            permit,
+            pending: true,
+        })
+    }
+
+    /// Start probing in the background when active checks are configured.
+    /// The task ends once the upstream is dropped, e.g. after a reload.
+    pub fn spawn_probes(self: &Arc<Self>, client: reqwest::Client) {
+        let Some(checks) = self.checks.clone() else {
+            return;
+        };
+        let Some(interval) = checks.interval else {
+            return;
+        };
+        let upstream: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let Some(upstream) = upstream.upgrade() else {
+                    break;
+                };
+                upstream.probe(&client, &checks).await;
+            }
+        });
+    }
+}
+
+/// An outstanding request to an upstream; done when dropped
+pub struct InFlight {
+    upstream: Arc<Upstream>,
+    started: Instant,
+    /// Circuit breaker permit, when the upstream has one
+    permit: Option<Permit>,
+    /// No outcome recorded yet
+    pending: bool,
+}
+
+impl InFlight {
+    pub fn upstream(&self) -> &Arc<Upstream> {
+        &self.upstream
+    }
+
+    /// Record how the request went, for passive health checks and the
+    /// circuit breaker: a status code, or why there was no response
+    pub fn finish(&mut self, result: Result<u16, &str>) {
+        if !std::mem::take(&mut self.pending) {
+            return;
+        }
+        self.upstream.record(result);
+        if let (Some(circuit), Some(permit)) = (&self.upstream.circuit, self.permit) {
+            let failed = !matches!(result, Ok(status) if status < 500);
+            circuit.record(permit, failed, self.started.elapsed());
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.upstream.outstanding.fetch_sub(1, Ordering::Relaxed);
+        if self.pending
+            && let (Some(circuit), Some(permit)) = (&self.upstream.circuit, self.permit)
+        {
+            circuit.release(permit);
+        }
+    }
+}
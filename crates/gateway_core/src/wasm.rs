@@ -1,3 +1,372 @@
+//! WASM plugin host.
+//!
+//! Plugins are WebAssembly modules run as middleware steps. A guest needs no
+//! imports and must export:
+//!
+//! - `memory`
+//! - `alloc(len: i32) -> i32`: reserve `len` bytes for the host to write into
+//! - `handle(ptr: i32, len: i32) -> i64`: process the request and return the
+//!   output location packed as `(ptr << 32) | len`; a zero length leaves the
+//!   request unchanged
+//!
+//! Input is JSON describing the request (`body` is base64, or `null` when the
+//! body is not available):
+//!
+//! ```json
+//! {"method": "POST", "path": "/orders", "headers": [["content-type", "application/json"]], "body": "e30="}
+//! ```
+//!
+//! Output is either a (modified) request in the same shape, or a response that
+//! short-circuits the pipeline (`body` is plain text):
+//!
+//! ```json
+//! {"request": {"method": "POST", "path": "/v2/orders", "headers": [], "body": "e30="}}
+//! {"response": {"status": 403, "headers": [["content-type", "text/plain"]], "body": "blocked"}}
+//! ```
+//!
+//! Every call runs in a fresh instance with its own fuel and memory budget.
+//! Modules are recompiled when their file changes on disk; if compilation
+//! fails the previous version keeps running.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+use wasmtime::{
+    Config, Engine, InstancePre, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+use crate::middleware::Middleware;
+use crate::types::{Request, Response};
+
+const DEFAULT_FUEL: u64 = 10_000_000;
+const DEFAULT_MAX_MEMORY_BYTES: usize = 16 * 1024 * 1024;
+const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+/// Guest output beyond the (base64) body: method, path, headers, JSON
+const MAX_OUTPUT_OVERHEAD: usize = 256 * 1024;
+
+/// Settings for a named plugin
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginConfig {
+    /// `.wasm` (or `.wat`) file to load
+    pub path: PathBuf,
+    /// Fuel available to each call; roughly one unit per instruction
+    pub fuel: u64,
+    /// Upper bound on the guest's linear memory
+    pub max_memory_bytes: usize,
+    /// Request bodies larger than this are rejected with `413`
+    pub max_body_bytes: usize,
+}
+
+impl PluginConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            fuel: DEFAULT_FUEL,
+            max_memory_bytes: DEFAULT_MAX_MEMORY_BYTES,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+        }
+    }
+}
+
+struct Host {
+    engine: Engine,
+    /// Compiled modules by file, shared by every route that uses them
+    modules: Mutex<HashMap<PathBuf, Weak<PluginModule>>>,
+}
+
+static HOST: OnceLock<Host> = OnceLock::new();
+
+fn host() -> &'static Host {
+    HOST.get_or_init(|| {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        Host {
+            engine: Engine::new(&config).expect("wasm engine config is valid"),
+            modules: Mutex::new(HashMap::new()),
+        }
+    })
+}
+
+/// Start the plugin host and watch loaded modules for changes
 pub fn init() {
+    host();
+    spawn_watch();
     println!("gateway wasm host initialized");
 }
+
+struct StoreState {
+    limits: StoreLimits,
+}
+
+struct Loaded {
+    pre: InstancePre<StoreState>,
+    fingerprint: Option<(SystemTime, u64)>,
+}
+
+/// A compiled module that can be swapped when its file changes
+struct PluginModule {
+    path: PathBuf,
+    current: ArcSwap<Loaded>,
+}
+
+impl PluginModule {
+    fn compile(path: &Path) -> anyhow::Result<Loaded> {
+        let fingerprint = fingerprint(path);
+        let engine = &host().engine;
+        let module = Module::from_file(engine, path)
+            .with_context(|| format!("compile wasm plugin {}", path.display()))?;
+        let pre = Linker::new(engine)
+            .instantiate_pre(&module)
+            .with_context(|| {
+                format!(
+                    "link wasm plugin {} (plugins may not import anything)",
+                    path.display()
+                )
+            })?;
+        Ok(Loaded { pre, fingerprint })
+    }
+
+    /// Recompile if the file changed; keeps the running version on failure
+    fn refresh(&self) {
+        let current = fingerprint(&self.path);
+        if current.is_none() || current == self.current.load().fingerprint {
+            return;
+        }
+        match Self::compile(&self.path) {
+            Ok(loaded) => {
+                self.current.store(Arc::new(loaded));
+                println!("[gateway] wasm plugin {} reloaded", self.path.display());
+            }
+            Err(err) => {
+                eprintln!("[gateway] wasm plugin reload failed, keeping previous version: {err:#}");
+                // Don't retry until the file changes again
+                let previous = self.current.load_full();
+                self.current.store(Arc::new(Loaded {
+                    pre: previous.pre.clone(),
+                    fingerprint: current,
+                }));
+            }
+        }
+    }
+}
+
+/// Load (or reuse) the module for a plugin
+fn module(config: &PluginConfig) -> anyhow::Result<Arc<PluginModule>> {
+    let mut modules = host().modules.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(module) = modules.get(&config.path).and_then(Weak::upgrade) {
+        return Ok(module);
+    }
+
+    let module = Arc::new(PluginModule {
+        path: config.path.clone(),
+        current: ArcSwap::from_pointee(PluginModule::compile(&config.path)?),
+    });
+    modules.retain(|_, m| m.strong_count() > 0);
+    modules.insert(config.path.clone(), Arc::downgrade(&module));
+    Ok(module)
+}
+
+fn spawn_watch() {
+    let interval = crate::reload::poll_interval();
+    if interval.is_zero() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let loaded: Vec<_> = host()
+                .modules
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .values()
+                .filter_map(Weak::upgrade)
+                .collect();
+            // Compiling can take a while; keep it off the async workers
+            let _ = tokio::task::spawn_blocking(move || {
+                for module in loaded {
+                    module.refresh();
+                }
+            })
+            .await;
+        }
+    });
+}
+
+fn fingerprint(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[derive(Serialize, Deserialize)]
+struct GuestRequest {
+    method: String,
+    path: String,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GuestResponse {
+    status: u16,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    body: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum GuestOutput {
+    Request(GuestRequest),
+    Response(GuestResponse),
+}
+
+/// Middleware step backed by a WASM module
+pub struct WasmPlugin {
+    name: String,
+    config: PluginConfig,
+    module: Arc<PluginModule>,
+}
+
+impl WasmPlugin {
+    pub fn load(name: impl Into<String>, config: &PluginConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            name: name.into(),
+            config: config.clone(),
+            module: module(config)?,
+        })
+    }
+
+    pub fn config(&self) -> &PluginConfig {
+        &self.config
+    }
+
+    /// Run the guest on `input`, enforcing the fuel and memory limits
+    fn call(
+        module: &PluginModule,
+        config: &PluginConfig,
+        input: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        let loaded = module.current.load_full();
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(config.max_memory_bytes)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&host().engine, StoreState { limits });
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(config.fuel)?;
+
+        let instance = loaded.pre.instantiate(&mut store)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .context("guest does not export memory")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let handle = instance.get_typed_func::<(i32, i32), i64>(&mut store, "handle")?;
+
+        let len = i32::try_from(input.len()).context("request too large for guest")?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, input)?;
+
+        let packed = handle.call(&mut store, (ptr, len))? as u64;
+        let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        // The guest picks both numbers; check them before allocating anything
+        if out_len > Self::max_output_bytes(config) {
+            anyhow::bail!("guest output of {out_len} bytes is over the limit");
+        }
+        if out_ptr
+            .checked_add(out_len)
+            .is_none_or(|end| end > memory.data_size(&store))
+        {
+            anyhow::bail!("guest output lies outside its memory");
+        }
+        let mut output = vec![0; out_len];
+        memory.read(&store, out_ptr, &mut output)?;
+        Ok(output)
+    }
+
+    /// Largest output accepted from the guest: a base64 body of up to
+    /// `max_body_bytes` plus room for the method, path and headers
+    fn max_output_bytes(config: &PluginConfig) -> usize {
+        (config.max_body_bytes / 3 + 1)
+            .saturating_mul(4)
+            .saturating_add(MAX_OUTPUT_OVERHEAD)
+    }
+
+    fn run(
+        module: &PluginModule,
+        config: &PluginConfig,
+        req: Request,
+    ) -> anyhow::Result<Result<Request, Response>> {
+        let input = serde_json::to_vec(&GuestRequest {
+            method: req.method.clone(),
+            path: req.path.clone(),
+            headers: req.headers.clone(),
+            body: req.body.as_ref().map(|b| BASE64.encode(b)),
+        })?;
+
+        let output = Self::call(module, config, &input)?;
+        if output.is_empty() {
+            return Ok(Ok(req));
+        }
+
+        match serde_json::from_slice(&output).context("guest returned invalid output")? {
+            GuestOutput::Request(updated) => {
+                let body = match updated.body {
+                    Some(body) => Some(
+                        BASE64
+                            .decode(body)
+                            .context("guest returned invalid base64 body")?,
+                    ),
+                    None => None,
+                };
+                Ok(Ok(Request {
+                    method: updated.method,
+                    path: updated.path,
+                    headers: updated.headers,
+                    body,
+                }))
+            }
+            GuestOutput::Response(res) => Ok(Err(Response {
+                status: res.status,
+                headers: res.headers,
+                body: res.body,
+            })),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for WasmPlugin {
+    async fn process(&self, req: Request) -> Result<Request, Response> {
+        // The guest may burn through all its fuel; keep it off the async workers
+        let (module, config) = (self.module.clone(), self.config.clone());
+        let result = tokio::task::spawn_blocking(move || Self::run(&module, &config, req))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result);
+        result.unwrap_or_else(|err| {
+            eprintln!("[gateway] wasm plugin {} failed: {err:#}", self.name);
+            Err(Response {
+                status: 500,
+                headers: Vec::new(),
+                body: "plugin error".to_string(),
+            })
+        })
+    }
+
+    fn body_limit(&self) -> Option<usize> {
+        Some(self.config.max_body_bytes)
+    }
+}
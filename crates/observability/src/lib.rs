@@ -7,3 +7,10 @@ pub fn init_observability(service_name: &str) {
 pub fn event(message: &str) {
     println!("event: {message}");
 }
+
+/// Security-relevant event (e.g. a replayed refresh token), written to stderr
+/// as `key=value` pairs so it can be picked out of the logs.
+pub fn security_event(name: &str, fields: &[(&str, String)]) {
+    let fields: String = fields.iter().map(|(k, v)| format!(" {k}={v}")).collect();
+    eprintln!("security event: {name}{fields}");
+}
@@ -1,5 +1,14 @@
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("migrate") {
+        if let Err(err) = admin_core::run_migrate_command(&args[1..]).await {
+            eprintln!("migrate: {err:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     common::init_service("admin");
     if let Err(err) = admin_core::run().await {
         eprintln!("admin service error: {err}");